@@ -26,27 +26,41 @@ class Client:
     """
     A client connection object. Can hold multiple
     :py:class:`Session`\ s.
+
+    Args:
+        address (`str`): Address of the server.
+        port (`int`): Port of the server.
+        weight (`float`): Share of cpus given to this client relative
+            to other clients of the server.
     """
 
-    def __init__(self, address, port):
+    def __init__(self, address, port, weight=1.0):
         self._rpc_client = capnp.TwoPartyClient("{}:{}".format(address, port))
 
         bootstrap = self._rpc_client.bootstrap().cast_as(
             rpc.server.ServerBootstrap)
-        registration = bootstrap.registerAsClient(CLIENT_PROTOCOL_VERSION)
+        registration = bootstrap.registerAsClient(
+            CLIENT_PROTOCOL_VERSION, weight=weight)
         self._service = registration.wait().service
         self._datastore = self._service.getDataStore().wait().store
 
-    def new_session(self):
+    def new_session(self, weight=1.0, cpu_quota=None):
         """
         Creates a new session.
 
         Note the session is destroyed server-side when the client disconnects.
 
+        Args:
+            weight (`float`): Share of the client's cpus given to this session
+                relative to other sessions of the client.
+            cpu_quota (`int` or `None`): Maximal number of cpus used at once
+                by tasks of the session.
+
         Returns:
             :class:`Session`: A new session
         """
-        session_id = self._service.newSession().wait().sessionId
+        session_id = self._service.newSession(
+            weight=weight, cpuQuota=cpu_quota or 0).wait().sessionId
         return Session(self, session_id)
 
     def get_server_info(self):
@@ -30,7 +30,21 @@ class Task:
         outputs (`LabeledList` or sequence): Specification of `Output`\ s for the task.
         session (`Session` or `None`): Session to create the task in.
             If not specified, the current `Session` is used.
-        cpus (`int`): Number of cpus.
+        cpus (`int`): Number of cpus (on each node for multi-node tasks).
+        nodes (`int` or `None`): Number of workers for a multi-node task.
+            The task runs on the first worker, cpus are reserved on all of them.
+        priority (`int` or `None`): Scheduling priority, ready tasks with higher
+            priority are scheduled first (default 0).
+        labels (`list[str]` or `None`): Labels that the worker running the task
+            has to have (workers are started with ``--label``).
+        hosts (`list[str]` or `None`): The task runs only on one of these hosts
+            (hostname, IP address or address with port of the worker).
+        wait_for_worker (`bool`): When no connected worker matches ``labels``
+            and ``hosts``, wait for such a worker instead of failing the submit.
+        anti_affinity (`str` or `None`): Tasks of the session with the same
+            anti-affinity group never run on one worker at the same time.
+        gang (`str` or `None`): Tasks of the session with the same gang are
+            started all at once (they have to be submitted together).
 
     Attributes:
         id (`ID`): Auto-assigned task ID.
@@ -52,7 +66,14 @@ class Task:
                  inputs=(),
                  outputs=None,
                  session=None,
-                 cpus=1):
+                 cpus=1,
+                 priority=None,
+                 labels=None,
+                 hosts=None,
+                 anti_affinity=None,
+                 gang=None,
+                 nodes=None,
+                 wait_for_worker=False):
         if session is None:
             session = get_active_session()
         self.session = session
@@ -68,6 +89,27 @@ class Task:
         if cpus is not None:
             self.attributes["resources"] = {"cpus": cpus}
 
+        if priority is not None:
+            self.attributes["priority"] = priority
+
+        constraints = {}
+        if labels:
+            constraints["labels"] = list(labels)
+        if hosts:
+            constraints["hosts"] = list(hosts)
+        if anti_affinity is not None:
+            constraints["anti_affinity"] = anti_affinity
+        if wait_for_worker and (labels or hosts):
+            constraints["wait"] = True
+        if constraints:
+            self.attributes["constraints"] = constraints
+
+        if gang is not None:
+            self.attributes["gang"] = gang
+
+        if nodes is not None:
+            self.attributes["nodes"] = nodes
+
         def to_data_object(o):
             if isinstance(o, int):
                 o = "out{}".format(o)
@@ -25,6 +25,66 @@ def sleep(timeout, dataobj, cpus=1):
                 cpus=cpus)
 
 
+def split(dataobj, parts, mode="lines", delimiter=None):
+    """Creates a task splitting a blob into `parts` outputs of (almost) equal size.
+    Mode "lines" and "delimiter" never divide a line/record, mode "bytes"
+    divides data by bytes."""
+    config = {"mode": mode}
+    if delimiter is not None:
+        config["mode"] = "delimiter"
+        config["delimiter"] = delimiter
+    return Task("!split", config, inputs=(to_data(dataobj),), outputs=parts)
+
+
+def slice(dataobj, start, end=None, lines=False):
+    """Creates a task returning a range of bytes (or lines if `lines` is True)
+    of a blob"""
+    return Task("!slice", {"start": start, "end": end, "lines": lines},
+                inputs=(to_data(dataobj),), outputs=1)
+
+
+def sort_lines(dataobj):
+    """Creates a task sorting lines of a blob"""
+    return Task("!sort_lines", inputs=(to_data(dataobj),), outputs=1)
+
+
+def merge_sorted(objs):
+    """Creates a task merging lines of sorted blobs into one sorted blob"""
+    return Task("!merge_sorted", inputs=tuple(objs), outputs=1)
+
+
+def tar(inputs, compression=None):
+    """Creates a task packing data objects into a tar archive.
+    `inputs` is a dict (or a list of pairs) mapping names in the archive
+    to data objects. A directory object with an empty name is unpacked
+    into the root of the archive. `compression` may be None or "gzip"."""
+    if isinstance(inputs, dict):
+        inputs = inputs.items()
+    inputs = tuple((name, to_data(obj)) for name, obj in inputs)
+    return Task("!tar", {"compression": compression}, inputs=inputs, outputs=1)
+
+
+def untar(dataobj, compression=None):
+    """Creates a task unpacking a tar archive into a directory object"""
+    return Task("!untar", {"compression": compression},
+                inputs=(to_data(dataobj),), outputs=1)
+
+
+def checksum(objs, algorithm="sha256"):
+    """Creates a task computing hex encoded digests of blobs; one output
+    for each input. `algorithm` may be "sha256" or "blake3"."""
+    inputs = tuple(to_data(obj) for obj in objs)
+    return Task("!checksum", {"algorithm": algorithm},
+                inputs=inputs, outputs=len(inputs))
+
+
+def assert_checksum(dataobj, digest, algorithm="sha256"):
+    """Creates a task that forwards a blob if its digest is equal to `digest`,
+    otherwise the task fails."""
+    return Task("!assert_checksum", {"algorithm": algorithm, "digest": digest},
+                inputs=(to_data(dataobj),), outputs=1)
+
+
 def open(filename):
     return Task("!open", {"path": filename}, outputs=1)
 
@@ -33,13 +93,27 @@ def export(dataobj, filename):
     return Task("!export", {"path": filename}, inputs=(dataobj,))
 
 
+def pipe(stages, stdin=None, cpus=1):
+    """Creates a task running a pipeline of programs.
+    Stdout of each stage is connected to stdin of the following stage,
+    stdout of the last stage is the output of the task.
+    The task fails when any of stages fails."""
+    stages = [shlex.split(args) if isinstance(args, str) else list(args)
+              for args in stages]
+    inputs = () if stdin is None else (to_data(stdin),)
+    return Task("!pipe", {"stages": stages}, inputs=inputs, outputs=1, cpus=cpus)
+
+
 def execute(args,
             stdout=None,
             stdin=None,
             input_files=(),
             output_files=(),
             shell=False,
-            cpus=1):
+            cpus=1,
+            success_codes=None,
+            optional_outputs=False,
+            task_socket=False):
 
     ins = []
     outs = []
@@ -86,14 +160,25 @@ def execute(args,
         proc_args = ("/bin/sh", "-c", " ".join(proc_args))
 #        proc_args = ("/bin/sh", "-c", " ".join(shlex.quote(a) for a in proc_args))
 
+    config = {
+        "args": proc_args,
+        "in_paths": [obj.path for obj in ins],
+        "out_paths": [obj.path for obj in outs],
+    }
+
+    if success_codes is not None:
+        config["success_codes"] = list(success_codes)
+
+    if optional_outputs:
+        config["optional_outputs"] = True
+
+    if task_socket:
+        config["task_socket"] = True
+
     task_inputs = [obj.dataobj for obj in ins]
     task_outputs = [output.create_data_object() for output in outs]
     return Task("!run",
-                {
-                    "args": proc_args,
-                    "in_paths": [obj.path for obj in ins],
-                    "out_paths": [obj.path for obj in outs],
-                },
+                config,
                 inputs=task_inputs,
                 outputs=task_outputs,
                 cpus=cpus)
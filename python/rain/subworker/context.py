@@ -4,18 +4,80 @@ import os.path
 from ..common.data_instance import DataInstance
 from ..common import RainException
 from ..common.content_type import (check_content_type, encode_value)
+from ..client.session import Session
+from ..client.data import DataObject
+from ..client import rpc
+
+
+class ChildSession(Session):
+    """
+    Session for tasks and objects submitted by a running Python task.
+
+    The tasks and objects are submitted into the session of the running
+    task when the task finishes. Use it as a context manager, it is not
+    closed at the end.
+    """
+
+    def __init__(self, session_id):
+        super().__init__(None, session_id)
+        # Objects returned by input_object() by their python id;
+        # their ids may collide with ids of objects of this session
+        self._existing_objects = {}
+
+    def close(self):
+        pass
+
+    def submit(self):
+        raise RainException(
+            "Child tasks and objects are submitted when the task finishes")
+
+    def input_object(self, data):
+        """Returns `DataObject` for an input of the running task,
+        so it may be used as an input of child tasks."""
+        if data._object_id is None:
+            raise RainException("Data are not an input of the task")
+        obj = DataObject.__new__(DataObject)
+        obj.session = self
+        obj.label = None
+        obj.id = data._object_id
+        obj.attributes = {"spec": {"content_type": data.content_type}}
+        obj.state = rpc.common.DataObjectState.finished
+        self._existing_objects[id(obj)] = obj
+        return obj
+
+    def is_existing_object(self, dataobj):
+        """True if the object was created by `input_object`"""
+        return id(dataobj) in self._existing_objects
 
 
 class Context:
 
-    def __init__(self, subworker):
+    def __init__(self, subworker, task_id=None):
         self._subworker = subworker
         self._id_counter = 0
         self._staged_paths = set()
         self._debug_messages = []
+        self._child_session = None
+        self.task_id = task_id
         self.attributes = {}
         self.function = None
 
+    def child_session(self):
+        """Returns a session for child tasks and objects of this task.
+
+           The children are submitted into the session of this task when
+           the task finishes. An output of the task may be returned as
+           an object of the child session; the output is then produced
+           by the child task (it is delegated).
+
+           >>> with ctx.child_session():
+           ...     t = tasks.concat((a, b))
+           >>> return t.output
+        """
+        if self._child_session is None:
+            self._child_session = ChildSession(self.task_id.session_id)
+        return self._child_session
+
     def stage_file(self, path, content_type=None):
         """Creates DataInstance from file.
 
@@ -36,7 +36,8 @@ class ControlImpl(rpc_subworker.SubworkerControl.Server):
             del self.cache[object_id]
 
     def runTask(self, task, _context):
-        task_context = Context(self.subworker)
+        task_context = Context(self.subworker,
+                               id_from_capnp(_context.params.task.id))
         try:
             params = _context.params
 
@@ -60,8 +61,9 @@ class ControlImpl(rpc_subworker.SubworkerControl.Server):
                        for reader, encode in zip(params.task.outputs,
                                                  cfg['encode_outputs'])]
 
-            task_results = self.subworker.run_task(
+            task_results, delegations = self.subworker.run_task(
                 task_context, inputs, outputs)
+            submit = self.subworker.submit_children(task_context, delegations)
 
             results = _context.results.init("data", len(task_results))
             for i, data in enumerate(task_results):
@@ -69,6 +71,9 @@ class ControlImpl(rpc_subworker.SubworkerControl.Server):
             task_context._cleanup(task_results)
             write_attributes(task_context, _context.results.taskAttributes)
             _context.results.ok = True
+            if submit is not None:
+                # Finish the task after the children are accepted by the server
+                return submit.then(lambda _: None)
 
         except Exception:
             task_context._cleanup_on_fail()
@@ -11,6 +11,8 @@ from .rpc import subworker as rpc_subworker
 from .control import ControlImpl
 from ..common.fs import remove_dir_content
 from ..common import DataInstance, RainException
+from ..common.ids import id_to_capnp
+from ..client.data import DataObject
 from ..common.content_type import merge_content_types
 
 SUBWORKER_PROTOCOL_VERSION = 0
@@ -83,7 +85,7 @@ class Subworker:
             inputs: is a list of `DataInstance`.
             outputs: is list of `ControlImpl.OutputSpec`.
         Returns:
-            list(DataInstance)
+            (list(DataInstance), list((output id, child object id)))
         """
         fn = inputs[0].load(cache=True)
         context.function = fn
@@ -114,9 +116,18 @@ class Subworker:
             raise RainException("Python task should return {} outputs, got {}."
                                 .format(len(outputs), len(result)))
         res = []
+        delegations = []
         for r, o in zip(result, outputs):
             encode = o.encode
-            if isinstance(r, DataInstance):
+            if isinstance(r, DataObject):
+                # The output is produced by a child task
+                if r.session is not context._child_session:
+                    raise RainException(
+                        "Returned data object {!r} is not from the child session"
+                        .format(r))
+                delegations.append((o.id, r.id))
+                di = context.blob(b"")
+            elif isinstance(r, DataInstance):
                 di = r
             elif encode is not None:
                 di = context.blob(r, encode=encode)
@@ -131,7 +142,35 @@ class Subworker:
                 di.attributes['user_spec'] = o.attributes['user_spec']
             res.append(di)
 
-        return res
+        return res, delegations
+
+    def submit_children(self, context, delegations):
+        """Submits children of the task, returns a promise or None
+        if there is nothing to submit."""
+        session = context._child_session
+        if session is None or (not session._tasks and not session._dataobjs):
+            if delegations:
+                raise RainException("Delegated outputs without child tasks")
+            return None
+        req = self.upstream.submitChildren_request()
+        id_to_capnp(context.task_id, req.parent)
+
+        req.init("tasks", len(session._tasks))
+        for i, task in enumerate(session._tasks):
+            task.to_capnp(req.tasks[i])
+            for j, (_, dataobj) in enumerate(task.inputs.items()):
+                if session.is_existing_object(dataobj):
+                    req.tasks[i].inputs[j].existing = True
+
+        req.init("objects", len(session._dataobjs))
+        for i, dataobj in enumerate(session._dataobjs):
+            dataobj.to_capnp(req.objects[i])
+
+        req.init("delegations", len(delegations))
+        for i, (output_id, source_id) in enumerate(delegations):
+            id_to_capnp(output_id, req.delegations[i].output)
+            id_to_capnp(source_id, req.delegations[i].source)
+        return req.send()
 
 
 def get_environ(name):
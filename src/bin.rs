@@ -26,10 +26,13 @@ use clap::{App, Arg, ArgMatches, SubCommand};
 use nix::unistd::getpid;
 
 use librain::{server, worker, VERSION};
+use librain::common::logging::{create_logger, LOGGER_NAMES};
+use librain::common::logging::sqlite_logger::RetentionPolicy;
 use librain::errors::Result;
 
 const DEFAULT_SERVER_PORT: u16 = 7210;
 const DEFAULT_WORKER_PORT: u16 = 0;
+const DEFAULT_WORKER_HTTP_PORT: u16 = 8081;
 
 const DEFAULT_HTTP_SERVER_PORT: u16 = 8080;
 
@@ -47,6 +50,28 @@ fn parse_listen_arg(key: &str, args: &ArgMatches, default_port: u16) -> SocketAd
     })
 }
 
+/// Resolve address of the server given as HOST[:PORT]
+fn resolve_server_address(address: &str) -> SocketAddr {
+    let mut server_address = address.to_string();
+    if !server_address.contains(':') {
+        server_address = format!("{}:{}", server_address, DEFAULT_SERVER_PORT);
+    }
+
+    match server_address.to_socket_addrs() {
+        Err(_) => {
+            error!("Cannot resolve server address");
+            exit(1);
+        }
+        Ok(mut addrs) => match addrs.next() {
+            None => {
+                error!("Cannot resolve server address");
+                exit(1);
+            }
+            Some(ref addr) => *addr,
+        },
+    }
+}
+
 fn run_server(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_SERVER_PORT);
     let http_listen_address =
@@ -86,12 +111,40 @@ fn run_server(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
         info!("TESTING mode enabled");
     }
 
+    let hours_arg = |key: &str| {
+        if cmd_args.is_present(key) {
+            Some(::chrono::Duration::hours(value_t_or_exit!(cmd_args, key, u32) as i64))
+        } else {
+            None
+        }
+    };
+    let log_retention = RetentionPolicy {
+        max_age: hours_arg("LOG_MAX_AGE"),
+        max_rows: if cmd_args.is_present("LOG_MAX_ROWS") {
+            Some(value_t_or_exit!(cmd_args, "LOG_MAX_ROWS", u64))
+        } else {
+            None
+        },
+        closed_sessions_age: hours_arg("LOG_CLOSED_SESSIONS"),
+    };
+    if !log_retention.is_unlimited() {
+        info!("Event log retention: {:?}", log_retention);
+    }
+
+    let logger_names: Vec<&str> = cmd_args.values_of("LOGGER").unwrap().collect();
+    info!("Event loggers: {}", logger_names.join(", "));
+    let logger = create_logger(&logger_names, &log_dir, log_retention).unwrap_or_else(|e| {
+        error!("Cannot create event logger: {}", e);
+        exit(1);
+    });
+
     let state = server::state::StateRef::new(
         tokio_core.handle(),
         listen_address,
         http_listen_address,
-        log_dir,
+        logger,
         test_mode,
+        cmd_args.is_present("LOG_CLIENT_DATA"),
     );
     state.start();
 
@@ -140,24 +193,17 @@ fn ensure_directory(dir: &Path, name: &str) -> Result<()> {
 fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     let ready_file = cmd_args.value_of("READY_FILE");
     let listen_address = parse_listen_arg("LISTEN_ADDRESS", cmd_args, DEFAULT_WORKER_PORT);
-    let mut server_address = cmd_args.value_of("SERVER_ADDRESS").unwrap().to_string();
-    if !server_address.contains(':') {
-        server_address = format!("{}:{}", server_address, DEFAULT_SERVER_PORT);
-    }
-
-    let server_addr = match server_address.to_socket_addrs() {
-        Err(_) => {
-            error!("Cannot resolve server address");
-            exit(1);
-        }
-        Ok(mut addrs) => match addrs.next() {
-            None => {
-                error!("Cannot resolve server address");
-                exit(1);
-            }
-            Some(ref addr) => *addr,
-        },
+    let http_listen_address = if cmd_args.is_present("HTTP_LISTEN_ADDRESS") {
+        Some(parse_listen_arg(
+            "HTTP_LISTEN_ADDRESS",
+            cmd_args,
+            DEFAULT_WORKER_HTTP_PORT,
+        ))
+    } else {
+        None
     };
+    let server_address = cmd_args.value_of("SERVER_ADDRESS").unwrap();
+    let server_addr = resolve_server_address(server_address);
 
     fn detect_cpus() -> i32 {
         debug!("Detecting number of cpus");
@@ -210,7 +256,15 @@ fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
     });
 
     info!("Starting Rain {} as worker", VERSION);
+    let labels: Vec<String> = cmd_args
+        .values_of("LABEL")
+        .map(|values| values.map(|v| v.to_string()).collect())
+        .unwrap_or_else(Vec::new);
+
     info!("Resources: {} cpus", cpus);
+    if !labels.is_empty() {
+        info!("Labels: {}", labels.join(", "));
+    }
     info!("Working directory: {:?}", work_dir);
     info!(
         "Server address {} was resolved as {}",
@@ -234,11 +288,12 @@ fn run_worker(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
         work_dir,
         log_dir,
         cpus as u32,
+        labels,
         // Python subworker
         subworkers,
     );
 
-    state.start(server_addr, listen_address, ready_file);
+    state.start(server_addr, listen_address, http_listen_address, ready_file);
 
     loop {
         tokio_core.turn(None);
@@ -376,6 +431,138 @@ fn init_log() {
     }
 }
 
+fn run_simulator(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    use librain::server::simulation::{load_spec, run_simulation};
+
+    let result = load_spec(Path::new(cmd_args.value_of("CLUSTER").unwrap()))
+        .and_then(|cluster| {
+            let graph = load_spec(Path::new(cmd_args.value_of("GRAPH").unwrap()))?;
+            run_simulation(&cluster, &graph)
+        })
+        .and_then(|report| Ok(serde_json::to_string_pretty(&report)?));
+    let report = result.unwrap_or_else(|e| {
+        error!("Simulation failed: {}", e);
+        exit(1);
+    });
+    match cmd_args.value_of("OUTPUT") {
+        Some(path) => {
+            ::std::fs::File::create(path)
+                .and_then(|mut f| f.write_all(report.as_bytes()))
+                .unwrap_or_else(|e| {
+                    error!("Cannot write report to {}: {}", path, e);
+                    exit(1);
+                });
+        }
+        None => println!("{}", report),
+    }
+}
+
+fn run_events(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    use librain::common::logging::export::{export_events, ExportFormat};
+
+    let export_args = match cmd_args.subcommand() {
+        ("export", Some(export_args)) => export_args,
+        _ => {
+            error!("No events subcommand provided.");
+            exit(1);
+        }
+    };
+    let log_dir = PathBuf::from(export_args.value_of("LOG_DIR").unwrap());
+    let session = if export_args.is_present("SESSION") {
+        Some(value_t_or_exit!(export_args, "SESSION", i32))
+    } else {
+        None
+    };
+    let format = match export_args.value_of("FORMAT").unwrap() {
+        "csv" => ExportFormat::Csv,
+        _ => ExportFormat::JsonLines,
+    };
+
+    let result = match export_args.value_of("OUTPUT") {
+        Some(path) => ::std::fs::File::create(path)
+            .map_err(::librain::errors::Error::from)
+            .and_then(|f| {
+                let mut output = ::std::io::BufWriter::new(f);
+                export_events(&log_dir, session, format, &mut output)
+            }),
+        None => {
+            let stdout = ::std::io::stdout();
+            let mut output = stdout.lock();
+            export_events(&log_dir, session, format, &mut output)
+        }
+    };
+    match result {
+        Ok(count) => info!("{} events exported", count),
+        Err(e) => {
+            error!("Export failed: {}", e);
+            exit(1);
+        }
+    }
+}
+
+fn run_trace(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    use librain::common::logging::export::load_session_events;
+    use librain::common::logging::trace::build_trace;
+
+    let log_dir = PathBuf::from(cmd_args.value_of("LOG_DIR").unwrap());
+    let session = value_t_or_exit!(cmd_args, "SESSION", i32);
+    let events = load_session_events(&log_dir, Some(session)).unwrap_or_else(|e| {
+        error!("Cannot load events: {}", e);
+        exit(1);
+    });
+    let trace = build_trace(&events);
+
+    let result = match cmd_args.value_of("OUTPUT") {
+        Some(path) => ::std::fs::File::create(path)
+            .map_err(serde_json::Error::io)
+            .and_then(|f| serde_json::to_writer(::std::io::BufWriter::new(f), &trace)),
+        None => serde_json::to_writer(::std::io::stdout(), &trace),
+    };
+    if let Err(e) = result {
+        error!("Cannot write trace: {}", e);
+        exit(1);
+    }
+}
+
+fn run_replay(_global_args: &ArgMatches, cmd_args: &ArgMatches) {
+    use librain::server::replay::{load_session_log, resubmit};
+
+    let log_dir = PathBuf::from(cmd_args.value_of("LOG_DIR").unwrap());
+    let session = value_t_or_exit!(cmd_args, "SESSION", i32);
+    let min_gap = value_t_or_exit!(cmd_args, "MIN_GAP", f64);
+    let log = load_session_log(&log_dir, session).unwrap_or_else(|e| {
+        error!("Cannot load session: {}", e);
+        exit(1);
+    });
+
+    if let Some(address) = cmd_args.value_of("SUBMIT") {
+        let server_addr = resolve_server_address(address);
+        match resubmit(&log, &server_addr) {
+            Ok(new_session) => info!(
+                "Session {} replayed as session {}",
+                session, new_session
+            ),
+            Err(e) => {
+                error!("Replay failed: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    let report = serde_json::to_string_pretty(&log.analyze(min_gap)).unwrap();
+    match cmd_args.value_of("OUTPUT") {
+        Some(path) => {
+            ::std::fs::File::create(path)
+                .and_then(|mut f| f.write_all(report.as_bytes()))
+                .unwrap_or_else(|e| {
+                    error!("Cannot write report to {}: {}", path, e);
+                    exit(1);
+                });
+        }
+        None => println!("{}", report),
+    }
+}
+
 fn main() {
     init_log();
 
@@ -401,6 +588,31 @@ fn main() {
                     .long("--logdir")
                     .help("Logging directory (default /tmp/rain-logs/server-$HOSTANE-$PID)")
                     .takes_value(true))
+                .arg(Arg::with_name("LOGGER")
+                    .long("--logger")
+                    .help("Event logger, may be used more times to log events by more loggers")
+                    .possible_values(LOGGER_NAMES)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .default_value("sqlite"))
+                .arg(Arg::with_name("LOG_MAX_AGE")
+                    .long("--log-max-age")
+                    .value_name("HOURS")
+                    .help("Remove logged events older than the given number of hours")
+                    .takes_value(true))
+                .arg(Arg::with_name("LOG_MAX_ROWS")
+                    .long("--log-max-rows")
+                    .value_name("N")
+                    .help("Keep at most N newest logged events")
+                    .takes_value(true))
+                .arg(Arg::with_name("LOG_CLOSED_SESSIONS")
+                    .long("--log-closed-sessions")
+                    .value_name("HOURS")
+                    .help("Remove logged events of sessions closed before the given number of hours")
+                    .takes_value(true))
+                .arg(Arg::with_name("LOG_CLIENT_DATA")
+                    .long("--log-client-data")
+                    .help("Log data of objects submitted by clients (up to 64 KiB) for replay"))
                 .arg(Arg::with_name("READY_FILE")
                     .long("--ready-file")
                     .help("Create a file when server is initialized and ready to accept connections")
@@ -417,11 +629,23 @@ fn main() {
                     .value_name("ADDRESS")
                     .help("Listening port/address/address:port (default = 0.0.0.0:auto)")
                     .takes_value(true))
+                .arg(Arg::with_name("HTTP_LISTEN_ADDRESS")
+                    .long("--http-listen")
+                    .value_name("ADDRESS")
+                    .help("Serve metrics over HTTP on port/address/address:port (disabled by default, default port = 8081)")
+                    .takes_value(true))
                 .arg(Arg::with_name("CPUS")
                     .long("--cpus")
                     .help("Number of cpus or 'detect' (default = detect)")
                     .value_name("N")
                     .default_value("detect"))
+                .arg(Arg::with_name("LABEL")
+                    .long("--label")
+                    .help("Label of the worker used in placement constraints of tasks (e.g. --label gpu-node), may be used more times")
+                    .value_name("LABEL")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1))
                 .arg(Arg::with_name("WORK_DIR")
                     .long("--workdir")
                     .help("Workding directory (default /tmp/rain-work/worker-$HOSTANE-$PID)")
@@ -484,12 +708,97 @@ fn main() {
                     .long("--logdir")
                     .help("Logging directory for workers & server (default /tmp/rain-logs/run-$HOSTANE-$PID)")
                     .takes_value(true)))
+        .subcommand( // ---- SIMULATE ----
+            SubCommand::with_name("simulate")
+                .about("Simulate execution of a task graph on a virtual cluster")
+                .arg(Arg::with_name("CLUSTER")
+                    .help("JSON file with description of workers")
+                    .required(true))
+                .arg(Arg::with_name("GRAPH")
+                    .help("JSON file with tasks and objects")
+                    .required(true))
+                .arg(Arg::with_name("OUTPUT")
+                    .long("--output")
+                    .value_name("FILE")
+                    .help("Write the report into a file (default = stdout)")
+                    .takes_value(true)))
+        .subcommand( // ---- EVENTS ----
+            SubCommand::with_name("events")
+                .about("Work with event logs of the server")
+                .subcommand(SubCommand::with_name("export")
+                    .about("Export events from the log directory of a server")
+                    .arg(Arg::with_name("LOG_DIR")
+                        .help("Logging directory of the server")
+                        .required(true))
+                    .arg(Arg::with_name("SESSION")
+                        .long("--session")
+                        .value_name("ID")
+                        .help("Export only events of the session")
+                        .takes_value(true))
+                    .arg(Arg::with_name("FORMAT")
+                        .long("--format")
+                        .help("Output format")
+                        .possible_values(&["jsonl", "csv"])
+                        .default_value("jsonl"))
+                    .arg(Arg::with_name("OUTPUT")
+                        .long("--output")
+                        .value_name("FILE")
+                        .help("Write the events into a file (default = stdout)")
+                        .takes_value(true))))
+        .subcommand( // ---- REPLAY ----
+            SubCommand::with_name("replay")
+                .about("Analyze a session from the event log and optionally resubmit it")
+                .arg(Arg::with_name("LOG_DIR")
+                    .help("Logging directory of the server")
+                    .required(true))
+                .arg(Arg::with_name("SESSION")
+                    .long("--session")
+                    .value_name("ID")
+                    .help("Session to replay")
+                    .required(true)
+                    .takes_value(true))
+                .arg(Arg::with_name("MIN_GAP")
+                    .long("--min-gap")
+                    .value_name("MS")
+                    .help("Minimal length of reported idle gaps of workers in milliseconds")
+                    .default_value("100"))
+                .arg(Arg::with_name("SUBMIT")
+                    .long("--submit")
+                    .value_name("ADDRESS")
+                    .help("Resubmit the session graph into the server and wait until it is finished")
+                    .takes_value(true))
+                .arg(Arg::with_name("OUTPUT")
+                    .long("--output")
+                    .value_name("FILE")
+                    .help("Write the report into a file (default = stdout)")
+                    .takes_value(true)))
+        .subcommand( // ---- TRACE ----
+            SubCommand::with_name("trace")
+                .about("Build a Chrome trace (Trace Event Format) of a session from the event log")
+                .arg(Arg::with_name("LOG_DIR")
+                    .help("Logging directory of the server")
+                    .required(true))
+                .arg(Arg::with_name("SESSION")
+                    .long("--session")
+                    .value_name("ID")
+                    .help("Session of the trace")
+                    .required(true)
+                    .takes_value(true))
+                .arg(Arg::with_name("OUTPUT")
+                    .long("--output")
+                    .value_name("FILE")
+                    .help("Write the trace into a file (default = stdout)")
+                    .takes_value(true)))
         .get_matches();
 
     match args.subcommand() {
         ("server", Some(cmd_args)) => run_server(&args, cmd_args),
         ("worker", Some(cmd_args)) => run_worker(&args, cmd_args),
         ("start", Some(cmd_args)) => run_starter(&args, cmd_args),
+        ("simulate", Some(cmd_args)) => run_simulator(&args, cmd_args),
+        ("events", Some(cmd_args)) => run_events(&args, cmd_args),
+        ("trace", Some(cmd_args)) => run_trace(&args, cmd_args),
+        ("replay", Some(cmd_args)) => run_replay(&args, cmd_args),
         _ => {
             error!("No subcommand provided.");
             ::std::process::exit(1);
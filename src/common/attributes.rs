@@ -2,6 +2,15 @@ use std::collections::HashMap;
 use errors::Result;
 use std::error::Error;
 
+/// Content of attribute "info" that is set by worker when a task is finished
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AttributeInfo {
+    pub worker: String,
+    pub start: String,
+    /// Duration of the task in milliseconds
+    pub duration: i64,
+}
+
 #[derive(Default, Debug)]
 pub struct Attributes {
     // TODO: Int & Float types
@@ -82,6 +91,11 @@ impl Attributes {
         }
     }
 
+    /// Create attributes from JSON encoded values (as returned by `as_hashmap`)
+    pub fn from_hashmap(items: HashMap<String, String>) -> Self {
+        Attributes { items }
+    }
+
     pub fn as_hashmap(&self) -> &HashMap<String, String> {
         &self.items
     }
@@ -1,14 +1,42 @@
 use super::id::{ClientId, DataObjectId, SessionId, TaskId, WorkerId};
 use server::graph::{DataObject, Task};
 use common::id::SId;
+use common::resources::Resources;
+use common::monitor::ProcessUsage;
 
 use std::collections::HashMap;
 
 pub type EventId = i64;
 
+/// Data of client objects up to this size are stored in `ClientSubmit` events
+/// (when enabled on the server), so the session can be replayed from the event log
+pub const MAX_LOGGED_DATA_SIZE: usize = 64 * 1024;
+
+/// (De)serialization of optional binary data as a base64 string
+mod base64_data {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde::de::Error;
+
+    pub fn serialize<S: Serializer>(data: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match *data {
+            Some(ref data) => s.serialize_some(&::base64::encode(data)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        match Option::<String>::deserialize(d)? {
+            Some(text) => ::base64::decode(&text).map(Some).map_err(D::Error::custom),
+            None => Ok(None),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct WorkerNewEvent {
-    pub worker: WorkerId, // TODO: Resources
+    pub worker: WorkerId,
+    #[serde(default)]
+    pub resources: Resources,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -35,6 +63,11 @@ pub struct SessionNewEvent {
     pub client: ClientId,
 }
 
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SessionClosedEvent {
+    pub session: SessionId,
+}
+
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ClientSubmitEvent {
     pub tasks: Vec<TaskDescriptor>,
@@ -43,16 +76,20 @@ pub struct ClientSubmitEvent {
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct InputDescriptor {
-    id: DataObjectId,
-    label: String,
+    pub id: DataObjectId,
+    pub label: String,
+    #[serde(default)]
+    pub path: String,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct TaskDescriptor {
-    id: TaskId,
-    inputs: Vec<InputDescriptor>,
-    task_type: String,
-    attributes: HashMap<String, String>,
+    pub id: TaskId,
+    pub inputs: Vec<InputDescriptor>,
+    #[serde(default)]
+    pub outputs: Vec<DataObjectId>,
+    pub task_type: String,
+    pub attributes: HashMap<String, String>,
 }
 
 impl TaskDescriptor {
@@ -64,25 +101,61 @@ impl TaskDescriptor {
                 .map(|i| InputDescriptor {
                     id: i.object.get().id(),
                     label: i.label.clone(),
+                    path: i.path.clone(),
                 })
                 .collect(),
+            outputs: task.outputs().iter().map(|o| o.get().id()).collect(),
             task_type: task.task_type().clone(),
             attributes: task.attributes().as_hashmap().clone(),
         }
     }
+
+    /// Number of cpus requested by the task, 1 if the attribute "resources" is missing
+    pub fn cpus(&self) -> u32 {
+        self.attributes
+            .get("resources")
+            .and_then(|r| ::serde_json::from_str::<Resources>(r).ok())
+            .map(|r| r.cpus())
+            .unwrap_or(1)
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct ObjectDescriptor {
-    id: DataObjectId,
-    producer: Option<TaskId>,
+    pub id: DataObjectId,
+    pub producer: Option<TaskId>,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub keep: bool,
+    #[serde(default)]
+    pub attributes: HashMap<String, String>,
+    /// Data submitted by the client (base64 encoded), missing if logging of data
+    /// is disabled or the data are larger than `MAX_LOGGED_DATA_SIZE`
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "base64_data")]
+    pub data: Option<Vec<u8>>,
+    /// Size of the data submitted by the client
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_size: Option<usize>,
 }
 
 impl ObjectDescriptor {
-    pub fn from(obj: &DataObject) -> Self {
+    /// Data submitted by the client are included only if `log_data` is true
+    pub fn from(obj: &DataObject, log_data: bool) -> Self {
         ObjectDescriptor {
             id: obj.id(),
             producer: obj.producer().as_ref().map(|t| t.get().id()),
+            label: obj.label().clone(),
+            keep: obj.client_keep(),
+            attributes: obj.attributes().as_hashmap().clone(),
+            data: obj.data().and_then(|d| {
+                if log_data && d.len() <= MAX_LOGGED_DATA_SIZE {
+                    Some(d.clone())
+                } else {
+                    None
+                }
+            }),
+            data_size: obj.data().map(|d| d.len()),
         }
     }
 }
@@ -119,6 +192,27 @@ pub struct MonitoringEvent {
     pub cpu_usage: Vec<CpuUsage>,            // Cpu usage in percent
     pub mem_usage: MemUsage,                 // Memory usage in bytes
     pub net_stat: HashMap<String, Vec<u64>>, // Network IO
+    #[serde(default)]
+    pub work_dir: WorkDirStats,
+}
+
+/// Disk usage and content of the working directory of a worker
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct WorkDirStats {
+    pub free_space: u64,      // Bytes available on the filesystem of work dir
+    pub data_in_memory: u64,  // Bytes of objects held in memory
+    pub data_in_files: u64,   // Bytes of objects stored in data/
+    pub task_dirs: usize,     // Number of temporary task directories
+    pub subworkers: usize,    // Number of running subworkers
+    pub idle_subworkers: usize,
+}
+
+/// Resources used by the process of a task (`!run` program or subworker)
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TaskUsageEvent {
+    pub task: TaskId,
+    pub worker: WorkerId,
+    pub usage: ProcessUsage,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -144,6 +238,7 @@ pub enum Event {
     ClientRemoved(ClientRemovedEvent),
 
     SessionNew(SessionNewEvent),
+    SessionClosed(SessionClosedEvent),
 
     ClientSubmit(ClientSubmitEvent),
     ClientUnkeep(ClientUnkeepEvent),
@@ -153,6 +248,7 @@ pub enum Event {
     DataObjectFinished(DataObjectFinishedEvent),
 
     Monitoring(MonitoringEvent),
+    TaskUsage(TaskUsageEvent),
 
     TaskFailed(TaskFailedEvent),
     ClientInvalidRequest(ClientInvalidRequestEvent),
@@ -168,6 +264,7 @@ impl Event {
             &Event::ClientNew(_) => "ClientNew",
             &Event::ClientRemoved(_) => "ClientRemoved",
             &Event::SessionNew(_) => "SessionNew",
+            &Event::SessionClosed(_) => "SessionClosed",
             &Event::ClientSubmit(_) => "ClientSubmit",
             &Event::ClientUnkeep(_) => "ClientUnkeep",
             &Event::TaskStarted(_) => "TaskStarted",
@@ -175,6 +272,7 @@ impl Event {
             &Event::TaskFailed(_) => "TaskFailed",
             &Event::DataObjectFinished(_) => "ObjectFinished",
             &Event::Monitoring(_) => "Monitoring",
+            &Event::TaskUsage(_) => "TaskUsage",
             &Event::ClientInvalidRequest(_) => "InvalidRequest",
             &Event::Dummy(_) => "Dummy",
         }
@@ -185,12 +283,51 @@ impl Event {
             &Event::TaskFinished(ref e) => Some(e.task.get_session_id()),
             &Event::TaskStarted(ref e) => Some(e.task.get_session_id()),
             &Event::TaskFailed(ref e) => Some(e.task.get_session_id()),
+            &Event::TaskUsage(ref e) => Some(e.task.get_session_id()),
             &Event::SessionNew(ref e) => Some(e.session),
+            &Event::SessionClosed(ref e) => Some(e.session),
+            &Event::DataObjectFinished(ref e) => Some(e.dataobject.get_session_id()),
             &Event::ClientSubmit(ref e) => {
                 // TODO: Quick hack, we expect that submit contains only tasks/obj from one session
-                e.tasks.get(0).map(|t| t.id.get_session_id())
+                e.tasks
+                    .get(0)
+                    .map(|t| t.id.get_session_id())
+                    .or_else(|| e.dataobjs.get(0).map(|o| o.id.get_session_id()))
             }
             _ => None,
         }
     }
+
+    /// Worker the event is related to
+    pub fn worker_id(&self) -> Option<WorkerId> {
+        match self {
+            &Event::WorkerNew(ref e) => Some(e.worker),
+            &Event::WorkerRemoved(ref e) => Some(e.worker),
+            &Event::TaskStarted(ref e) => Some(e.worker),
+            &Event::TaskFailed(ref e) => Some(e.worker),
+            &Event::DataObjectFinished(ref e) => Some(e.worker),
+            &Event::Monitoring(ref e) => Some(e.worker),
+            &Event::TaskUsage(ref e) => Some(e.worker),
+            _ => None,
+        }
+    }
+
+    /// Task the event is related to (events with more tasks return None)
+    pub fn task_id(&self) -> Option<TaskId> {
+        match self {
+            &Event::TaskStarted(ref e) => Some(e.task),
+            &Event::TaskFinished(ref e) => Some(e.task),
+            &Event::TaskFailed(ref e) => Some(e.task),
+            &Event::TaskUsage(ref e) => Some(e.task),
+            _ => None,
+        }
+    }
+
+    /// Object the event is related to (events with more objects return None)
+    pub fn dataobject_id(&self) -> Option<DataObjectId> {
+        match self {
+            &Event::DataObjectFinished(ref e) => Some(e.dataobject),
+            _ => None,
+        }
+    }
 }
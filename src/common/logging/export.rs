@@ -0,0 +1,189 @@
+use std::io::Write;
+use std::path::Path;
+
+use rusqlite::{Connection, OpenFlags};
+use serde_json;
+
+use common::events::Event;
+use common::id::{SId, SessionId};
+use errors::Result;
+use super::logger::{QueryEvents, SearchCriteria, SearchItemInt, SearchItemString};
+use super::sqlite_logger::load_events;
+
+/// Number of events loaded from the log at once
+const EXPORT_PAGE_SIZE: u32 = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// One JSON document `{"id": ..., "time": ..., "event": ...}` per line
+    JsonLines,
+    /// Columns id, timestamp, event_type, session, worker, task, dataobject and event
+    Csv,
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn to_string_or_empty<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_else(String::new)
+}
+
+/// Open the event log in the log directory of a server for reading
+pub fn open_event_log(log_dir: &Path) -> Result<Connection> {
+    let path = log_dir.join("events.db");
+    if !path.is_file() {
+        bail!("No event log (events.db) found in {:?}", log_dir);
+    }
+    Ok(Connection::open_with_flags(
+        &path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY,
+    )?)
+}
+
+/// Criteria selecting events of the session (all events if the session is None)
+pub fn session_criteria(session: Option<SessionId>) -> SearchCriteria {
+    SearchCriteria {
+        session: session.map(|s| SearchItemInt {
+            value: s as i64,
+            mode: "=".to_string(),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Criteria selecting events of the session in the event log (all events if the
+/// session is None). Servers restarted with the same log directory reuse session ids,
+/// so only events between `SessionNew` and `SessionClosed` of the session are selected
+/// and logs where the session was created more than once are rejected.
+pub fn logged_session_criteria(
+    conn: &mut Connection,
+    session: Option<SessionId>,
+) -> Result<SearchCriteria> {
+    let session = match session {
+        Some(session) => session,
+        None => return Ok(session_criteria(None)),
+    };
+    let event_criteria = |event_type: &str| SearchCriteria {
+        event_type: Some(SearchItemString {
+            value: event_type.to_string(),
+            mode: "=".to_string(),
+        }),
+        ..session_criteria(Some(session))
+    };
+
+    let created = load_events(conn, &event_criteria("SessionNew"))?.events;
+    let first_id = match created.len() {
+        0 => bail!("Session {} is not in the event log", session),
+        1 => created[0].0,
+        n => bail!(
+            "Session {} was created {} times in the event log \
+             (by servers started with the same log directory)",
+            session,
+            n
+        ),
+    };
+    let closed = load_events(
+        conn,
+        &SearchCriteria {
+            cursor: Some(first_id),
+            limit: Some(1),
+            ..event_criteria("SessionClosed")
+        },
+    )?.events;
+
+    Ok(SearchCriteria {
+        id: closed.first().map(|&(id, _, _)| SearchItemInt {
+            value: id,
+            mode: "<=".to_string(),
+        }),
+        cursor: Some(first_id - 1),
+        ..session_criteria(Some(session))
+    })
+}
+
+/// Load all events of the session from the log directory of a server
+pub fn load_session_events(log_dir: &Path, session: Option<SessionId>) -> Result<QueryEvents> {
+    let mut conn = open_event_log(log_dir)?;
+    let criteria = logged_session_criteria(&mut conn, session)?;
+    Ok(load_events(&mut conn, &criteria)?.events)
+}
+
+/// Export events of the event log in the log directory of a server,
+/// returns the number of exported events
+pub fn export_events(
+    log_dir: &Path,
+    session: Option<SessionId>,
+    format: ExportFormat,
+    output: &mut Write,
+) -> Result<usize> {
+    let mut conn = open_event_log(log_dir)?;
+    let mut criteria = SearchCriteria {
+        limit: Some(EXPORT_PAGE_SIZE),
+        ..logged_session_criteria(&mut conn, session)?
+    };
+
+    if format == ExportFormat::Csv {
+        writeln!(
+            output,
+            "id,timestamp,event_type,session,worker,task,dataobject,event"
+        )?;
+    }
+
+    let mut count = 0;
+    loop {
+        let response = load_events(&mut conn, &criteria)?;
+        for &(id, timestamp, ref event) in response.events.iter() {
+            match format {
+                ExportFormat::JsonLines => writeln!(
+                    output,
+                    "{{\"id\":{}, \"time\":\"{}\", \"event\":{}}}",
+                    id, timestamp, event
+                )?,
+                ExportFormat::Csv => {
+                    // Events that cannot be parsed (e.g. from other versions) are exported
+                    // without the derived columns
+                    let parsed: Option<Event> = serde_json::from_str(event).ok();
+                    let parsed = parsed.as_ref();
+                    writeln!(
+                        output,
+                        "{},{},{},{},{},{},{},{}",
+                        id,
+                        timestamp.to_rfc3339(),
+                        to_string_or_empty(parsed.map(|e| e.event_type())),
+                        to_string_or_empty(parsed.and_then(|e| e.session_id())),
+                        to_string_or_empty(parsed.and_then(|e| e.worker_id())),
+                        to_string_or_empty(parsed.and_then(|e| e.task_id()).map(|t| t.get_id())),
+                        to_string_or_empty(
+                            parsed
+                                .and_then(|e| e.dataobject_id())
+                                .map(|o| o.get_id())
+                        ),
+                        csv_field(event)
+                    )?
+                }
+            }
+            count += 1;
+        }
+        match response.next_cursor {
+            Some(cursor) => criteria.cursor = Some(cursor),
+            None => break,
+        }
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::csv_field;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("abc"), "abc");
+        assert_eq!(csv_field("{\"a\":1,\"b\":2}"), "\"{\"\"a\"\":1,\"\"b\"\":2}\"");
+    }
+}
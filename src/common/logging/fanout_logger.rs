@@ -0,0 +1,80 @@
+use futures::Future;
+use chrono::{DateTime, Utc};
+
+use common::events;
+use errors::Error;
+use super::logger::{Logger, QueryResponse, SearchCriteria};
+use super::feed::Subscription;
+
+/// Logger passing all events to several (at least one) loggers. Queries and
+/// subscriptions are served by the first logger that supports queries, so that
+/// ids of events in the live feed match ids of stored events.
+pub struct FanOutLogger {
+    loggers: Vec<Box<Logger>>,
+}
+
+impl FanOutLogger {
+    pub fn new(loggers: Vec<Box<Logger>>) -> Self {
+        assert!(!loggers.is_empty());
+        FanOutLogger { loggers }
+    }
+}
+
+impl Logger for FanOutLogger {
+    fn add_event_with_timestamp(&mut self, event: events::Event, timestamp: DateTime<Utc>) {
+        for logger in self.loggers.iter_mut() {
+            logger.add_event_with_timestamp(event.clone(), timestamp);
+        }
+    }
+
+    fn flush_events(&mut self) {
+        for logger in self.loggers.iter_mut() {
+            logger.flush_events();
+        }
+    }
+
+    fn supports_queries(&self) -> bool {
+        self.loggers.iter().any(|l| l.supports_queries())
+    }
+
+    fn get_events(
+        &self,
+        search_criteria: SearchCriteria,
+    ) -> Box<Future<Item = QueryResponse, Error = Error>> {
+        match self.loggers.iter().find(|l| l.supports_queries()) {
+            Some(logger) => logger.get_events(search_criteria),
+            None => Box::new(::futures::future::err(
+                "None of the loggers supports querying events".into(),
+            )),
+        }
+    }
+
+    fn subscribe(&mut self, search_criteria: SearchCriteria) -> Subscription {
+        let index = self.loggers
+            .iter()
+            .position(|l| l.supports_queries())
+            .unwrap_or(0);
+        self.loggers[index].subscribe(search_criteria)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::null_logger::NullLogger;
+    use super::super::sqlite_logger::SQLiteLogger;
+
+    #[test]
+    fn test_supports_queries() {
+        let loggers: Vec<Box<Logger>> = vec![Box::new(NullLogger::new())];
+        let logger = FanOutLogger::new(loggers);
+        assert!(!logger.supports_queries());
+        assert!(logger.get_events(Default::default()).wait().is_err());
+
+        let dir = ::tempdir::TempDir::new("rain-test").unwrap();
+        let sqlite = SQLiteLogger::new(&dir.path().to_path_buf(), Default::default()).unwrap();
+        let loggers: Vec<Box<Logger>> = vec![Box::new(NullLogger::new()), Box::new(sqlite)];
+        let logger = FanOutLogger::new(loggers);
+        assert!(logger.supports_queries());
+    }
+}
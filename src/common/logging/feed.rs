@@ -0,0 +1,201 @@
+use std::rc::{Rc, Weak};
+
+use futures::unsync::mpsc;
+use futures::{Poll, Stream};
+use chrono::{DateTime, Utc};
+use serde_json;
+
+use common::events::{Event, EventId};
+use common::id::SId;
+use super::logger::{SearchCriteria, SearchItemInt, SearchItemString};
+
+fn compare<T: PartialOrd>(value: &T, mode: &str, reference: &T) -> bool {
+    match mode {
+        "=" => value == reference,
+        "<" => value < reference,
+        ">" => value > reference,
+        "<=" => value <= reference,
+        ">=" => value >= reference,
+        _ => false,
+    }
+}
+
+impl SearchItemInt {
+    /// Check the value against the criterion; a missing value never matches
+    pub fn matches(&self, value: Option<i64>) -> bool {
+        value.map_or(false, |v| compare(&v, &self.mode, &self.value))
+    }
+}
+
+impl SearchItemString {
+    pub fn matches(&self, value: &str) -> bool {
+        compare(&value, &self.mode, &self.value.as_str())
+    }
+}
+
+impl SearchCriteria {
+    /// Check whether an event that has not been stored yet matches the criteria.
+    /// Criteria on event ids (`id`, `cursor`) and `limit` are ignored since ids
+    /// are assigned by the storage.
+    pub fn matches(&self, event: &Event, timestamp: &DateTime<Utc>) -> bool {
+        if let Some(ref v) = self.event_type {
+            if !v.matches(event.event_type()) {
+                return false;
+            }
+        }
+        if let Some(ref v) = self.session {
+            if !v.matches(event.session_id().map(|s| s as i64)) {
+                return false;
+            }
+        }
+        if let Some(ref v) = self.worker {
+            match event.worker_id() {
+                Some(w) => if !v.matches(&w.to_string()) {
+                    return false;
+                },
+                None => return false,
+            }
+        }
+        if let Some(ref v) = self.task {
+            if !v.matches(event.task_id().map(|t| t.get_id() as i64)) {
+                return false;
+            }
+        }
+        if let Some(ref v) = self.dataobject {
+            if !v.matches(event.dataobject_id().map(|o| o.get_id() as i64)) {
+                return false;
+            }
+        }
+        if self.time_from.map_or(false, |t| timestamp < &t)
+            || self.time_to.map_or(false, |t| timestamp >= &t)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+struct Subscriber {
+    criteria: SearchCriteria,
+    sender: mpsc::UnboundedSender<String>,
+    /// Dead when the subscription is dropped
+    alive: Weak<()>,
+}
+
+/// Stream of events of a subscription of `EventFeed`
+pub struct Subscription {
+    receiver: mpsc::UnboundedReceiver<String>,
+    _alive: Rc<()>,
+}
+
+impl Stream for Subscription {
+    type Item = String;
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<Option<String>, ()> {
+        self.receiver.poll()
+    }
+}
+
+/// Live feed of events, subscribers get events as they are added to a logger.
+/// Each event is sent as a JSON document `{"id": ..., "time": ..., "event": ...}`.
+#[derive(Default)]
+pub struct EventFeed {
+    subscribers: Vec<Subscriber>,
+}
+
+impl EventFeed {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Subscribe events matching the criteria. The subscription is removed
+    /// on the next published event after it is dropped.
+    pub fn subscribe(&mut self, criteria: SearchCriteria) -> Subscription {
+        let (sender, receiver) = mpsc::unbounded();
+        let alive = Rc::new(());
+        self.subscribers.push(Subscriber {
+            criteria,
+            sender,
+            alive: Rc::downgrade(&alive),
+        });
+        Subscription {
+            receiver,
+            _alive: alive,
+        }
+    }
+
+    /// Send the event with the id assigned by the logger to matching subscribers
+    pub fn publish(&mut self, id: EventId, event: &Event, timestamp: &DateTime<Utc>) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let mut data = None;
+        self.subscribers.retain(|s| {
+            if s.alive.upgrade().is_none() {
+                return false;
+            }
+            if !s.criteria.matches(event, timestamp) {
+                return true;
+            }
+            let data = data.get_or_insert_with(|| {
+                format!(
+                    "{{\"id\":{}, \"time\":\"{}\", \"event\":{}}}",
+                    id,
+                    timestamp,
+                    serde_json::to_string(event).unwrap()
+                )
+            });
+            s.sender.unbounded_send(data.clone()).is_ok()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::events;
+    use common::id::TaskId;
+    use futures::{Future, Stream};
+
+    fn session_criteria(session: i64) -> SearchCriteria {
+        SearchCriteria {
+            session: Some(SearchItemInt {
+                value: session,
+                mode: "=".to_string(),
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_feed_filters() {
+        let mut feed = EventFeed::new();
+        let receiver = feed.subscribe(session_criteria(2));
+        let now = Utc::now();
+        for session in 1..4 {
+            feed.publish(
+                session as EventId,
+                &Event::TaskFinished(events::TaskFinishedEvent {
+                    task: TaskId::new(session, 10),
+                }),
+                &now,
+            );
+        }
+        feed.publish(4, &Event::Dummy(1), &now);
+        drop(feed);
+        let received = receiver.collect().wait().unwrap();
+        assert_eq!(received.len(), 1);
+        assert!(received[0].starts_with("{\"id\":2,"));
+        assert!(received[0].contains("\"session_id\":2"));
+    }
+
+    #[test]
+    fn test_feed_drops_closed_subscribers() {
+        let mut feed = EventFeed::new();
+        drop(feed.subscribe(session_criteria(1)));
+        // The event does not match the criteria of the subscriber
+        feed.publish(1, &Event::Dummy(1), &Utc::now());
+        assert!(feed.subscribers.is_empty());
+    }
+}
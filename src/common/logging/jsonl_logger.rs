@@ -0,0 +1,110 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use futures::Future;
+use chrono::{DateTime, Utc};
+use serde_json;
+
+use common::events;
+use errors::{Error, Result};
+use super::logger::{Logger, QueryResponse, SearchCriteria};
+use super::feed::{EventFeed, Subscription};
+use super::sqlite_logger::EventWrapper;
+
+/// Logger appending events into `events.jsonl` in the log directory,
+/// one JSON document `{"time": ..., "event": ...}` per line.
+/// The file is never truncated and events cannot be queried from it.
+pub struct JsonLinesLogger {
+    events: Vec<EventWrapper>,
+    file: BufWriter<File>,
+    feed: EventFeed,
+    /// Id of the last event, events are numbered from 1 in each run of the server
+    last_id: events::EventId,
+}
+
+impl JsonLinesLogger {
+    pub fn new(log_dir: &PathBuf) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log_dir.join("events.jsonl"))?;
+        Ok(JsonLinesLogger {
+            events: Vec::new(),
+            file: BufWriter::new(file),
+            feed: EventFeed::new(),
+            last_id: 0,
+        })
+    }
+
+    fn write_events(&mut self, events: Vec<EventWrapper>) -> Result<()> {
+        for e in events {
+            writeln!(
+                self.file,
+                "{{\"time\":\"{}\", \"event\":{}}}",
+                e.timestamp.to_rfc3339(),
+                serde_json::to_string(&e.event)?
+            )?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+impl Logger for JsonLinesLogger {
+    fn add_event_with_timestamp(&mut self, event: events::Event, timestamp: DateTime<Utc>) {
+        self.last_id += 1;
+        self.feed.publish(self.last_id, &event, &timestamp);
+        self.events.push(EventWrapper { event, timestamp });
+    }
+
+    fn flush_events(&mut self) {
+        debug!("Writing {} events", self.events.len());
+        let events = ::std::mem::replace(&mut self.events, Vec::new());
+        if let Err(e) = self.write_events(events) {
+            error!("Writing events failed: {}", e);
+        }
+    }
+
+    fn get_events(
+        &self,
+        _search_criteria: SearchCriteria,
+    ) -> Box<Future<Item = QueryResponse, Error = Error>> {
+        Box::new(::futures::future::err(
+            "Events cannot be queried from the JSON lines logger".into(),
+        ))
+    }
+
+    fn subscribe(&mut self, search_criteria: SearchCriteria) -> Subscription {
+        self.feed.subscribe(search_criteria)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_write_events() {
+        let dir = ::tempdir::TempDir::new("rain-test").unwrap();
+        let path = dir.path().to_path_buf();
+        let mut logger = JsonLinesLogger::new(&path).unwrap();
+        logger.add_dummy_event();
+        logger.add_session_closed_event(2);
+        logger.flush_events();
+        logger.add_dummy_event();
+        logger.flush_events();
+
+        let mut content = String::new();
+        File::open(path.join("events.jsonl"))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        let lines: Vec<_> = content.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let value: ::serde_json::Value = ::serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(value["event"]["type"], "SessionClosed");
+        assert_eq!(value["event"]["session"], 2);
+    }
+}
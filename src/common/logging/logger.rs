@@ -1,9 +1,11 @@
 use common::id::{ClientId, DataObjectId, SessionId, TaskId, WorkerId};
 use common::events::{Event, ObjectDescriptor, TaskDescriptor};
 use common::events;
+use common::resources::Resources;
 use futures::Future;
 use chrono::{DateTime, Utc};
 use errors::Error;
+use super::feed::Subscription;
 
 #[derive(Deserialize)]
 pub struct SearchItemInt {
@@ -17,15 +19,41 @@ pub struct SearchItemString {
     pub mode: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct SearchCriteria {
     pub id: Option<SearchItemInt>,
     pub event_type: Option<SearchItemString>,
     pub session: Option<SearchItemInt>,
+    /// Worker address, e.g. "127.0.0.1:1234"
+    pub worker: Option<SearchItemString>,
+    /// Id of a task within its session, usually combined with `session`
+    pub task: Option<SearchItemInt>,
+    /// Id of a data object within its session, usually combined with `session`
+    pub dataobject: Option<SearchItemInt>,
+    /// Only events logged at this time or later
+    pub time_from: Option<DateTime<Utc>>,
+    /// Only events logged before this time
+    pub time_to: Option<DateTime<Utc>>,
+    /// Only events after the cursor, i.e. `next_cursor` of the previous page
+    pub cursor: Option<events::EventId>,
+    /// Maximal number of returned events
+    pub limit: Option<u32>,
+    /// Count all events matching the criteria (ignoring `cursor` and `limit`)
+    #[serde(default)]
+    pub count: bool,
 }
 
 pub type QueryEvents = Vec<(events::EventId, DateTime<Utc>, String)>;
 
+#[derive(Debug)]
+pub struct QueryResponse {
+    pub events: QueryEvents,
+    /// Number of all matching events, when requested by `SearchCriteria::count`
+    pub count: Option<i64>,
+    /// Cursor of the next page, None if there are no more events
+    pub next_cursor: Option<events::EventId>,
+}
+
 pub trait Logger {
     fn add_event(&mut self, event: Event) {
         self.add_event_with_timestamp(event, Utc::now());
@@ -35,8 +63,8 @@ pub trait Logger {
 
     fn flush_events(&mut self);
 
-    fn add_new_worker_event(&mut self, worker: WorkerId) {
-        self.add_event(Event::WorkerNew(events::WorkerNewEvent { worker }));
+    fn add_new_worker_event(&mut self, worker: WorkerId, resources: Resources) {
+        self.add_event(Event::WorkerNew(events::WorkerNewEvent { worker, resources }));
     }
 
     fn add_worker_removed_event(&mut self, worker: WorkerId, error_msg: String) {
@@ -46,10 +74,6 @@ pub trait Logger {
         }));
     }
 
-    fn add_worker_new_event(&mut self, worker: WorkerId) {
-        self.add_event(Event::WorkerNew(events::WorkerNewEvent { worker }));
-    }
-
     fn add_new_client_event(&mut self, client: ClientId) {
         self.add_event(Event::ClientNew(events::ClientNewEvent { client }));
     }
@@ -125,8 +149,20 @@ pub trait Logger {
         }));
     }
 
+    fn add_session_closed_event(&mut self, session: SessionId) {
+        self.add_event(Event::SessionClosed(events::SessionClosedEvent { session }));
+    }
+
+    /// Whether `get_events` can be served by the logger
+    fn supports_queries(&self) -> bool {
+        false
+    }
+
     fn get_events(
         &self,
         search_criteria: SearchCriteria,
-    ) -> Box<Future<Item = QueryEvents, Error = Error>>;
+    ) -> Box<Future<Item = QueryResponse, Error = Error>>;
+
+    /// Receive events matching the criteria as they are added (see `EventFeed`)
+    fn subscribe(&mut self, search_criteria: SearchCriteria) -> Subscription;
 }
@@ -1,2 +1,47 @@
 pub mod logger;
 pub mod sqlite_logger;
+pub mod jsonl_logger;
+pub mod null_logger;
+pub mod fanout_logger;
+pub mod feed;
+pub mod export;
+pub mod trace;
+
+use std::path::PathBuf;
+
+use errors::Result;
+use self::logger::Logger;
+use self::sqlite_logger::{RetentionPolicy, SQLiteLogger};
+use self::jsonl_logger::JsonLinesLogger;
+use self::null_logger::NullLogger;
+use self::fanout_logger::FanOutLogger;
+
+/// Names of loggers that can be created by `create_logger`
+pub const LOGGER_NAMES: &[&str] = &["sqlite", "jsonl", "null"];
+
+/// Create a logger by its name ("sqlite", "jsonl" or "null"),
+/// more names create a fan-out logger passing events to all of them
+pub fn create_logger(
+    names: &[&str],
+    log_dir: &PathBuf,
+    retention: RetentionPolicy,
+) -> Result<Box<Logger>> {
+    if names.is_empty() {
+        bail!("No logger selected");
+    }
+    let mut loggers: Vec<Box<Logger>> = Vec::new();
+    for name in names {
+        let logger: Box<Logger> = match *name {
+            "sqlite" => Box::new(SQLiteLogger::new(log_dir, retention.clone())?),
+            "jsonl" => Box::new(JsonLinesLogger::new(log_dir)?),
+            "null" => Box::new(NullLogger::new()),
+            _ => bail!("Unknown logger {:?}", name),
+        };
+        loggers.push(logger);
+    }
+    if loggers.len() == 1 {
+        Ok(loggers.pop().unwrap())
+    } else {
+        Ok(Box::new(FanOutLogger::new(loggers)))
+    }
+}
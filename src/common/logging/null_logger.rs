@@ -0,0 +1,43 @@
+use futures::Future;
+use chrono::{DateTime, Utc};
+
+use common::events;
+use errors::Error;
+use super::logger::{Logger, QueryResponse, SearchCriteria};
+use super::feed::{EventFeed, Subscription};
+
+/// Logger that drops all events; they are only passed to the live feed
+#[derive(Default)]
+pub struct NullLogger {
+    feed: EventFeed,
+    /// Id of the last event, events are numbered from 1 in each run of the server
+    last_id: events::EventId,
+}
+
+impl NullLogger {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Logger for NullLogger {
+    fn add_event_with_timestamp(&mut self, event: events::Event, timestamp: DateTime<Utc>) {
+        self.last_id += 1;
+        self.feed.publish(self.last_id, &event, &timestamp);
+    }
+
+    fn flush_events(&mut self) {}
+
+    fn get_events(
+        &self,
+        _search_criteria: SearchCriteria,
+    ) -> Box<Future<Item = QueryResponse, Error = Error>> {
+        Box::new(::futures::future::err(
+            "Events are not stored by the null logger".into(),
+        ))
+    }
+
+    fn subscribe(&mut self, search_criteria: SearchCriteria) -> Subscription {
+        self.feed.subscribe(search_criteria)
+    }
+}
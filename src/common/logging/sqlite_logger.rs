@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 //use common::id::{SessionId, WorkerId, DataObjectId, TaskId, ClientId, SId};
 use common::events;
@@ -6,8 +7,9 @@ use futures::sync::{mpsc, oneshot};
 use futures::Stream;
 use futures::Future;
 use errors::{Error, Result};
-use common::logging::logger::QueryEvents;
-use super::logger::{Logger, SearchCriteria};
+use common::id::SId;
+use super::logger::{Logger, QueryResponse, SearchCriteria};
+use super::feed::{EventFeed, Subscription};
 
 use serde_json;
 use rusqlite::Connection;
@@ -20,30 +22,60 @@ pub struct EventWrapper {
     pub timestamp: DateTime<Utc>,
 }
 
+/// How often are retention limits applied
+const MAINTENANCE_INTERVAL: u64 = 600; // seconds
+
+/// Limits of events kept in the log; events beyond the limits are periodically
+/// removed and the database is vacuumed
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    /// Remove events older than the given age
+    pub max_age: Option<::chrono::Duration>,
+    /// Keep at most the given number of the newest events
+    pub max_rows: Option<u64>,
+    /// Remove events of sessions closed longer than the given time ago
+    pub closed_sessions_age: Option<::chrono::Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_age.is_none() && self.max_rows.is_none() && self.closed_sessions_age.is_none()
+    }
+}
+
 pub struct SQLiteLogger {
-    events: Vec<EventWrapper>,
+    /// Events with ids assigned by the logger that are not saved yet
+    events: Vec<(events::EventId, EventWrapper)>,
     queue: mpsc::UnboundedSender<LoggerMessage>,
+    feed: EventFeed,
+    /// Id of the last event; ids continue after events of previous runs in the log
+    last_id: events::EventId,
     //    conn: Connection,
 }
 
 enum LoggerMessage {
-    SaveEvents(Vec<EventWrapper>),
-    LoadEvents(SearchCriteria, oneshot::Sender<QueryEvents>),
+    SaveEvents(Vec<(events::EventId, EventWrapper)>),
+    LoadEvents(SearchCriteria, oneshot::Sender<QueryResponse>),
 }
 
-fn save_events(conn: &mut Connection, events: Vec<EventWrapper>) -> Result<()> {
+fn save_events(conn: &mut Connection, events: Vec<(events::EventId, EventWrapper)>) -> Result<()> {
     debug!("Saving {} events into log", events.len());
     let tx = conn.transaction()?;
     {
         let mut stmt = tx.prepare_cached(
-            "INSERT INTO events (timestamp, event_type, session, event) VALUES (?, ?, ?, ?)",
+            "INSERT INTO events (id, timestamp, event_type, session, worker, task, dataobject, event)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
         )?;
 
-        for e in events.iter() {
+        for &(ref id, ref e) in events.iter() {
             stmt.execute(&[
+                id,
                 &e.timestamp,
                 &e.event.event_type(),
                 &e.event.session_id(),
+                &e.event.worker_id().map(|w| w.to_string()),
+                &e.event.task_id().map(|t| t.get_id()),
+                &e.event.dataobject_id().map(|o| o.get_id()),
                 &serde_json::to_string(&e.event)?,
             ])?;
         }
@@ -52,7 +84,52 @@ fn save_events(conn: &mut Connection, events: Vec<EventWrapper>) -> Result<()> {
     Ok(())
 }
 
-fn load_events(conn: &mut Connection, search_criteria: &SearchCriteria) -> Result<QueryEvents> {
+/// Remove events beyond the retention limits, returns the number of removed events
+fn apply_retention(conn: &mut Connection, retention: &RetentionPolicy) -> Result<usize> {
+    let now = Utc::now();
+    let mut removed = 0;
+    if let Some(age) = retention.max_age {
+        let oldest = now - age;
+        removed += conn.execute("DELETE FROM events WHERE timestamp < ?", &[&oldest])? as usize;
+    }
+    if let Some(age) = retention.closed_sessions_age {
+        let closed_before = now - age;
+        // Session ids restart in every run of the server, so only events logged
+        // before the SessionClosed event belong to the closed session
+        removed += conn.execute(
+            "DELETE FROM events WHERE EXISTS
+             (SELECT 1 FROM events AS closed
+              WHERE closed.event_type = 'SessionClosed' AND closed.timestamp < ?
+                    AND closed.session = events.session AND events.id <= closed.id)",
+            &[&closed_before],
+        )? as usize;
+    }
+    if let Some(max_rows) = retention.max_rows {
+        let offset = max_rows.saturating_sub(1) as i64;
+        removed += conn.execute(
+            "DELETE FROM events
+             WHERE id < (SELECT id FROM events ORDER BY id DESC LIMIT 1 OFFSET ?)",
+            &[&offset],
+        )? as usize;
+    }
+    Ok(removed)
+}
+
+fn maintain(conn: &mut Connection, retention: &RetentionPolicy) -> Result<()> {
+    let removed = apply_retention(conn, retention)?;
+    if removed > 0 {
+        info!("{} events removed from log, vacuuming", removed);
+        conn.execute_batch("VACUUM;")?;
+    }
+    Ok(())
+}
+
+pub fn load_events(
+    conn: &mut Connection,
+    search_criteria: &SearchCriteria,
+) -> Result<QueryResponse> {
+    // One more event is loaded to find out whether there is a next page
+    let limit = search_criteria.limit.map(|l| l as i64 + 1);
     let mut args: Vec<&::rusqlite::types::ToSql> = Vec::new();
     let mut where_conds = Vec::new();
 
@@ -71,60 +148,175 @@ fn load_events(conn: &mut Connection, search_criteria: &SearchCriteria) -> Resul
         args.push(&v.value);
     }
 
-    let query_str = if where_conds.is_empty() {
-        "SELECT id, timestamp, event FROM events ORDER BY id".to_string()
+    if let Some(ref v) = search_criteria.worker {
+        where_conds.push(make_where_string("worker", &v.mode)?);
+        args.push(&v.value);
+    }
+
+    if let Some(ref v) = search_criteria.task {
+        where_conds.push(make_where_string("task", &v.mode)?);
+        args.push(&v.value);
+    }
+
+    if let Some(ref v) = search_criteria.dataobject {
+        where_conds.push(make_where_string("dataobject", &v.mode)?);
+        args.push(&v.value);
+    }
+
+    if let Some(ref v) = search_criteria.time_from {
+        where_conds.push(make_where_string("timestamp", ">=")?);
+        args.push(v);
+    }
+
+    if let Some(ref v) = search_criteria.time_to {
+        where_conds.push(make_where_string("timestamp", "<")?);
+        args.push(v);
+    }
+
+    let count = if search_criteria.count {
+        let query_str = format!("SELECT COUNT(*) FROM events{}", where_clause(&where_conds));
+        debug!("Running query: {}", query_str);
+        Some(conn.query_row(&query_str, &args, |row| row.get(0))?)
     } else {
-        format!(
-            "SELECT id, timestamp, event FROM events WHERE {} ORDER BY id",
-            where_conds.join(" AND ")
-        )
+        None
     };
 
+    if let Some(ref v) = search_criteria.cursor {
+        where_conds.push(make_where_string("id", ">")?);
+        args.push(v);
+    }
+
+    let mut query_str = format!(
+        "SELECT id, timestamp, event FROM events{} ORDER BY id",
+        where_clause(&where_conds)
+    );
+    if let Some(ref v) = limit {
+        query_str.push_str(" LIMIT ?");
+        args.push(v);
+    }
+
     debug!("Running query: {}", query_str);
     let mut query = conn.prepare_cached(&query_str)?;
     //query.execute(&[])?;
     let iter = query
         .query_map(&args, |row| (row.get(0), row.get(1), row.get(2)))?
         .map(|e| e.unwrap());
-    let results: Vec<_> = iter.collect();
-    debug!("Logger query response: {} rows", results.len());
-    Ok(results)
+    let mut events: Vec<_> = iter.collect();
+    let next_cursor = match search_criteria.limit {
+        Some(l) if events.len() > l as usize => {
+            events.truncate(l as usize);
+            events.last().map(|e| e.0)
+        }
+        _ => None,
+    };
+    debug!("Logger query response: {} rows", events.len());
+    Ok(QueryResponse {
+        events,
+        count,
+        next_cursor,
+    })
+}
+
+fn where_clause(where_conds: &[String]) -> String {
+    if where_conds.is_empty() {
+        String::new()
+    } else {
+        format!(" WHERE {}", where_conds.join(" AND "))
+    }
+}
+
+/// Create the events table and its indices; columns added in newer versions are
+/// added into tables of older logs
+fn create_schema(conn: &mut Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+            timestamp TEXT NOT NULL,
+            event_type VARCHAR(14) NOT NULL,
+            session INTEGER,
+            worker TEXT,
+            task INTEGER,
+            dataobject INTEGER,
+            event TEXT NOT NULL
+         );",
+    )?;
+
+    let mut columns: Vec<String> = Vec::new();
+    {
+        let mut stmt = conn.prepare("PRAGMA table_info(events)")?;
+        for name in stmt.query_map(&[], |row| row.get(1))? {
+            columns.push(name?);
+        }
+    }
+    for &(column, column_type) in [
+        ("worker", "TEXT"),
+        ("task", "INTEGER"),
+        ("dataobject", "INTEGER"),
+    ].iter()
+    {
+        if !columns.iter().any(|c| c == column) {
+            debug!("Adding column {} into events", column);
+            conn.execute_batch(&format!(
+                "ALTER TABLE events ADD COLUMN {} {};",
+                column, column_type
+            ))?;
+        }
+    }
+
+    // There are basically two type of queries
+    // (1) initial "big", where "id" is not involved
+    // (2) "small" update, where we ask only for new updates, and ID is involved
+    // Indexes are created for type (1) query; type (2) uses implicit "id" index
+    // TOOD: This needs a benchmark
+    conn.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_timestamp ON events(timestamp);
+         CREATE INDEX IF NOT EXISTS idx_event_type ON events(event_type);
+         CREATE INDEX IF NOT EXISTS idx_session ON events(session);
+         CREATE INDEX IF NOT EXISTS idx_worker ON events(worker);
+         CREATE INDEX IF NOT EXISTS idx_task ON events(session, task);
+         CREATE INDEX IF NOT EXISTS idx_dataobject ON events(session, dataobject);",
+    )?;
+    Ok(())
+}
+
+/// Id of the last event ever saved into the log (also of removed events), 0 for a new log
+fn last_event_id(conn: &mut Connection) -> Result<events::EventId> {
+    match conn.query_row(
+        "SELECT seq FROM sqlite_sequence WHERE name = 'events'",
+        &[],
+        |row| row.get(0),
+    ) {
+        Ok(id) => Ok(id),
+        Err(::rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+        Err(e) => Err(e.into()),
+    }
 }
 
 impl SQLiteLogger {
-    pub fn new(log_dir: &PathBuf) -> Result<Self> {
+    pub fn new(log_dir: &PathBuf, retention: RetentionPolicy) -> Result<Self> {
         let mut conn = Connection::open(log_dir.join("events.db"))?;
-
-        // There are basically two type of queries
-        // (1) initial "big", where "id" is not involved
-        // (2) "small" update, where we ask only for new updates, and ID is involved
-        // Indexes are created for type (1) query; type (2) uses implicit "id" index
-        // TOOD: This needs a benchmark
-
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS events (
-                id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
-                timestamp TEXT NOT NULL,
-                event_type VARCHAR(14) NOT NULL,
-                session INTEGER,
-                event TEXT NOT NULL
-             );
-             CREATE INDEX IF NOT EXISTS idx_timestamp ON events(timestamp);
-             CREATE INDEX IF NOT EXISTS idx_event_type ON events(event_type);
-             CREATE INDEX IF NOT EXISTS idx_session ON events(session);
-             ",
-            &[],
-        )?;
+        create_schema(&mut conn)?;
+        let last_id = last_event_id(&mut conn)?;
 
         let (sx, rx) = mpsc::unbounded();
 
         ::std::thread::spawn(move || {
             debug!("Logger thread started");
             let mut core = ::tokio_core::reactor::Core::new().unwrap();
+            let maintenance_interval = Duration::from_secs(MAINTENANCE_INTERVAL);
+            let mut last_maintenance: Option<Instant> = None;
             let future = rx.for_each(move |m| {
                 match m {
                     LoggerMessage::SaveEvents(events) => {
                         save_events(&mut conn, events).unwrap();
+                        let maintenance_due = last_maintenance
+                            .map_or(true, |t| t.elapsed() >= maintenance_interval);
+                        if !retention.is_unlimited() && maintenance_due {
+                            if let Err(e) = maintain(&mut conn, &retention) {
+                                error!("Event log maintenance failed: {}", e.description());
+                            }
+                            last_maintenance = Some(Instant::now());
+                        }
                     }
                     LoggerMessage::LoadEvents(search_criteria, sender) => {
                         match load_events(&mut conn, &search_criteria) {
@@ -141,6 +333,8 @@ impl SQLiteLogger {
         Ok(SQLiteLogger {
             events: Vec::new(),
             queue: sx,
+            feed: EventFeed::new(),
+            last_id,
         })
     }
 }
@@ -153,10 +347,14 @@ fn make_where_string(column: &str, mode: &str) -> Result<String> {
 }
 
 impl Logger for SQLiteLogger {
+    fn supports_queries(&self) -> bool {
+        true
+    }
+
     fn get_events(
         &self,
         search_criteria: SearchCriteria,
-    ) -> Box<Future<Item = QueryEvents, Error = Error>> {
+    ) -> Box<Future<Item = QueryResponse, Error = Error>> {
         let (sx, rx) = oneshot::channel();
         self.queue
             .unbounded_send(LoggerMessage::LoadEvents(search_criteria, sx))
@@ -175,7 +373,17 @@ impl Logger for SQLiteLogger {
     }
 
     fn add_event_with_timestamp(&mut self, event: events::Event, timestamp: DateTime<Utc>) {
-        self.events.push(EventWrapper { event, timestamp });
+        self.last_id += 1;
+        self.feed.publish(self.last_id, &event, &timestamp);
+        self.events
+            .push((self.last_id, EventWrapper { event, timestamp }));
+    }
+
+    fn subscribe(
+        &mut self,
+        search_criteria: SearchCriteria,
+    ) -> Subscription {
+        self.feed.subscribe(search_criteria)
     }
 }
 
@@ -184,14 +392,24 @@ impl Logger for SQLiteLogger {
 mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr};
-    use common::id::WorkerId;
+    use common::id::{TaskId, WorkerId};
+    use common::resources::Resources;
+    use super::super::logger::{SearchItemInt, SearchItemString};
 
     fn create_test_worker_id() -> WorkerId {
         WorkerId::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9010)
     }
 
     fn create_logger() -> SQLiteLogger {
-        SQLiteLogger::new(&PathBuf::from("/tmp")).unwrap()
+        SQLiteLogger::new(&PathBuf::from("/tmp"), Default::default()).unwrap()
+    }
+
+    /// Assign ids to events as the logger does
+    fn numbered(
+        first_id: events::EventId,
+        wrappers: Vec<EventWrapper>,
+    ) -> Vec<(events::EventId, EventWrapper)> {
+        (first_id..).zip(wrappers).collect()
     }
 
     #[test]
@@ -215,8 +433,164 @@ mod tests {
     fn test_add_new_worker_event() {
         let mut logger = create_logger();
         let w = create_test_worker_id();
-        logger.add_new_worker_event(w);
-        let et = events::Event::WorkerNew(events::WorkerNewEvent { worker: w });
-        assert!(logger.events[0].event == et);
+        logger.add_new_worker_event(w, Resources { cpus: 2 });
+        let et = events::Event::WorkerNew(events::WorkerNewEvent {
+            worker: w,
+            resources: Resources { cpus: 2 },
+        });
+        assert!(logger.events[0].1.event == et);
+    }
+
+    #[test]
+    fn test_load_events_pagination() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&mut conn).unwrap();
+        let w = create_test_worker_id();
+        let timestamp = Utc::now();
+        let wrappers = (0..5)
+            .map(|i| EventWrapper {
+                event: events::Event::TaskStarted(events::TaskStartedEvent {
+                    task: TaskId::new(1, i % 2),
+                    worker: w,
+                }),
+                timestamp,
+            })
+            .collect();
+        save_events(&mut conn, numbered(1, wrappers)).unwrap();
+
+        let criteria = SearchCriteria {
+            task: Some(SearchItemInt {
+                value: 0,
+                mode: "=".to_string(),
+            }),
+            worker: Some(SearchItemString {
+                value: w.to_string(),
+                mode: "=".to_string(),
+            }),
+            limit: Some(2),
+            count: true,
+            ..Default::default()
+        };
+        let response = load_events(&mut conn, &criteria).unwrap();
+        assert_eq!(response.count, Some(3));
+        assert_eq!(response.events.len(), 2);
+        assert_eq!(response.next_cursor, Some(3));
+
+        let criteria = SearchCriteria {
+            cursor: response.next_cursor,
+            ..criteria
+        };
+        let response = load_events(&mut conn, &criteria).unwrap();
+        assert_eq!(response.events.len(), 1);
+        assert_eq!(response.events[0].0, 5);
+        assert_eq!(response.next_cursor, None);
+
+        let criteria = SearchCriteria {
+            time_to: Some(timestamp),
+            ..Default::default()
+        };
+        assert!(load_events(&mut conn, &criteria).unwrap().events.is_empty());
+    }
+
+    #[test]
+    fn test_logged_session_criteria() {
+        use super::super::export::logged_session_criteria;
+
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&mut conn).unwrap();
+        let w = create_test_worker_id();
+        let client = "127.0.0.1:9020".parse().unwrap();
+        let wrap = |event| EventWrapper {
+            event,
+            timestamp: Utc::now(),
+        };
+        let task_started = events::Event::TaskStarted(events::TaskStartedEvent {
+            task: TaskId::new(1, 0),
+            worker: w,
+        });
+        let wrappers = vec![
+            wrap(task_started.clone()),
+            wrap(events::Event::SessionNew(events::SessionNewEvent {
+                session: 1,
+                client,
+            })),
+            wrap(task_started.clone()),
+            wrap(events::Event::SessionClosed(events::SessionClosedEvent {
+                session: 1,
+            })),
+            wrap(task_started.clone()),
+        ];
+        save_events(&mut conn, numbered(1, wrappers)).unwrap();
+
+        let criteria = logged_session_criteria(&mut conn, Some(1)).unwrap();
+        let ids: Vec<_> = load_events(&mut conn, &criteria)
+            .unwrap()
+            .events
+            .iter()
+            .map(|e| e.0)
+            .collect();
+        assert_eq!(ids, vec![2, 3, 4]);
+        assert!(logged_session_criteria(&mut conn, Some(2)).is_err());
+
+        // The session id was reused by a restarted server
+        let wrappers = vec![
+            wrap(events::Event::SessionNew(events::SessionNewEvent {
+                session: 1,
+                client,
+            })),
+        ];
+        save_events(&mut conn, numbered(6, wrappers)).unwrap();
+        assert!(logged_session_criteria(&mut conn, Some(1)).is_err());
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_schema(&mut conn).unwrap();
+        let old = Utc::now() - ::chrono::Duration::hours(10);
+        let wrappers = vec![
+            EventWrapper {
+                event: events::Event::SessionNew(events::SessionNewEvent {
+                    session: 1,
+                    client: "127.0.0.1:1234".parse().unwrap(),
+                }),
+                timestamp: old,
+            },
+            EventWrapper {
+                event: events::Event::SessionClosed(events::SessionClosedEvent { session: 1 }),
+                timestamp: old,
+            },
+            EventWrapper {
+                event: events::Event::Dummy(1),
+                timestamp: Utc::now(),
+            },
+            // Session with the same id in a later run of the server
+            EventWrapper {
+                event: events::Event::SessionNew(events::SessionNewEvent {
+                    session: 1,
+                    client: "127.0.0.1:1234".parse().unwrap(),
+                }),
+                timestamp: Utc::now(),
+            },
+        ];
+        save_events(&mut conn, numbered(1, wrappers)).unwrap();
+
+        let retention = RetentionPolicy {
+            closed_sessions_age: Some(::chrono::Duration::hours(1)),
+            ..Default::default()
+        };
+        assert_eq!(apply_retention(&mut conn, &retention).unwrap(), 2);
+
+        let retention = RetentionPolicy {
+            max_rows: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(apply_retention(&mut conn, &retention).unwrap(), 1);
+        let response = load_events(&mut conn, &Default::default()).unwrap();
+        assert_eq!(response.events.len(), 1);
+        assert_eq!(response.events[0].0, 4);
+        assert!(response.events[0].2.contains("SessionNew"));
+        // Ids of removed events are not reused
+        assert_eq!(last_event_id(&mut conn).unwrap(), 4);
     }
 }
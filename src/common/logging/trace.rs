@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use serde_json::{self, Value};
+
+use common::events::Event;
+use common::id::{SId, TaskId, WorkerId};
+use super::logger::QueryEvents;
+
+/// Thread lane of data object events; cpu slot `i` is the lane `i + 1`
+const OBJECTS_LANE: usize = 0;
+
+/// Event in the Chrome Trace Event format
+#[derive(Serialize, Debug)]
+pub struct TraceEvent {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cat: Option<&'static str>,
+    /// Phase: "X" (complete event), "i" (instant event) or "M" (metadata)
+    pub ph: &'static str,
+    /// Time in microseconds since the first event
+    pub ts: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dur: Option<i64>,
+    pub pid: usize,
+    pub tid: usize,
+    /// Scope of instant events
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s: Option<&'static str>,
+    pub args: BTreeMap<String, Value>,
+}
+
+/// Trace in the Chrome Trace Event format (JSON object format), it can be loaded
+/// into Perfetto or chrome://tracing
+#[derive(Serialize, Debug)]
+pub struct Trace {
+    #[serde(rename = "traceEvents")]
+    pub trace_events: Vec<TraceEvent>,
+    #[serde(rename = "displayTimeUnit")]
+    pub display_time_unit: &'static str,
+}
+
+struct RunningTask {
+    worker: WorkerId,
+    lanes: Vec<usize>,
+    start: DateTime<Utc>,
+}
+
+struct WorkerLanes {
+    pid: usize,
+    /// Busy flags of cpu slots
+    busy: Vec<bool>,
+}
+
+impl WorkerLanes {
+    /// Occupy `cpus` free slots (the lowest ones), returns their thread lanes
+    fn occupy(&mut self, cpus: u32) -> Vec<usize> {
+        let mut lanes = Vec::new();
+        let mut slot = 0;
+        while lanes.len() < cpus.max(1) as usize {
+            if slot == self.busy.len() {
+                self.busy.push(false);
+            }
+            if !self.busy[slot] {
+                self.busy[slot] = true;
+                lanes.push(slot + 1);
+            }
+            slot += 1;
+        }
+        lanes
+    }
+
+    fn release(&mut self, lanes: &[usize]) {
+        for lane in lanes {
+            self.busy[lane - 1] = false;
+        }
+    }
+}
+
+#[derive(Default)]
+struct TraceBuilder {
+    origin: Option<DateTime<Utc>>,
+    workers: HashMap<WorkerId, WorkerLanes>,
+    /// Task types and cpus of submitted tasks
+    tasks: HashMap<TaskId, (String, u32)>,
+    running: HashMap<TaskId, RunningTask>,
+    trace_events: Vec<TraceEvent>,
+}
+
+impl TraceBuilder {
+    fn ts(&self, timestamp: &DateTime<Utc>) -> i64 {
+        self.origin
+            .map(|origin| {
+                timestamp
+                    .signed_duration_since(origin)
+                    .num_microseconds()
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0)
+    }
+
+    fn worker_lanes(&mut self, worker: &WorkerId) -> &mut WorkerLanes {
+        let pid = self.workers.len() + 1;
+        self.workers.entry(*worker).or_insert_with(|| WorkerLanes {
+            pid,
+            busy: Vec::new(),
+        })
+    }
+
+    fn task_started(&mut self, task: TaskId, worker: WorkerId, timestamp: DateTime<Utc>) {
+        let cpus = self.tasks.get(&task).map(|t| t.1).unwrap_or(1);
+        let lanes = self.worker_lanes(&worker).occupy(cpus);
+        self.running.insert(
+            task,
+            RunningTask {
+                worker,
+                lanes,
+                start: timestamp,
+            },
+        );
+    }
+
+    fn task_ended(
+        &mut self,
+        task: TaskId,
+        timestamp: DateTime<Utc>,
+        category: &'static str,
+        mut args: BTreeMap<String, Value>,
+    ) {
+        let running = match self.running.remove(&task) {
+            Some(r) => r,
+            None => return, // Start of the task was not logged
+        };
+        let pid = {
+            let lanes = self.worker_lanes(&running.worker);
+            lanes.release(&running.lanes);
+            lanes.pid
+        };
+        let name = match self.tasks.get(&task) {
+            Some(&(ref task_type, _)) => format!("{} {}", task_type, task.get_id()),
+            None => format!("task {}", task.get_id()),
+        };
+        args.insert("task".to_string(), Value::from(task.to_string()));
+        args.insert("worker".to_string(), Value::from(running.worker.to_string()));
+        args.insert("cpus".to_string(), Value::from(running.lanes.len()));
+        let ts = self.ts(&running.start);
+        let dur = self.ts(&timestamp) - ts;
+        for lane in running.lanes {
+            self.trace_events.push(TraceEvent {
+                name: name.clone(),
+                cat: Some(category),
+                ph: "X",
+                ts,
+                dur: Some(dur),
+                pid,
+                tid: lane,
+                s: None,
+                args: args.clone(),
+            });
+        }
+    }
+
+    fn add_event(&mut self, event: Event, timestamp: DateTime<Utc>) {
+        if self.origin.is_none() {
+            self.origin = Some(timestamp);
+        }
+        match event {
+            Event::ClientSubmit(e) => for t in e.tasks {
+                let cpus = t.cpus();
+                self.tasks.insert(t.id, (t.task_type, cpus));
+            },
+            Event::TaskStarted(e) => self.task_started(e.task, e.worker, timestamp),
+            Event::TaskFinished(e) => {
+                self.task_ended(e.task, timestamp, "task", BTreeMap::new())
+            }
+            Event::TaskFailed(e) => {
+                let mut args = BTreeMap::new();
+                args.insert("error".to_string(), Value::from(e.error_msg));
+                self.task_ended(e.task, timestamp, "failed", args)
+            }
+            Event::DataObjectFinished(e) => {
+                let pid = self.worker_lanes(&e.worker).pid;
+                let mut args = BTreeMap::new();
+                args.insert("object".to_string(), Value::from(e.dataobject.to_string()));
+                args.insert("size".to_string(), Value::from(e.size));
+                let ts = self.ts(&timestamp);
+                self.trace_events.push(TraceEvent {
+                    name: format!("object {}", e.dataobject.get_id()),
+                    cat: Some("object"),
+                    ph: "i",
+                    ts,
+                    dur: None,
+                    pid,
+                    tid: OBJECTS_LANE,
+                    s: Some("t"),
+                    args,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn metadata(&mut self, name: &str, pid: usize, tid: usize, value: String) {
+        let mut args = BTreeMap::new();
+        args.insert("name".to_string(), Value::from(value));
+        self.trace_events.push(TraceEvent {
+            name: name.to_string(),
+            cat: None,
+            ph: "M",
+            ts: 0,
+            dur: None,
+            pid,
+            tid,
+            s: None,
+            args,
+        });
+    }
+
+    fn finish(mut self, end: Option<DateTime<Utc>>) -> Trace {
+        // Tasks without a logged end are shown until the last event
+        if let Some(end) = end {
+            let unfinished: Vec<TaskId> = self.running.keys().cloned().collect();
+            for task in unfinished {
+                let mut args = BTreeMap::new();
+                args.insert("unfinished".to_string(), Value::from(true));
+                self.task_ended(task, end, "task", args);
+            }
+        }
+        let workers: Vec<_> = self.workers
+            .iter()
+            .map(|(id, lanes)| (*id, lanes.pid, lanes.busy.len()))
+            .collect();
+        for (id, pid, slots) in workers {
+            self.metadata("process_name", pid, 0, format!("worker {}", id));
+            self.metadata("thread_name", pid, OBJECTS_LANE, "objects".to_string());
+            for slot in 0..slots {
+                self.metadata("thread_name", pid, slot + 1, format!("cpu {}", slot));
+            }
+        }
+        Trace {
+            trace_events: self.trace_events,
+            display_time_unit: "ms",
+        }
+    }
+}
+
+/// Build a trace from logged events. Each worker is a process where each cpu slot
+/// is a thread lane; tasks occupy the lowest free slots of their workers.
+/// Finished data objects are instant events in a separate lane of the worker.
+pub fn build_trace(events: &QueryEvents) -> Trace {
+    let mut builder = TraceBuilder::default();
+    for &(_, timestamp, ref event) in events.iter() {
+        match serde_json::from_str(event) {
+            Ok(event) => builder.add_event(event, timestamp),
+            Err(e) => debug!("Event skipped in trace: {}", e),
+        }
+    }
+    let end = events.last().map(|e| e.1);
+    builder.finish(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::events;
+    use common::id::TaskId;
+    use chrono::Duration;
+
+    #[test]
+    fn test_build_trace_lanes() {
+        let worker: WorkerId = "127.0.0.1:1234".parse().unwrap();
+        let start = Utc::now();
+        let event = |secs: i64, e: events::Event| {
+            (0, start + Duration::seconds(secs), serde_json::to_string(&e).unwrap())
+        };
+        let started = |id| {
+            events::Event::TaskStarted(events::TaskStartedEvent {
+                task: TaskId::new(1, id),
+                worker,
+            })
+        };
+        let finished =
+            |id| events::Event::TaskFinished(events::TaskFinishedEvent { task: TaskId::new(1, id) });
+        let log = vec![
+            event(0, started(1)),
+            event(1, started(2)),
+            event(2, finished(1)),
+            event(3, started(3)),
+            event(4, finished(2)),
+            event(5, finished(3)),
+        ];
+        let trace = build_trace(&log);
+        let tasks: Vec<_> = trace
+            .trace_events
+            .iter()
+            .filter(|e| e.ph == "X")
+            .map(|e| (e.name.clone(), e.tid, e.ts, e.dur.unwrap()))
+            .collect();
+        assert_eq!(
+            tasks,
+            vec![
+                ("task 1".to_string(), 1, 0, 2_000_000),
+                ("task 2".to_string(), 2, 1_000_000, 3_000_000),
+                ("task 3".to_string(), 1, 3_000_000, 2_000_000),
+            ]
+        );
+        let threads = trace
+            .trace_events
+            .iter()
+            .filter(|e| e.name == "thread_name")
+            .count();
+        assert_eq!(threads, 3);
+    }
+}
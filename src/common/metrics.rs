@@ -0,0 +1,80 @@
+use std::fmt::{Display, Write};
+
+/// Builder of metrics in the Prometheus text exposition format
+pub struct Metrics {
+    text: String,
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics {
+            text: String::new(),
+        }
+    }
+
+    /// Start a metric family, `kind` is "gauge" or "counter"
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        writeln!(self.text, "# HELP {} {}", name, help).unwrap();
+        writeln!(self.text, "# TYPE {} {}", name, kind).unwrap();
+    }
+
+    /// Add a sample of the current family
+    pub fn value<T: Display>(&mut self, name: &str, labels: &[(&str, &str)], value: T) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<_> = labels
+                .iter()
+                .map(|&(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+                .collect();
+            write!(self.text, "{{{}}}", labels.join(",")).unwrap();
+        }
+        writeln!(self.text, " {}", value).unwrap();
+    }
+
+    /// Add a family with a single unlabeled gauge
+    pub fn gauge<T: Display>(&mut self, name: &str, help: &str, value: T) {
+        self.family(name, "gauge", help);
+        self.value(name, &[], value);
+    }
+
+    /// Add a family with a single unlabeled counter
+    pub fn counter<T: Display>(&mut self, name: &str, help: &str, value: T) {
+        self.family(name, "counter", help);
+        self.value(name, &[], value);
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_format() {
+        let mut m = Metrics::new();
+        m.gauge("rain_workers", "Connected workers", 2);
+        m.family("rain_tasks", "gauge", "Tasks by state");
+        m.value("rain_tasks", &[("state", "ready")], 3);
+        m.value("rain_tasks", &[("state", "a\"b\\")], 0);
+        assert_eq!(
+            m.into_string(),
+            "# HELP rain_workers Connected workers\n\
+             # TYPE rain_workers gauge\n\
+             rain_workers 2\n\
+             # HELP rain_tasks Tasks by state\n\
+             # TYPE rain_tasks gauge\n\
+             rain_tasks{state=\"ready\"} 3\n\
+             rain_tasks{state=\"a\\\"b\\\\\"} 0\n"
+        );
+    }
+}
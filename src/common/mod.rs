@@ -16,6 +16,7 @@ pub use self::attributes::Attributes;
 pub use self::resources::Resources;
 
 pub mod monitor;
+pub mod metrics;
 pub mod logging;
 pub mod fs;
 
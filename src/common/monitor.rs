@@ -6,12 +6,121 @@ use common::id::WorkerId;
 use std::collections::HashMap;
 use chrono::{DateTime, Utc};
 use sys_info::mem_info;
+use std::process::ExitStatus;
+use errors::Result;
 
 type CpuTimes = Vec<u64>;
 type CpuUsage = u8;
 
 type MemUsage = u8;
 
+/// Resource usage of a process read from `/proc/<pid>`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessUsage {
+    /// User and system cpu time of the process and its waited-for children (ms)
+    pub cpu_time: u64,
+    /// Peak resident set size of the process (bytes)
+    pub peak_rss: u64,
+    /// Bytes read by the process and its waited-for children (all read syscalls,
+    /// including pipes and reads served from the page cache)
+    pub read_bytes: u64,
+    /// Bytes written by the process and its waited-for children
+    pub written_bytes: u64,
+}
+
+impl ProcessUsage {
+    /// Keep the larger peak RSS of this and another measurement of the same process
+    pub fn update_peak_rss(&mut self, peak_rss: u64) {
+        self.peak_rss = self.peak_rss.max(peak_rss);
+    }
+
+    /// Usage of a long running process (subworker) since the `start` sample.
+    /// The peak RSS is taken from this sample, it is expected to be reset at the start.
+    pub fn since(&self, start: &ProcessUsage) -> ProcessUsage {
+        ProcessUsage {
+            cpu_time: self.cpu_time.saturating_sub(start.cpu_time),
+            peak_rss: self.peak_rss,
+            read_bytes: self.read_bytes.saturating_sub(start.read_bytes),
+            written_bytes: self.written_bytes.saturating_sub(start.written_bytes),
+        }
+    }
+}
+
+/// Sum of utime, stime, cutime and cstime (in clock ticks) from /proc/<pid>/stat
+fn parse_stat_cpu_ticks(stat: &str) -> Option<u64> {
+    // The command name may contain spaces and parentheses, fields are counted after it
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let mut ticks = 0;
+    for i in 11..15 {
+        ticks += fields.get(i)?.parse::<u64>().ok()?;
+    }
+    Some(ticks)
+}
+
+/// Value of the line "<key>: <value> [kB]" in /proc/<pid>/status or /proc/<pid>/io
+fn parse_proc_field(text: &str, key: &str) -> Option<u64> {
+    text.lines()
+        .find(|line| line.starts_with(key) && line[key.len()..].starts_with(':'))
+        .and_then(|line| line[key.len() + 1..].split_whitespace().next())
+        .and_then(|value| value.parse().ok())
+}
+
+/// Wait until the child process exits and reap it. Returns its exit status and
+/// final resource usage (including its waited-for children). The call blocks;
+/// `on_exit` is called when the process has exited but is not reaped yet, i.e.
+/// its pid cannot be reused by another process.
+pub fn wait_process<F: FnOnce()>(pid: u32, on_exit: F) -> Result<(ExitStatus, ProcessUsage)> {
+    use nix::libc;
+    use std::io::Error;
+    use std::os::unix::process::ExitStatusExt;
+
+    loop {
+        let mut info: libc::siginfo_t = unsafe { ::std::mem::zeroed() };
+        let r = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                &mut info,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if r == 0 {
+            break;
+        }
+        let error = Error::last_os_error();
+        if error.kind() != ::std::io::ErrorKind::Interrupted {
+            return Err(error.into());
+        }
+    }
+    on_exit();
+
+    // IO counters are not in rusage, they are read while the process is a zombie
+    let io = ::std::fs::read_to_string(format!("/proc/{}/io", pid)).unwrap_or_default();
+
+    let mut status = 0;
+    let mut rusage: libc::rusage = unsafe { ::std::mem::zeroed() };
+    loop {
+        let r = unsafe { libc::wait4(pid as libc::pid_t, &mut status, 0, &mut rusage) };
+        if r >= 0 {
+            break;
+        }
+        let error = Error::last_os_error();
+        if error.kind() != ::std::io::ErrorKind::Interrupted {
+            return Err(error.into());
+        }
+    }
+
+    let millis = |t: libc::timeval| t.tv_sec as u64 * 1000 + t.tv_usec as u64 / 1000;
+    let usage = ProcessUsage {
+        cpu_time: millis(rusage.ru_utime) + millis(rusage.ru_stime),
+        // Kilobytes on Linux
+        peak_rss: rusage.ru_maxrss as u64 * 1024,
+        read_bytes: parse_proc_field(&io, "rchar").unwrap_or(0),
+        written_bytes: parse_proc_field(&io, "wchar").unwrap_or(0),
+    };
+    Ok((ExitStatus::from_raw(status), usage))
+}
+
 pub struct Monitor {
     clk_tck: isize, // Result of syscall CLK_TCK
     last_timestamp: DateTime<Utc>,
@@ -116,6 +225,33 @@ impl Monitor {
         net_stat
     }
 
+    /// Read resource usage of a process; None if the process does not exist (anymore)
+    pub fn process_usage(&self, pid: u32) -> Option<ProcessUsage> {
+        if cfg!(not(target_os = "linux")) {
+            return None;
+        }
+        let read = |name: &str| ::std::fs::read_to_string(format!("/proc/{}/{}", pid, name)).ok();
+        let ticks = parse_stat_cpu_ticks(&read("stat")?)?;
+        // Memory of zombie processes is already released and io may not be permitted
+        let status = read("status").unwrap_or_default();
+        let io = read("io").unwrap_or_default();
+        Some(ProcessUsage {
+            cpu_time: ticks * 1000 / self.clk_tck as u64,
+            peak_rss: parse_proc_field(&status, "VmHWM").unwrap_or(0) * 1024,
+            read_bytes: parse_proc_field(&io, "rchar").unwrap_or(0),
+            written_bytes: parse_proc_field(&io, "wchar").unwrap_or(0),
+        })
+    }
+
+    /// Reset the peak RSS of a process (it is then reported since the reset)
+    pub fn reset_peak_rss(&self, pid: u32) {
+        if cfg!(target_os = "linux") {
+            if let Err(e) = ::std::fs::write(format!("/proc/{}/clear_refs", pid), "5") {
+                debug!("Peak RSS of process {} cannot be reset: {}", pid, e);
+            }
+        }
+    }
+
     pub fn build_event(&mut self, worker_id: &WorkerId) -> ::common::events::Event {
         let timestamp = Utc::now();
         let cpu_time = self.get_cpu_time();
@@ -131,6 +267,7 @@ impl Monitor {
             cpu_usage: cpu_usage,
             mem_usage: mem_usage,
             net_stat: net_stat,
+            work_dir: Default::default(),
         })
     }
 }
@@ -155,6 +292,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_parse_process_usage() {
+        let stat = "4242 (my (prog) x) S 1 4242 4242 0 -1 4194304 100 0 0 0 \
+                    120 30 5 7 20 0 1 0 100 1000 200";
+        assert_eq!(parse_stat_cpu_ticks(stat), Some(162));
+        assert_eq!(parse_stat_cpu_ticks("4242 (prog) S 1"), None);
+
+        let status = "Name:\tprog\nVmHWMx:\t1 kB\nVmHWM:\t    2048 kB\nVmRSS:\t1024 kB\n";
+        assert_eq!(parse_proc_field(status, "VmHWM"), Some(2048));
+        assert_eq!(parse_proc_field("rchar: 12345\nwchar: 10\n", "wchar"), Some(10));
+        assert_eq!(parse_proc_field("", "rchar"), None);
+    }
+
+    #[test]
+    fn test_process_usage() {
+        let monitor = Monitor::new();
+        let usage = monitor.process_usage(::std::process::id()).unwrap();
+        assert!(usage.peak_rss > 0);
+        assert!(monitor.process_usage(u32::max_value()).is_none());
+    }
+
+    #[test]
+    fn test_wait_process() {
+        let child = ::std::process::Command::new("sh")
+            .args(&["-c", "head -c 100000 /dev/zero > /dev/null; exit 3"])
+            .spawn()
+            .unwrap();
+        let mut exited = false;
+        let (status, usage) = wait_process(child.id(), || exited = true).unwrap();
+        assert!(exited);
+        assert_eq!(status.code(), Some(3));
+        assert!(usage.peak_rss > 0);
+        assert!(usage.read_bytes >= 100000);
+        assert!(usage.written_bytes >= 100000);
+    }
+
     #[test]
     fn test_net_stat() {
         let monitor = Monitor::new();
@@ -1,5 +1,7 @@
 #[macro_use]
 extern crate arrayref;
+extern crate base64;
+extern crate blake3;
 extern crate bytes;
 #[macro_use]
 extern crate capnp;
@@ -8,6 +10,7 @@ extern crate capnp_rpc;
 extern crate chrono;
 #[macro_use]
 extern crate error_chain;
+extern crate flate2;
 extern crate futures;
 extern crate hyper;
 #[macro_use]
@@ -21,8 +24,10 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate sha2;
 extern crate sys_info;
 extern crate sysconf;
+extern crate tar;
 extern crate tempdir;
 extern crate tokio_core;
 extern crate tokio_io;
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+
+use common::attributes::AttributeInfo;
+use server::graph::Task;
+
+/// Smoothing factor of the exponentially weighted moving average
+const RECENT_ALPHA: f64 = 0.2;
+
+/// Maximal number of remembered task signatures
+const MAX_SIGNATURES: usize = 10000;
+
+/// Estimated duration (in milliseconds) that counts as one more level in bottom levels
+const LEVEL_DURATION: f64 = 1000.0;
+
+/// Maximal weight of one task in bottom levels
+const MAX_WEIGHT: u32 = 100;
+
+/// Running statistics of durations of finished tasks (in milliseconds)
+#[derive(Debug, Clone, Serialize)]
+pub struct DurationEstimate {
+    /// Number of finished tasks
+    pub count: u64,
+    /// Mean of all durations
+    pub mean: f64,
+    /// Exponentially weighted moving average, follows recent changes
+    pub recent: f64,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl DurationEstimate {
+    fn new(duration: i64) -> Self {
+        DurationEstimate {
+            count: 1,
+            mean: duration as f64,
+            recent: duration as f64,
+            min: duration,
+            max: duration,
+        }
+    }
+
+    fn add(&mut self, duration: i64) {
+        self.count += 1;
+        self.mean += (duration as f64 - self.mean) / self.count as f64;
+        self.recent += RECENT_ALPHA * (duration as f64 - self.recent);
+        self.min = self.min.min(duration);
+        self.max = self.max.max(duration);
+    }
+}
+
+/// Signature of the task: task type and its configuration.
+/// Tasks with the same signature are expected to have similar durations.
+pub fn task_signature(task: &Task) -> String {
+    match task.attributes().as_hashmap().get("config") {
+        Some(config) => format!("{} {}", task.task_type(), config),
+        None => task.task_type().clone(),
+    }
+}
+
+/// Estimates of task durations learned from durations reported by workers
+/// (attribute "info"), kept per task type and per task signature.
+#[derive(Default, Debug, Clone, Serialize)]
+pub struct DurationEstimates {
+    by_type: HashMap<String, DurationEstimate>,
+    by_signature: HashMap<String, DurationEstimate>,
+}
+
+impl DurationEstimates {
+    /// Record the duration of a finished task
+    pub fn add_finished_task(&mut self, task: &Task) {
+        let info: AttributeInfo = match task.attributes().find("info") {
+            Ok(Some(info)) => info,
+            _ => return,
+        };
+        let duration = info.duration.max(0);
+
+        let signature = task_signature(task);
+        if let Some(estimate) = self.by_signature.get_mut(&signature) {
+            estimate.add(duration);
+        } else if self.by_signature.len() < MAX_SIGNATURES {
+            self.by_signature
+                .insert(signature, DurationEstimate::new(duration));
+        }
+
+        if let Some(estimate) = self.by_type.get_mut(task.task_type()) {
+            estimate.add(duration);
+            return;
+        }
+        self.by_type
+            .insert(task.task_type().clone(), DurationEstimate::new(duration));
+    }
+
+    /// Estimated duration of the task in milliseconds. Tasks with the same signature
+    /// are used when available, otherwise tasks of the same type.
+    pub fn estimate(&self, task: &Task) -> Option<f64> {
+        self.by_signature
+            .get(&task_signature(task))
+            .or_else(|| self.by_type.get(task.task_type()))
+            .map(|e| e.recent)
+    }
+
+    /// Weight of the task in bottom levels: 1 for tasks without an estimate,
+    /// tasks estimated to run long get one more level per `LEVEL_DURATION`
+    pub fn weight(&self, task: &Task) -> u32 {
+        self.estimate(task)
+            .map(|e| 1 + ((e / LEVEL_DURATION) as u32).min(MAX_WEIGHT - 1))
+            .unwrap_or(1)
+    }
+
+    /// Estimates for task types
+    pub fn by_type(&self) -> &HashMap<String, DurationEstimate> {
+        &self.by_type
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DurationEstimate;
+
+    #[test]
+    fn test_duration_estimate() {
+        let mut e = DurationEstimate::new(100);
+        e.add(200);
+        e.add(300);
+        assert_eq!(e.count, 3);
+        assert!((e.mean - 200.0).abs() < 1e-9);
+        assert!(e.recent > 100.0 && e.recent < e.mean);
+        assert_eq!((e.min, e.max), (100, 300));
+    }
+}
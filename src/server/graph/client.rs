@@ -10,6 +10,9 @@ use errors::Result;
 pub struct Client {
     pub(in super::super) id: ClientId,
     pub(in super::super) sessions: RcSet<SessionRef>,
+
+    /// Weight of the client when cpus are shared among clients
+    pub(in super::super) weight: f32,
 }
 
 pub type ClientRef = WrappedRcRefCell<Client>;
@@ -20,6 +23,7 @@ impl ClientRef {
         ClientRef::wrap(Client {
             id: address.clone(),
             sessions: Default::default(),
+            weight: 1f32,
         })
     }
 
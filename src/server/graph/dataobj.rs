@@ -121,6 +121,27 @@ impl DataObject {
     pub fn producer(&self) -> &Option<TaskRef> {
         &self.producer
     }
+
+    #[inline]
+    pub fn label(&self) -> &String {
+        &self.label
+    }
+
+    #[inline]
+    pub fn client_keep(&self) -> bool {
+        self.client_keep
+    }
+
+    /// Data submitted by the client (or downloaded by the server)
+    #[inline]
+    pub fn data(&self) -> Option<&Vec<u8>> {
+        self.data.as_ref()
+    }
+
+    #[inline]
+    pub fn attributes(&self) -> &Attributes {
+        &self.attributes
+    }
 }
 
 pub type DataObjectRef = WrappedRcRefCell<DataObject>;
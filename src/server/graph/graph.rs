@@ -46,6 +46,8 @@ mod tests {
                 format!("0.0.0.{}:67", wi + 1).parse().unwrap(),
                 None,
                 Resources { cpus: 8 },
+                Default::default(),
+                Default::default(),
             );
         }
         for ci in 0..clients {
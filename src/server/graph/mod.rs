@@ -7,7 +7,7 @@ mod graph;
 
 pub use self::client::{Client, ClientRef};
 pub use self::session::{Session, SessionError, SessionRef};
-pub use self::task::{Task, TaskInput, TaskRef, TaskState};
+pub use self::task::{Task, TaskConstraints, TaskInput, TaskRef, TaskState};
 pub use self::dataobj::{DataObject, DataObjectRef, DataObjectState};
 pub use self::worker::{Worker, WorkerRef};
 pub use self::graph::Graph;
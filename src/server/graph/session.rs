@@ -1,9 +1,10 @@
 use futures::unsync::oneshot::Receiver;
+use std::collections::HashMap;
 use std::fmt;
 
 use common::wrapped::WrappedRcRefCell;
 use common::{ConsistencyCheck, FinishHook, RcSet};
-use common::id::SessionId;
+use common::id::{Id, SessionId};
 use super::{ClientRef, DataObjectRef, DataObjectState, TaskRef, TaskState};
 use errors::Result;
 
@@ -31,10 +32,32 @@ pub struct Session {
 
     /// Hooks executed when all tasks are finished.
     pub(in super::super) finish_hooks: Vec<FinishHook>,
+
+    /// Weight of the session when cpus are shared among sessions of the client
+    pub(in super::super) weight: f32,
+
+    /// Maximal number of cpus used at once by tasks of the session (None = unlimited)
+    pub(in super::super) cpu_quota: Option<u32>,
+
+    /// Tasks of gangs (tasks with attribute "gang") by the gang name
+    pub(in super::super) gangs: HashMap<String, RcSet<TaskRef>>,
+
+    /// Number of cpus reserved by ready or running tasks scheduled on workers
+    pub(in super::super) active_cpus: u32,
+
+    /// Total number of cpus given to tasks of the session by the scheduler
+    pub(in super::super) served_cpus: u64,
+
+    /// Next id for tasks and objects submitted by running tasks
+    pub(in super::super) next_dynamic_id: Id,
 }
 
 pub type SessionRef = WrappedRcRefCell<Session>;
 
+/// Ids of tasks and objects submitted by running tasks start here, so they do not
+/// collide with ids chosen by the client.
+pub const DYNAMIC_ID_BASE: Id = 1 << 30;
+
 impl Session {
     /// Return the state of the session with optional error
     pub fn get_error(&self) -> &Option<SessionError> {
@@ -45,6 +68,13 @@ impl Session {
     pub fn is_failed(&self) -> bool {
         self.error.is_some()
     }
+
+    /// Allocate an id for a task or an object submitted by a running task
+    pub fn new_dynamic_id(&mut self) -> Id {
+        let id = self.next_dynamic_id;
+        self.next_dynamic_id += 1;
+        id
+    }
 }
 
 impl Session {
@@ -82,6 +112,12 @@ impl SessionRef {
             unfinished_tasks: 0,
             finish_hooks: Default::default(),
             error: None,
+            weight: 1f32,
+            cpu_quota: None,
+            gangs: Default::default(),
+            active_cpus: 0,
+            served_cpus: 0,
+            next_dynamic_id: DYNAMIC_ID_BASE,
         });
         // add to client
         client.get_mut().sessions.insert(s.clone());
@@ -6,7 +6,7 @@ use common::convert::ToCapnp;
 use common::wrapped::WrappedRcRefCell;
 use common::{Attributes, ConsistencyCheck, FinishHook, RcSet};
 use common::id::{SId, TaskId};
-use super::{DataObjectRef, DataObjectState, SessionRef, WorkerRef};
+use super::{DataObjectRef, DataObjectState, SessionRef, Worker, WorkerRef};
 pub use common_capnp::TaskState;
 use errors::Result;
 
@@ -21,6 +21,37 @@ pub struct TaskInput {
     // TODO: add any input params or flags
 }
 
+/// Placement constraints of a task (attribute "constraints")
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TaskConstraints {
+    /// Labels that the worker has to have
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// When non-empty, the task runs only on one of these hosts
+    /// (hostname, IP address or address with port of the worker)
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Tasks of the session with the same group never run on one worker at the same time
+    #[serde(default)]
+    pub anti_affinity: Option<String>,
+    /// Wait for a worker satisfying labels and hosts instead of rejecting the task
+    /// when no such worker is connected at submit time
+    #[serde(default)]
+    pub wait: bool,
+}
+
+impl TaskConstraints {
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.hosts.is_empty() && self.anti_affinity.is_none()
+    }
+
+    /// Worker satisfies labels and hosts (anti-affinity depends on other tasks)
+    pub fn allows_worker(&self, worker: &Worker) -> bool {
+        self.labels.iter().all(|label| worker.labels.contains(label))
+            && (self.hosts.is_empty() || self.hosts.iter().any(|host| worker.is_host(host)))
+    }
+}
+
 #[derive(Debug)]
 pub struct Task {
     /// Unique ID within a `Session`
@@ -61,6 +92,27 @@ pub struct Task {
 
     /// Task resources
     pub(in super::super) resources: Resources,
+
+    /// Priority set by the client (attribute "priority"), higher goes first
+    pub(in super::super) priority: i32,
+
+    /// Number of tasks on the longest path from this task to the end of the
+    /// session graph (including this task). Maintained by the scheduler.
+    pub(in super::super) bottom_level: u32,
+
+    /// Placement constraints set by the client (attribute "constraints")
+    pub(in super::super) constraints: TaskConstraints,
+
+    /// Name of the gang (attribute "gang"); all tasks of the session with the same
+    /// gang are scheduled at once
+    pub(in super::super) gang: Option<String>,
+
+    /// Number of workers the task needs (attribute "nodes"); `resources` are requested
+    /// on each of them. The task runs on the scheduled worker.
+    pub(in super::super) nodes: u32,
+
+    /// Other workers whose resources are reserved for the task (when `nodes` > 1)
+    pub(in super::super) peers: Vec<WorkerRef>,
 }
 
 pub type TaskRef = WrappedRcRefCell<Task>;
@@ -87,8 +139,27 @@ impl Task {
             }
         }
 
-        self.attributes
-            .to_capnp(&mut builder.borrow().get_attributes().unwrap());
+        if self.peers.is_empty() {
+            self.attributes
+                .to_capnp(&mut builder.borrow().get_attributes().unwrap());
+        } else {
+            // Multi-node task gets hosts of all its workers, the first one runs the task
+            let mut attributes = self.attributes.clone();
+            let hosts: Vec<String> = self.scheduled
+                .iter()
+                .chain(self.peers.iter())
+                .map(|wref| {
+                    let w = wref.get();
+                    if w.hostname.is_empty() {
+                        w.id().ip().to_string()
+                    } else {
+                        w.hostname.clone()
+                    }
+                })
+                .collect();
+            attributes.set("node_hosts", hosts).unwrap();
+            attributes.to_capnp(&mut builder.borrow().get_attributes().unwrap());
+        }
 
         builder.set_task_type(&self.task_type);
     }
@@ -103,6 +174,11 @@ impl Task {
         &self.inputs
     }
 
+    #[inline]
+    pub fn outputs(&self) -> &Vec<DataObjectRef> {
+        &self.outputs
+    }
+
     #[inline]
     pub fn attributes(&self) -> &Attributes {
         &self.attributes
@@ -121,6 +197,51 @@ impl Task {
         &self.task_type
     }
 
+    #[inline]
+    pub fn priority(&self) -> i32 {
+        self.priority
+    }
+
+    #[inline]
+    pub fn bottom_level(&self) -> u32 {
+        self.bottom_level
+    }
+
+    #[inline]
+    pub fn constraints(&self) -> &TaskConstraints {
+        &self.constraints
+    }
+
+    #[inline]
+    pub fn gang(&self) -> Option<&String> {
+        self.gang.as_ref()
+    }
+
+    #[inline]
+    pub fn nodes(&self) -> u32 {
+        self.nodes
+    }
+
+    /// The task may be scheduled on the worker: the worker satisfies the constraints
+    /// of the task and no other task of the same anti-affinity group is scheduled there
+    pub fn can_run_on(&self, worker: &Worker) -> bool {
+        if !self.constraints.allows_worker(worker) {
+            return false;
+        }
+        let group = match self.constraints.anti_affinity {
+            Some(ref group) => group,
+            None => return true,
+        };
+        worker.scheduled_tasks.iter().all(|tref| {
+            if tref.get_id() == self.id {
+                return true;
+            }
+            let t = tref.get();
+            t.id.get_session_id() != self.id.get_session_id()
+                || t.constraints.anti_affinity.as_ref() != Some(group)
+        })
+    }
+
     /// Inform observers that task is finished
     pub fn trigger_finish_hooks(&mut self) {
         assert!(self.is_finished());
@@ -167,6 +288,16 @@ impl TaskRef {
         resources: Resources,
     ) -> Result<Self> {
         assert_eq!(id.get_session_id(), session.get_id());
+        let priority: i32 = attributes.find("priority")?.unwrap_or(0);
+        let constraints: TaskConstraints = attributes.find("constraints")?.unwrap_or_default();
+        let gang: Option<String> = attributes.find("gang")?;
+        let nodes: u32 = attributes.find("nodes")?.unwrap_or(1);
+        if nodes == 0 {
+            bail!("Task {} requests zero nodes", id);
+        }
+        if gang.as_ref().map(|g| g.is_empty()).unwrap_or(false) {
+            bail!("Task {} has an empty gang name", id);
+        }
         let mut waiting = RcSet::new();
         for i in inputs.iter() {
             let inobj = i.object.get();
@@ -226,12 +357,24 @@ impl TaskRef {
             finish_hooks: Default::default(),
             attributes: attributes,
             resources: resources,
+            priority: priority,
+            bottom_level: 1,
+            constraints: constraints,
+            gang: gang.clone(),
+            nodes: nodes,
+            peers: Vec::new(),
         });
         {
             // add to session
             let mut s = session.get_mut();
             s.tasks.insert(sref.clone());
             s.unfinished_tasks += 1;
+            if let Some(gang) = gang {
+                s.gangs
+                    .entry(gang)
+                    .or_insert_with(Default::default)
+                    .insert(sref.clone());
+            }
         }
         {
             let s = sref.get_mut();
@@ -249,7 +392,73 @@ impl TaskRef {
         Ok(sref)
     }
 
+    /// Reserve resources of the task on peer workers (for multi-node tasks)
+    pub fn reserve_peers(&self, peers: Vec<WorkerRef>) {
+        let mut inner = self.get_mut();
+        assert!(inner.peers.is_empty());
+        let cpus = inner.resources.cpus();
+        for wref in &peers {
+            let mut w = wref.get_mut();
+            w.active_resources += cpus;
+            w.peer_tasks.insert(self.clone());
+        }
+        {
+            let mut s = inner.session.get_mut();
+            s.active_cpus += cpus * peers.len() as u32;
+            s.served_cpus += (cpus * peers.len() as u32) as u64;
+        }
+        inner.peers = peers;
+    }
+
+    /// Release resources reserved on peer workers
+    pub fn release_peers(&self) {
+        let mut inner = self.get_mut();
+        let cpus = inner.resources.cpus();
+        let peers = ::std::mem::replace(&mut inner.peers, Vec::new());
+        for wref in &peers {
+            let mut w = wref.get_mut();
+            w.active_resources -= cpus;
+            assert!(w.peer_tasks.remove(self));
+        }
+        inner.session.get_mut().active_cpus -= cpus * peers.len() as u32;
+    }
+
+    /// Detach an unfinished output from the task. The output is then delegated to
+    /// (produced by) a task submitted by this task.
+    pub fn detach_output(&self, output: &DataObjectRef) -> Result<()> {
+        let mut inner = self.get_mut();
+        let position = match inner.outputs.iter().position(|o| o == output) {
+            Some(p) => p,
+            None => bail!(
+                "Object {} is not an output of task {}",
+                output.get_id(),
+                inner.id
+            ),
+        };
+        if output.get().state != DataObjectState::Unfinished {
+            bail!("Finished object {} cannot be delegated", output.get_id());
+        }
+        inner.outputs.remove(position);
+        output.unschedule();
+        output.get_mut().producer = None;
+        Ok(())
+    }
+
+    /// Attach the output detached by `detach_output` back to the task.
+    pub fn attach_output(&self, output: &DataObjectRef) {
+        let mut inner = self.get_mut();
+        let mut o = output.get_mut();
+        assert!(o.producer.is_none());
+        o.producer = Some(self.clone());
+        if let Some(ref w) = inner.scheduled {
+            w.get_mut().scheduled_objects.insert(output.clone());
+            o.scheduled.insert(w.clone());
+        }
+        inner.outputs.push(output.clone());
+    }
+
     pub fn unschedule(&self) {
+        self.release_peers();
         let mut inner = self.get_mut();
         if let Some(ref w) = inner.scheduled {
             assert!(w.get_mut().scheduled_tasks.remove(&self));
@@ -259,6 +468,7 @@ impl TaskRef {
 
             if inner.state != TaskState::NotAssigned {
                 w.get_mut().active_resources -= inner.resources.cpus();
+                inner.session.get_mut().active_cpus -= inner.resources.cpus();
             }
         }
         inner.scheduled = None;
@@ -285,7 +495,20 @@ impl TaskRef {
         }
 
         // remove from owner
-        assert!(inner.session.get_mut().tasks.remove(&self));
+        {
+            let mut session = inner.session.get_mut();
+            assert!(session.tasks.remove(&self));
+            if let Some(ref gang) = inner.gang {
+                let empty = {
+                    let members = session.gangs.get_mut(gang).unwrap();
+                    members.remove(self);
+                    members.is_empty()
+                };
+                if empty {
+                    session.gangs.remove(gang);
+                }
+            }
+        }
         // clear and fail finish_hooks
         inner.finish_hooks.clear();
     }
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::fmt;
 
@@ -33,6 +34,9 @@ pub struct Worker {
     // (TODO: Generalize for Resource not only cpus)
     pub(in super::super) active_resources: u32,
 
+    /// Multi-node tasks scheduled on other workers that reserve resources here.
+    pub(in super::super) peer_tasks: RcSet<TaskRef>,
+
     /// Obects fully located on the worker.
     pub(in super::super) located_objects: RcSet<DataObjectRef>,
 
@@ -49,6 +53,12 @@ pub struct Worker {
     datastore: Option<AsyncInitWrapper<::datastore_capnp::data_store::Client>>,
 
     pub(in super::super) resources: Resources,
+
+    /// Labels announced by the worker (e.g. "gpu-node")
+    pub(in super::super) labels: HashSet<String>,
+
+    /// Hostname announced by the worker
+    pub(in super::super) hostname: String,
 }
 
 pub type WorkerRef = WrappedRcRefCell<Worker>;
@@ -59,6 +69,21 @@ impl Worker {
         &self.id
     }
 
+    #[inline]
+    pub fn labels(&self) -> &HashSet<String> {
+        &self.labels
+    }
+
+    #[inline]
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    /// Host matches the hostname, IP address or the full address of the worker
+    pub fn is_host(&self, host: &str) -> bool {
+        host == self.hostname || host == self.id.ip().to_string() || host == self.id.to_string()
+    }
+
     /// Get datastore of worker,
     /// First you have to call wait_for_datastore to make sure that
     /// datastore exists
@@ -106,6 +131,8 @@ impl WorkerRef {
         address: SocketAddr,
         control: Option<::worker_capnp::worker_control::Client>,
         resources: Resources,
+        labels: HashSet<String>,
+        hostname: String,
     ) -> Self {
         WorkerRef::wrap(Worker {
             id: address,
@@ -118,8 +145,11 @@ impl WorkerRef {
             scheduled_objects: Default::default(),
             control: control,
             active_resources: 0,
+            peer_tasks: Default::default(),
             resources: resources,
             datastore: None,
+            labels: labels,
+            hostname: hostname,
         })
     }
 
@@ -135,7 +165,7 @@ impl ConsistencyCheck for WorkerRef {
     fn check_consistency(&self) -> Result<()> {
         let s = self.get();
 
-        if s.scheduled_tasks.is_empty() && s.active_resources != 0 {
+        if s.scheduled_tasks.is_empty() && s.peer_tasks.is_empty() && s.active_resources != 0 {
             bail!(
                 "Invalid active resources: active_resources = {}",
                 s.active_resources
@@ -168,6 +198,11 @@ impl ConsistencyCheck for WorkerRef {
                 bail!("scheduled task ref {:?} inconsistency in {:?}", tref, s)
             }
         }
+        for tref in s.peer_tasks.iter() {
+            if !tref.get().peers.contains(self) {
+                bail!("peer task ref {:?} inconsistency in {:?}", tref, s)
+            }
+        }
         for tref in s.scheduled_ready_tasks.iter() {
             if tref.get().scheduled != Some(self.clone()) {
                 bail!(
@@ -1,10 +1,22 @@
-use hyper::{Error, StatusCode};
-use hyper::header::{AccessControlAllowOrigin, ContentEncoding, ContentLength, Encoding};
+use hyper::{Body, Chunk, Error, StatusCode};
+use hyper::header::{AccessControlAllowOrigin, CacheControl, CacheDirective, ContentEncoding,
+                    ContentLength, ContentType, Encoding};
 use hyper::server::{Request, Response, Service};
 use futures::Stream;
 use futures;
 use futures::Future;
+use futures::Sink;
+use serde::Serialize;
+use common::RcSet;
+use common::id::{DataObjectId, SId, SessionId};
+use common::metrics::Metrics;
+use common::logging::export::session_criteria;
+use common::logging::trace::build_trace;
+use common::logging::logger::{QueryEvents, SearchCriteria, SearchItemInt, SearchItemString};
+use errors::Result;
 use server::state::StateRef;
+use server::estimates::DurationEstimate;
+use server::graph::{TaskState, WorkerRef};
 
 pub struct RequestHandler {
     state: ::server::state::StateRef,
@@ -31,28 +43,449 @@ impl RequestHandler {
 
 type ResponseFuture = Box<futures::Future<Item = Response, Error = ::errors::Error>>;
 
+fn format_events(events: &QueryEvents) -> String {
+    let chunks: Vec<_> = events
+        .iter()
+        .map(|&(id, time, ref event)| {
+            format!(
+                "{{\"id\":{}, \"time\":\"{}\", \"event\":{}}}",
+                id, time, event
+            )
+        })
+        .collect();
+    format!("[{}]", chunks.join(","))
+}
+
 fn get_events(state: &StateRef, body: &str) -> ResponseFuture {
+    let state = state.clone();
+    match ::serde_json::from_str(body) {
+        Ok(search_criteria) => Box::new(
+            state
+                .get()
+                .logger
+                .get_events(search_criteria)
+                .map(|response| make_text_response(format_events(&response.events))),
+        ),
+        Err(e) => Box::new(::futures::future::failed(e.into())),
+    }
+}
+
+/// As `get_events` but the events are wrapped together with the count of matching
+/// events and the cursor of the next page
+fn search_events(state: &StateRef, body: &str) -> ResponseFuture {
     let state = state.clone();
     match ::serde_json::from_str(body) {
         Ok(search_criteria) => Box::new(state.get().logger.get_events(search_criteria).map(
-            |events| {
-                let chunks: Vec<_> = events
-                    .iter()
-                    .map(|&(id, time, ref event)| {
-                        format!(
-                            "{{\"id\":{}, \"time\":\"{}\", \"event\":{}}}",
-                            id, time, event
-                        )
-                    })
-                    .collect();
-                let result = format!("[{}]", chunks.join(","));
-                make_text_response(result)
+            |response| {
+                make_text_response(format!(
+                    "{{\"events\":{}, \"count\":{}, \"next_cursor\":{}}}",
+                    format_events(&response.events),
+                    ::serde_json::to_string(&response.count).unwrap(),
+                    ::serde_json::to_string(&response.next_cursor).unwrap()
+                ))
             },
         )),
         Err(e) => Box::new(::futures::future::failed(e.into())),
     }
 }
 
+fn feed_item_int(key: &str, value: &str) -> Result<SearchItemInt> {
+    Ok(SearchItemInt {
+        value: value
+            .parse()
+            .map_err(|_| format!("Invalid {}: {:?}", key, value))?,
+        mode: "=".to_string(),
+    })
+}
+
+fn feed_item_string(value: &str) -> SearchItemString {
+    SearchItemString {
+        value: value.to_string(),
+        mode: "=".to_string(),
+    }
+}
+
+/// Parse filters of the live event feed from the query string,
+/// e.g. `session=1&event_type=TaskFinished`
+fn parse_feed_criteria(query: &str) -> Result<SearchCriteria> {
+    let mut criteria = SearchCriteria::default();
+    for item in query.split('&').filter(|item| !item.is_empty()) {
+        let mut pair = item.splitn(2, '=');
+        let key = pair.next().unwrap();
+        let value = pair.next().unwrap_or("");
+        match key {
+            "session" => criteria.session = Some(feed_item_int(key, value)?),
+            "event_type" => criteria.event_type = Some(feed_item_string(value)),
+            "worker" => criteria.worker = Some(feed_item_string(value)),
+            "task" => criteria.task = Some(feed_item_int(key, value)?),
+            "dataobject" => criteria.dataobject = Some(feed_item_int(key, value)?),
+            _ => bail!("Invalid event filter: {:?}", key),
+        }
+    }
+    Ok(criteria)
+}
+
+/// Stream events as server-sent events as they are logged
+fn live_events(state: &StateRef, query: &str) -> ResponseFuture {
+    let criteria = match parse_feed_criteria(query) {
+        Ok(criteria) => criteria,
+        Err(e) => return Box::new(::futures::future::failed(e)),
+    };
+    let receiver = state.get_mut().logger.subscribe(criteria);
+    let (sender, body) = Body::pair();
+    // Forwarding ends when the client disconnects; the subscription is then
+    // removed by the logger when the next event is logged
+    let forward = receiver
+        .fold(sender, |sender, data| {
+            sender
+                .send(Ok(Chunk::from(format!("data: {}\n\n", data))))
+                .map_err(|_| ())
+        })
+        .map(|_| ());
+    state.get().handle().spawn(forward);
+    Box::new(::futures::future::ok(
+        Response::new()
+            .with_header(ContentType("text/event-stream".parse().unwrap()))
+            .with_header(CacheControl(vec![CacheDirective::NoCache]))
+            .with_header(AccessControlAllowOrigin::Any)
+            .with_body(body),
+    ))
+}
+
+#[derive(Serialize)]
+struct SessionEstimate {
+    id: i32,
+    unfinished_tasks: usize,
+    /// Estimated remaining work in cpu-milliseconds
+    remaining_work: f64,
+    /// Number of unfinished tasks without any estimate
+    unknown_tasks: usize,
+    /// Estimated time to finish in milliseconds when all cpus are used
+    eta: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Estimates<'a> {
+    task_types: &'a ::std::collections::HashMap<String, DurationEstimate>,
+    sessions: Vec<SessionEstimate>,
+}
+
+fn get_estimates(state: &StateRef) -> ResponseFuture {
+    let state = state.get();
+    let total_cpus: u32 = state
+        .graph
+        .workers
+        .values()
+        .map(|w| w.get().resources.cpus())
+        .sum();
+    let sessions = state
+        .graph
+        .sessions
+        .values()
+        .map(|sref| {
+            let (remaining_work, unknown_tasks) = state.session_remaining_work(sref);
+            SessionEstimate {
+                id: sref.get_id(),
+                unfinished_tasks: sref.get().unfinished_tasks,
+                remaining_work,
+                unknown_tasks,
+                eta: if total_cpus > 0 {
+                    Some(remaining_work / total_cpus as f64)
+                } else {
+                    None
+                },
+            }
+        })
+        .collect();
+    let estimates = Estimates {
+        task_types: state.scheduler_estimates().by_type(),
+        sessions,
+    };
+    make_json_response(&estimates)
+}
+
+#[derive(Serialize)]
+struct WorkerInfo {
+    id: String,
+    hostname: String,
+    labels: Vec<String>,
+    cpus: u32,
+    active_resources: u32,
+    scheduled_tasks: usize,
+    assigned_tasks: usize,
+    located_objects: usize,
+    assigned_objects: usize,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SessionInfo {
+    id: i32,
+    client: String,
+    tasks: usize,
+    objects: usize,
+    unfinished_tasks: usize,
+    active_cpus: u32,
+    cpu_quota: Option<u32>,
+    weight: f32,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TaskInfo {
+    id: i32,
+    task_type: String,
+    state: String,
+    cpus: u32,
+    scheduled: Option<String>,
+    worker: Option<String>,
+    inputs: Vec<i32>,
+    outputs: Vec<i32>,
+}
+
+#[derive(Serialize)]
+struct ObjectInfo {
+    id: i32,
+    session: i32,
+    label: String,
+    state: String,
+    size: Option<usize>,
+    keep: bool,
+    producer: Option<i32>,
+    consumers: Vec<i32>,
+    scheduled: Vec<String>,
+    assigned: Vec<String>,
+    located: Vec<String>,
+}
+
+fn worker_ids(workers: &RcSet<WorkerRef>) -> Vec<String> {
+    workers.iter().map(|w| w.get_id().to_string()).collect()
+}
+
+fn get_workers(state: &StateRef) -> ResponseFuture {
+    let state = state.get();
+    let workers: Vec<_> = state
+        .graph
+        .workers
+        .values()
+        .map(|wref| {
+            let w = wref.get();
+            WorkerInfo {
+                id: w.id().to_string(),
+                hostname: w.hostname().to_string(),
+                labels: w.labels().iter().cloned().collect(),
+                cpus: w.resources.cpus(),
+                active_resources: w.active_resources,
+                scheduled_tasks: w.scheduled_tasks.len(),
+                assigned_tasks: w.assigned_tasks.len(),
+                located_objects: w.located_objects.len(),
+                assigned_objects: w.assigned_objects.len(),
+                error: w.error.clone(),
+            }
+        })
+        .collect();
+    make_json_response(&workers)
+}
+
+fn get_sessions(state: &StateRef) -> ResponseFuture {
+    let state = state.get();
+    let sessions: Vec<_> = state
+        .graph
+        .sessions
+        .values()
+        .map(|sref| {
+            let s = sref.get();
+            SessionInfo {
+                id: s.id,
+                client: s.client.get_id().to_string(),
+                tasks: s.tasks.len(),
+                objects: s.objects.len(),
+                unfinished_tasks: s.unfinished_tasks,
+                active_cpus: s.active_cpus,
+                cpu_quota: s.cpu_quota,
+                weight: s.weight,
+                error: s.error.as_ref().map(|e| e.to_string()),
+            }
+        })
+        .collect();
+    make_json_response(&sessions)
+}
+
+fn get_session_tasks(state: &StateRef, session_id: SessionId) -> ResponseFuture {
+    let state = state.get();
+    let session = match state.graph.sessions.get(&session_id) {
+        Some(s) => s.clone(),
+        None => return not_found(),
+    };
+    let mut tasks: Vec<_> = session
+        .get()
+        .tasks
+        .iter()
+        .map(|tref| {
+            let t = tref.get();
+            TaskInfo {
+                id: t.id.get_id(),
+                task_type: t.task_type.clone(),
+                state: format!("{:?}", t.state),
+                cpus: t.resources.cpus(),
+                scheduled: t.scheduled.as_ref().map(|w| w.get_id().to_string()),
+                worker: t.assigned.as_ref().map(|w| w.get_id().to_string()),
+                inputs: t.inputs.iter().map(|i| i.object.get_id().get_id()).collect(),
+                outputs: t.outputs.iter().map(|o| o.get_id().get_id()).collect(),
+            }
+        })
+        .collect();
+    tasks.sort_by_key(|t| t.id);
+    make_json_response(&tasks)
+}
+
+/// Chrome trace of the session built from the event log
+fn get_session_trace(state: &StateRef, session_id: SessionId) -> ResponseFuture {
+    Box::new(
+        state
+            .get()
+            .logger
+            .get_events(session_criteria(Some(session_id)))
+            .and_then(|response| make_json_response(&build_trace(&response.events))),
+    )
+}
+
+fn get_object(state: &StateRef, id: DataObjectId) -> ResponseFuture {
+    let state = state.get();
+    let oref = match state.graph.objects.get(&id) {
+        Some(o) => o.clone(),
+        None => return not_found(),
+    };
+    let o = oref.get();
+    make_json_response(&ObjectInfo {
+        id: id.get_id(),
+        session: id.get_session_id(),
+        label: o.label.clone(),
+        state: format!("{:?}", o.state),
+        size: o.size,
+        keep: o.client_keep,
+        producer: o.producer.as_ref().map(|t| t.get_id().get_id()),
+        consumers: o.consumers.iter().map(|t| t.get_id().get_id()).collect(),
+        scheduled: worker_ids(&o.scheduled),
+        assigned: worker_ids(&o.assigned),
+        located: worker_ids(&o.located),
+    })
+}
+
+/// Read-only JSON API:
+/// `/api/workers`, `/api/sessions`, `/api/sessions/{session_id}/tasks`
+/// and `/api/objects/{session_id}/{id}`
+fn api_request(state: &StateRef, path: &str) -> ResponseFuture {
+    let parts: Vec<&str> = path.trim_right_matches('/').split('/').skip(2).collect();
+    match parts.len() {
+        1 if parts[0] == "workers" => get_workers(state),
+        1 if parts[0] == "sessions" => get_sessions(state),
+        3 if parts[0] == "sessions" && parts[2] == "tasks" => match parts[1].parse() {
+            Ok(session_id) => get_session_tasks(state, session_id),
+            Err(_) => not_found(),
+        },
+        3 if parts[0] == "sessions" && parts[2] == "trace" => match parts[1].parse() {
+            Ok(session_id) => get_session_trace(state, session_id),
+            Err(_) => not_found(),
+        },
+        3 if parts[0] == "objects" => match (parts[1].parse(), parts[2].parse()) {
+            (Ok(session_id), Ok(id)) => get_object(state, DataObjectId::new(session_id, id)),
+            _ => not_found(),
+        },
+        _ => not_found(),
+    }
+}
+
+fn get_metrics(state: &StateRef) -> ResponseFuture {
+    let state = state.get();
+    let mut m = Metrics::new();
+
+    let mut task_states = [
+        (TaskState::NotAssigned, "not_assigned", 0),
+        (TaskState::Ready, "ready", 0),
+        (TaskState::Assigned, "assigned", 0),
+        (TaskState::Running, "running", 0),
+        (TaskState::Finished, "finished", 0),
+        (TaskState::Failed, "failed", 0),
+    ];
+    for tref in state.graph.tasks.values() {
+        let task_state = tref.get().state;
+        for entry in task_states.iter_mut() {
+            if entry.0 == task_state {
+                entry.2 += 1;
+            }
+        }
+    }
+    m.family("rain_tasks", "gauge", "Tasks in the server graph by state");
+    for &(_, name, count) in task_states.iter() {
+        m.value("rain_tasks", &[("state", name)], count);
+    }
+    m.gauge(
+        "rain_ready_tasks",
+        "Ready tasks waiting for a worker",
+        state.ready_tasks_count(),
+    );
+    m.gauge("rain_objects", "Objects in the server graph", state.graph.objects.len());
+
+    let stats = &state.stats;
+    m.counter(
+        "rain_scheduler_runs_total",
+        "Number of scheduler runs",
+        stats.scheduler_runs,
+    );
+    m.counter(
+        "rain_scheduler_seconds_total",
+        "Time spent in the scheduler",
+        stats.scheduler_seconds,
+    );
+    m.counter(
+        "rain_transferred_objects_total",
+        "Objects sent to workers from the server or other workers",
+        stats.transferred_objects,
+    );
+    m.counter(
+        "rain_transferred_bytes_total",
+        "Size of objects sent to workers from the server or other workers",
+        stats.transferred_bytes,
+    );
+
+    m.gauge("rain_clients", "Connected clients", state.graph.clients.len());
+    m.gauge("rain_sessions", "Open sessions", state.graph.sessions.len());
+    m.gauge("rain_workers", "Connected workers", state.graph.workers.len());
+
+    let workers: Vec<_> = state
+        .graph
+        .workers
+        .values()
+        .map(|wref| {
+            let w = wref.get();
+            (
+                w.id().to_string(),
+                w.resources.cpus(),
+                w.active_resources,
+                w.assigned_tasks.len(),
+            )
+        })
+        .collect();
+    m.family("rain_worker_cpus", "gauge", "Cpus of the worker");
+    for &(ref id, cpus, _, _) in workers.iter() {
+        m.value("rain_worker_cpus", &[("worker", id.as_str())], cpus);
+    }
+    m.family(
+        "rain_worker_active_cpus",
+        "gauge",
+        "Cpus used by tasks assigned to the worker",
+    );
+    for &(ref id, _, active, _) in workers.iter() {
+        m.value("rain_worker_active_cpus", &[("worker", id.as_str())], active);
+    }
+    m.family("rain_worker_assigned_tasks", "gauge", "Tasks assigned to the worker");
+    for &(ref id, _, _, assigned) in workers.iter() {
+        m.value("rain_worker_assigned_tasks", &[("worker", id.as_str())], assigned);
+    }
+    Box::new(::futures::future::ok(make_text_response(m.into_string())))
+}
+
 fn lite_dashboard(state: &StateRef) -> ResponseFuture {
     Box::new(::futures::future::ok(make_text_response(format!(
         "<html>
@@ -71,6 +504,12 @@ fn lite_dashboard(state: &StateRef) -> ResponseFuture {
     </thead>
     {worker_tab}
     </table>
+    <h2>Task durations</h2>
+    <table>
+    <thead><tr><th>Type<th>Finished<th>Mean [ms]<th>Recent [ms]<th>Min [ms]<th>Max [ms]</tr>
+    </thead>
+    {duration_tab}
+    </table>
     </body>
     </html>",
         time = ::chrono::Utc::now(),
@@ -87,6 +526,19 @@ fn lite_dashboard(state: &StateRef) -> ResponseFuture {
                     id,
                     wref.get().resources.cpus
                 ))
+        ),
+        duration_tab = wrap_elements(
+            "<tr>",
+            "</tr>",
+            state
+                .get()
+                .scheduler_estimates()
+                .by_type()
+                .iter()
+                .map(|(task_type, e)| format!(
+                    "<td>{}</td><td>{}</td><td>{:.0}</td><td>{:.0}</td><td>{}</td><td>{}</td>",
+                    task_type, e.count, e.mean, e.recent, e.min, e.max
+                ))
         )
     ))))
 }
@@ -105,6 +557,22 @@ fn make_text_response(data: String) -> Response {
     }*/
 }
 
+fn make_json_response<T: Serialize>(value: &T) -> ResponseFuture {
+    Box::new(::futures::future::result(
+        ::serde_json::to_string(value)
+            .map(make_text_response)
+            .map_err(|e| e.into()),
+    ))
+}
+
+fn not_found() -> ResponseFuture {
+    Box::new(::futures::future::ok(
+        Response::new()
+            .with_status(StatusCode::NotFound)
+            .with_header(AccessControlAllowOrigin::Any),
+    ))
+}
+
 fn static_data_response(data: &'static [u8]) -> ResponseFuture {
     Box::new(::futures::future::ok(
         Response::new()
@@ -133,11 +601,17 @@ impl Service for RequestHandler {
         let state_ref = self.state.clone();
         debug!("HTTP request: {}", req.path());
         let path = req.path().to_string();
+        let query = req.query().unwrap_or("").to_string();
         Box::new(req.body().concat2().and_then(move |body| {
             let body = ::std::str::from_utf8(&body).unwrap();
             let future = match path.as_str() {
                 "/events" => get_events(&state_ref, &body),
+                "/events/live" => live_events(&state_ref, &query),
+                "/events/search" => search_events(&state_ref, &body),
                 "/lite" | "/lite/" => lite_dashboard(&state_ref),
+                "/estimates" => get_estimates(&state_ref),
+                "/metrics" => get_metrics(&state_ref),
+                path if path.starts_with("/api/") => api_request(&state_ref, path),
                 // to protect against caching, .js contain hash in index.html, the same for .css file
                 path if path.starts_with("/static/js/main.") && path.ends_with(".js") => {
                     static_gzipped_response(
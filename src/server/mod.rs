@@ -2,5 +2,8 @@ pub mod state;
 pub mod graph;
 pub mod rpc;
 pub mod scheduler;
+pub mod estimates;
+pub mod simulation;
+pub mod replay;
 pub mod http;
 pub mod testmode;
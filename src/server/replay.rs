@@ -0,0 +1,633 @@
+//! Replay of sessions from the event log.
+//!
+//! The graph of a session is reconstructed from `ClientSubmit` events and its
+//! execution from events of tasks and data objects. The analysis reports the
+//! critical path, utilization and idle gaps of workers and the volume of data
+//! transfers. The graph can be also resubmitted into a running server, e.g. to
+//! reproduce a run with another version of the server or on another cluster.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use capnp_rpc::rpc_twoparty_capnp;
+use chrono::{DateTime, Utc};
+use futures::Future;
+use serde_json;
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Core;
+
+use common::Attributes;
+use common::convert::ToCapnp;
+use common::events::{Event, ObjectDescriptor, TaskDescriptor};
+use common::id::{DataObjectId, SId, SessionId, TaskId, WorkerId};
+use common::logging::export::{logged_session_criteria, open_event_log};
+use common::logging::logger::{QueryEvents, SearchCriteria, SearchItemString};
+use common::logging::sqlite_logger::load_events;
+use common::resources::Resources;
+use errors::Result;
+use CLIENT_PROTOCOL_VERSION;
+
+struct TaskRun {
+    worker: WorkerId,
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    failed: bool,
+}
+
+/// Graph and execution of a session reconstructed from the event log
+pub struct SessionLog {
+    pub session: SessionId,
+    pub tasks: Vec<TaskDescriptor>,
+    pub objects: Vec<ObjectDescriptor>,
+    /// The last run of each started task
+    runs: HashMap<TaskId, TaskRun>,
+    /// Workers where objects were finished and their sizes
+    finished_objects: HashMap<DataObjectId, (WorkerId, usize)>,
+    workers: HashMap<WorkerId, Resources>,
+    last_timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IdleGap {
+    /// Start of the gap (ms since the start of the session)
+    pub start: f64,
+    pub end: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplayWorkerReport {
+    pub id: String,
+    /// Cpus of the worker (0 if not known from the log)
+    pub cpus: u32,
+    pub tasks: usize,
+    /// Sum of durations of tasks multiplied by their cpus (ms)
+    pub busy: f64,
+    /// busy / (cpus * makespan)
+    pub utilization: f64,
+    /// Time when no task of the session was running on the worker (ms)
+    pub idle: f64,
+    /// Idle periods not shorter than the minimal gap
+    pub idle_gaps: Vec<IdleGap>,
+    /// Bytes transferred to the worker
+    pub transferred: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CriticalPathReport {
+    /// Sum of durations of tasks on the path (ms)
+    pub length: f64,
+    /// Ids of tasks on the path
+    pub tasks: Vec<i32>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReplayReport {
+    pub session: SessionId,
+    pub tasks: usize,
+    pub objects: usize,
+    pub finished_tasks: usize,
+    pub failed_tasks: usize,
+    /// Time from the start of the first task to the end of the last task (ms)
+    pub makespan: f64,
+    /// The longest chain of dependent tasks
+    pub critical_path: CriticalPathReport,
+    /// Number of objects transferred to workers
+    pub transfers: usize,
+    /// Total number of bytes transferred between workers (and from the server)
+    pub transferred: u64,
+    pub workers: Vec<ReplayWorkerReport>,
+}
+
+fn duration_ms(from: &DateTime<Utc>, to: &DateTime<Utc>) -> f64 {
+    to.signed_duration_since(*from)
+        .num_microseconds()
+        .unwrap_or(0) as f64 / 1000f64
+}
+
+impl SessionLog {
+    pub fn new(session: SessionId) -> Self {
+        SessionLog {
+            session,
+            tasks: Vec::new(),
+            objects: Vec::new(),
+            runs: HashMap::new(),
+            finished_objects: HashMap::new(),
+            workers: HashMap::new(),
+            last_timestamp: None,
+        }
+    }
+
+    /// Add logged events, events of other sessions are ignored
+    pub fn add_events(&mut self, events: &QueryEvents) {
+        for &(_, timestamp, ref event) in events.iter() {
+            match serde_json::from_str(event) {
+                Ok(event) => self.add_event(event, timestamp),
+                Err(e) => debug!("Event skipped in replay: {}", e),
+            }
+        }
+    }
+
+    fn add_event(&mut self, event: Event, timestamp: DateTime<Utc>) {
+        if let Event::WorkerNew(ref e) = event {
+            self.workers.insert(e.worker, e.resources.clone());
+            return;
+        }
+        if event.session_id() != Some(self.session) {
+            return;
+        }
+        self.last_timestamp = Some(timestamp);
+        match event {
+            Event::ClientSubmit(mut e) => {
+                // Logs of older versions do not contain outputs of tasks,
+                // they are recovered from producers of objects
+                for t in e.tasks.iter_mut() {
+                    if t.outputs.is_empty() {
+                        t.outputs = e.dataobjs
+                            .iter()
+                            .filter(|o| o.producer == Some(t.id))
+                            .map(|o| o.id)
+                            .collect();
+                    }
+                }
+                self.tasks.extend(e.tasks);
+                self.objects.extend(e.dataobjs);
+            }
+            Event::TaskStarted(e) => {
+                self.runs.insert(
+                    e.task,
+                    TaskRun {
+                        worker: e.worker,
+                        start: timestamp,
+                        end: None,
+                        failed: false,
+                    },
+                );
+            }
+            Event::TaskFinished(e) => if let Some(run) = self.runs.get_mut(&e.task) {
+                run.end = Some(timestamp);
+            },
+            Event::TaskFailed(e) => if let Some(run) = self.runs.get_mut(&e.task) {
+                run.end = Some(timestamp);
+                run.failed = true;
+            },
+            Event::DataObjectFinished(e) => {
+                self.finished_objects
+                    .insert(e.dataobject, (e.worker, e.size));
+            }
+            _ => {}
+        }
+    }
+
+    /// Start and end of the run of the task; unfinished tasks end with the last event
+    fn run_interval(&self, task: &TaskId) -> Option<(&TaskRun, DateTime<Utc>)> {
+        self.runs.get(task).map(|run| {
+            let end = run.end
+                .or(self.last_timestamp)
+                .unwrap_or(run.start);
+            (run, end)
+        })
+    }
+
+    fn task_duration(&self, task: &TaskId) -> f64 {
+        self.run_interval(task)
+            .map(|(run, end)| duration_ms(&run.start, &end))
+            .unwrap_or(0f64)
+    }
+
+    /// The longest path of dependent tasks weighted by durations of tasks
+    fn critical_path(&self) -> CriticalPathReport {
+        let mut producers = HashMap::new();
+        for t in &self.tasks {
+            for o in &t.outputs {
+                producers.insert(*o, t.id);
+            }
+        }
+        let mut consumers: HashMap<TaskId, Vec<TaskId>> = HashMap::new();
+        let mut waiting: HashMap<TaskId, usize> = HashMap::new();
+        for t in &self.tasks {
+            let deps: HashSet<TaskId> = t.inputs
+                .iter()
+                .filter_map(|i| producers.get(&i.id).cloned())
+                .filter(|d| *d != t.id)
+                .collect();
+            for d in &deps {
+                consumers.entry(*d).or_insert_with(Vec::new).push(t.id);
+            }
+            waiting.insert(t.id, deps.len());
+        }
+
+        // Tasks are processed in a topological order; for each task the longest
+        // path ending in one of its dependencies is known when it is processed
+        let mut queue: VecDeque<TaskId> = self.tasks
+            .iter()
+            .filter(|t| waiting[&t.id] == 0)
+            .map(|t| t.id)
+            .collect();
+        let mut best: HashMap<TaskId, (f64, Option<TaskId>)> = HashMap::new();
+        let mut last: Option<(f64, TaskId)> = None;
+        while let Some(task) = queue.pop_front() {
+            let length =
+                best.get(&task).map(|b| b.0).unwrap_or(0f64) + self.task_duration(&task);
+            if last.map_or(true, |(l, _)| length > l) {
+                last = Some((length, task));
+            }
+            for c in consumers.get(&task).map(|c| c.as_slice()).unwrap_or(&[]) {
+                let entry = best.entry(*c).or_insert((0f64, None));
+                if entry.1.is_none() || length > entry.0 {
+                    *entry = (length, Some(task));
+                }
+                let w = waiting.get_mut(c).unwrap();
+                *w -= 1;
+                if *w == 0 {
+                    queue.push_back(*c);
+                }
+            }
+        }
+
+        let mut tasks = Vec::new();
+        let mut current = last.map(|l| l.1);
+        while let Some(task) = current {
+            tasks.push(task.get_id());
+            current = best.get(&task).and_then(|b| b.1);
+        }
+        tasks.reverse();
+        CriticalPathReport {
+            length: last.map(|l| l.0).unwrap_or(0f64),
+            tasks,
+        }
+    }
+
+    /// Analyze the execution of the session; idle gaps shorter than `min_gap` (ms)
+    /// are counted into the idle time but they are not listed
+    pub fn analyze(&self, min_gap: f64) -> ReplayReport {
+        let start = self.runs.values().map(|r| r.start).min();
+        let ms = |t: &DateTime<Utc>| start.map(|s| duration_ms(&s, t)).unwrap_or(0f64);
+
+        struct WorkerStats {
+            tasks: usize,
+            busy: f64,
+            intervals: Vec<(f64, f64)>,
+            transferred: u64,
+        }
+        let mut workers: HashMap<WorkerId, WorkerStats> = HashMap::new();
+        let mut makespan = 0f64;
+        for t in &self.tasks {
+            if let Some((run, end)) = self.run_interval(&t.id) {
+                let interval = (ms(&run.start), ms(&end));
+                let stats = workers.entry(run.worker).or_insert_with(|| WorkerStats {
+                    tasks: 0,
+                    busy: 0f64,
+                    intervals: Vec::new(),
+                    transferred: 0,
+                });
+                stats.tasks += 1;
+                stats.busy += (interval.1 - interval.0) * t.cpus() as f64;
+                stats.intervals.push(interval);
+                if interval.1 > makespan {
+                    makespan = interval.1;
+                }
+            }
+        }
+
+        // An object is transferred to a worker at most once; objects submitted by
+        // the client are uploaded from the server
+        let objects: HashMap<DataObjectId, &ObjectDescriptor> =
+            self.objects.iter().map(|o| (o.id, o)).collect();
+        let mut fetched = HashSet::new();
+        let mut transfers = 0;
+        for t in &self.tasks {
+            let worker = match self.runs.get(&t.id) {
+                Some(run) => run.worker,
+                None => continue,
+            };
+            for input in &t.inputs {
+                if !fetched.insert((input.id, worker)) {
+                    continue;
+                }
+                let size = match self.finished_objects.get(&input.id) {
+                    Some(&(w, _)) if w == worker => continue,
+                    Some(&(_, size)) => size,
+                    None => match objects.get(&input.id).and_then(|o| o.data_size) {
+                        Some(size) => size,
+                        None => continue,
+                    },
+                };
+                workers.get_mut(&worker).unwrap().transferred += size as u64;
+                transfers += 1;
+            }
+        }
+
+        let mut worker_reports: Vec<_> = workers
+            .into_iter()
+            .map(|(id, mut stats)| {
+                let cpus = self.workers.get(&id).map(|r| r.cpus()).unwrap_or(0);
+                stats
+                    .intervals
+                    .sort_by(|a, b| a.partial_cmp(b).unwrap());
+                let mut idle = 0f64;
+                let mut idle_gaps = Vec::new();
+                let mut cursor = 0f64;
+                // The end of the session closes the last gap
+                stats.intervals.push((makespan, makespan));
+                for &(s, e) in stats.intervals.iter() {
+                    if s > cursor {
+                        idle += s - cursor;
+                        if s - cursor >= min_gap {
+                            idle_gaps.push(IdleGap {
+                                start: cursor,
+                                end: s,
+                            });
+                        }
+                    }
+                    cursor = cursor.max(e);
+                }
+                ReplayWorkerReport {
+                    id: id.to_string(),
+                    cpus,
+                    tasks: stats.tasks,
+                    busy: stats.busy,
+                    utilization: if makespan > 0f64 && cpus > 0 {
+                        stats.busy / (cpus as f64 * makespan)
+                    } else {
+                        0f64
+                    },
+                    idle,
+                    idle_gaps,
+                    transferred: stats.transferred,
+                }
+            })
+            .collect();
+        worker_reports.sort_by(|a, b| a.id.cmp(&b.id));
+
+        ReplayReport {
+            session: self.session,
+            tasks: self.tasks.len(),
+            objects: self.objects.len(),
+            finished_tasks: self.runs
+                .values()
+                .filter(|r| r.end.is_some() && !r.failed)
+                .count(),
+            failed_tasks: self.runs.values().filter(|r| r.failed).count(),
+            makespan,
+            critical_path: self.critical_path(),
+            transfers,
+            transferred: worker_reports.iter().map(|w| w.transferred).sum(),
+            workers: worker_reports,
+        }
+    }
+}
+
+/// Load the session from the event log in the log directory of a server
+pub fn load_session_log(log_dir: &Path, session: SessionId) -> Result<SessionLog> {
+    let mut conn = open_event_log(log_dir)?;
+    let mut log = SessionLog::new(session);
+    // Workers are not bound to sessions, all of them are loaded for their resources
+    let worker_criteria = SearchCriteria {
+        event_type: Some(SearchItemString {
+            value: "WorkerNew".to_string(),
+            mode: "=".to_string(),
+        }),
+        ..Default::default()
+    };
+    log.add_events(&load_events(&mut conn, &worker_criteria)?.events);
+    let session_criteria = logged_session_criteria(&mut conn, Some(session))?;
+    log.add_events(&load_events(&mut conn, &session_criteria)?.events);
+    if log.tasks.is_empty() && log.objects.is_empty() {
+        bail!("No submitted tasks of session {} in the event log", session);
+    }
+    Ok(log)
+}
+
+/// Submit the graph of the session into a running server as a new session,
+/// wait until it is finished and close it. Returns the id of the new session.
+pub fn resubmit(log: &SessionLog, server: &SocketAddr) -> Result<SessionId> {
+    let missing: Vec<String> = log.objects
+        .iter()
+        .filter(|o| o.producer.is_none() && o.data.is_none())
+        .map(|o| o.id.to_string())
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "Data of objects {} are not in the event log (data are logged only by a server \
+             started with --log-client-data and only up to {} bytes)",
+            missing.join(", "),
+            ::common::events::MAX_LOGGED_DATA_SIZE
+        );
+    }
+
+    let mut core = Core::new()?;
+    let handle = core.handle();
+    let stream = core.run(TcpStream::connect(server, &handle))?;
+    stream.set_nodelay(true)?;
+    let mut rpc_system = ::common::rpc::new_rpc_system(stream, None);
+    let bootstrap: ::server_capnp::server_bootstrap::Client =
+        rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+    handle.spawn(rpc_system.map_err(|e| error!("RPC error: {:?}", e)));
+
+    let mut req = bootstrap.register_as_client_request();
+    req.get().set_version(CLIENT_PROTOCOL_VERSION);
+    let response = core.run(req.send().promise)?;
+    let service = response.get()?.get_service()?;
+
+    let response = core.run(service.new_session_request().send().promise)?;
+    let session_id = response.get()?.get_session_id();
+    info!(
+        "Resubmitting session {} as session {}",
+        log.session, session_id
+    );
+
+    // The session is closed also when it failed, so that it does not stay in the server
+    let result = submit_and_wait(&mut core, &service, log, session_id);
+    let mut req = service.close_session_request();
+    req.get().set_session_id(session_id);
+    let closed = core.run(req.send().promise);
+    result?;
+    closed?;
+    Ok(session_id)
+}
+
+/// Submit tasks and objects of the log into the session and wait for all its tasks
+fn submit_and_wait(
+    core: &mut Core,
+    service: &::client_capnp::client_service::Client,
+    log: &SessionLog,
+    session_id: SessionId,
+) -> Result<()> {
+    let mut req = service.submit_request();
+    {
+        let mut tasks = req.get().init_tasks(log.tasks.len() as u32);
+        for (i, t) in log.tasks.iter().enumerate() {
+            let mut ct = tasks.borrow().get(i as u32);
+            TaskId::new(session_id, t.id.get_id()).to_capnp(&mut ct.borrow().get_id()?);
+            {
+                let mut cinputs = ct.borrow().init_inputs(t.inputs.len() as u32);
+                for (j, input) in t.inputs.iter().enumerate() {
+                    let mut ci = cinputs.borrow().get(j as u32);
+                    DataObjectId::new(session_id, input.id.get_id())
+                        .to_capnp(&mut ci.borrow().get_id()?);
+                    ci.set_label(&input.label);
+                    ci.set_path(&input.path);
+                }
+            }
+            {
+                let mut coutputs = ct.borrow().init_outputs(t.outputs.len() as u32);
+                for (j, output) in t.outputs.iter().enumerate() {
+                    DataObjectId::new(session_id, output.get_id())
+                        .to_capnp(&mut coutputs.borrow().get(j as u32));
+                }
+            }
+            ct.set_task_type(&t.task_type);
+            Attributes::from_hashmap(t.attributes.clone())
+                .to_capnp(&mut ct.borrow().get_attributes()?);
+        }
+    }
+    {
+        let mut objects = req.get().init_objects(log.objects.len() as u32);
+        for (i, o) in log.objects.iter().enumerate() {
+            let mut co = objects.borrow().get(i as u32);
+            DataObjectId::new(session_id, o.id.get_id()).to_capnp(&mut co.borrow().get_id()?);
+            co.set_keep(o.keep);
+            co.set_label(&o.label);
+            if let Some(ref data) = o.data {
+                co.set_has_data(true);
+                co.set_data(data);
+            }
+            Attributes::from_hashmap(o.attributes.clone())
+                .to_capnp(&mut co.borrow().get_attributes()?);
+        }
+    }
+    core.run(req.send().promise)?;
+
+    let mut req = service.wait_request();
+    {
+        let mut ids = req.get().init_task_ids(1);
+        let mut id = ids.borrow().get(0);
+        id.set_id(::common_capnp::ALL_TASKS_ID);
+        id.set_session_id(session_id);
+    }
+    req.get().init_object_ids(0);
+    let response = core.run(req.send().promise)?;
+    match response.get()?.which()? {
+        ::common_capnp::unit_result::Which::Ok(()) => Ok(()),
+        ::common_capnp::unit_result::Which::Error(e) => bail!(
+            "Resubmitted session {} failed: {}",
+            session_id,
+            e?.get_message()?
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use common::events;
+
+    #[test]
+    fn test_analyze_chain() {
+        let w1: WorkerId = "127.0.0.1:1001".parse().unwrap();
+        let w2: WorkerId = "127.0.0.1:1002".parse().unwrap();
+        let start = Utc::now();
+        let o = |id| DataObjectId::new(1, id);
+        let t = |id| TaskId::new(1, id);
+        let object = |id, producer: Option<TaskId>| events::ObjectDescriptor {
+            id: o(id),
+            producer,
+            label: String::new(),
+            keep: false,
+            attributes: HashMap::new(),
+            data: None,
+            data_size: if producer.is_none() { Some(100) } else { None },
+        };
+        let task = |id, inputs: Vec<i32>, outputs: Vec<i32>| events::TaskDescriptor {
+            id: t(id),
+            inputs: inputs
+                .into_iter()
+                .map(|i| events::InputDescriptor {
+                    id: o(i),
+                    label: String::new(),
+                    path: String::new(),
+                })
+                .collect(),
+            outputs: outputs.into_iter().map(|i| o(i)).collect(),
+            task_type: "test".to_string(),
+            attributes: HashMap::new(),
+        };
+        let event = |secs: i64, e: Event| {
+            (0, start + Duration::seconds(secs), serde_json::to_string(&e).unwrap())
+        };
+        let started = |id, worker| {
+            Event::TaskStarted(events::TaskStartedEvent {
+                task: t(id),
+                worker,
+            })
+        };
+        let finished = |id| Event::TaskFinished(events::TaskFinishedEvent { task: t(id) });
+        let object_finished = |id, worker| {
+            Event::DataObjectFinished(events::DataObjectFinishedEvent {
+                dataobject: o(id),
+                worker,
+                size: 1000,
+            })
+        };
+
+        // 1 (data) -> task 10 -> 2 -> task 11 -> 4
+        //          -> task 12 -> 3 -> task 11
+        let log = vec![
+            event(
+                0,
+                Event::WorkerNew(events::WorkerNewEvent {
+                    worker: w1,
+                    resources: Resources { cpus: 1 },
+                }),
+            ),
+            event(
+                0,
+                Event::ClientSubmit(events::ClientSubmitEvent {
+                    tasks: vec![
+                        task(10, vec![1], vec![2]),
+                        task(12, vec![1], vec![3]),
+                        task(11, vec![2, 3], vec![4]),
+                    ],
+                    dataobjs: vec![
+                        object(1, None),
+                        object(2, Some(t(10))),
+                        object(3, Some(t(12))),
+                        object(4, Some(t(11))),
+                    ],
+                }),
+            ),
+            event(1, started(10, w1)),
+            event(1, started(12, w2)),
+            event(2, finished(12)),
+            event(2, object_finished(3, w2)),
+            event(4, finished(10)),
+            event(4, object_finished(2, w1)),
+            event(4, started(11, w1)),
+            event(6, finished(11)),
+        ];
+        let mut session_log = SessionLog::new(1);
+        session_log.add_events(&log);
+        let report = session_log.analyze(0f64);
+
+        assert_eq!(report.tasks, 3);
+        assert_eq!(report.finished_tasks, 3);
+        assert!((report.makespan - 5000f64).abs() < 1f64);
+        assert_eq!(report.critical_path.tasks, vec![10, 11]);
+        assert!((report.critical_path.length - 5000f64).abs() < 1f64);
+        // Data object 1 to both workers, object 3 from w2 to w1
+        assert_eq!(report.transfers, 3);
+        assert_eq!(report.transferred, 1200);
+
+        let r1 = &report.workers[0];
+        assert_eq!(r1.cpus, 1);
+        assert!((r1.utilization - 1f64).abs() < 0.01);
+        assert!(r1.idle_gaps.is_empty());
+        let r2 = &report.workers[1];
+        assert_eq!(r2.cpus, 0);
+        assert_eq!(r2.idle_gaps.len(), 1);
+        assert!((r2.idle_gaps[0].start - 1000f64).abs() < 1f64);
+        assert!((r2.idle - 4000f64).abs() < 1f64);
+    }
+}
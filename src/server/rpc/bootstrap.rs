@@ -1,4 +1,5 @@
 use futures::Future;
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use capnp::capability::Promise;
 use capnp;
@@ -63,7 +64,8 @@ impl server_bootstrap::Server for ServerBootstrapImpl {
 
         let service = ::client_capnp::client_service::ToClient::new(pry!(ClientServiceImpl::new(
             &self.state,
-            &self.address
+            &self.address,
+            params.get_weight()
         ))).from_server::<::capnp_rpc::Server>();
 
         info!("Connection {} registered as client", self.address);
@@ -102,10 +104,15 @@ impl server_bootstrap::Server for ServerBootstrapImpl {
         };
 
         let resources = Resources::from_capnp(&pry!(params.get_resources()));
+        let mut labels = HashSet::new();
+        for label in pry!(params.get_labels()).iter() {
+            labels.insert(pry!(label).to_string());
+        }
+        let hostname = pry!(params.get_hostname()).to_string();
 
         info!(
-            "Connection {} registered as worker {} with {:?}",
-            self.address, worker_id, resources
+            "Connection {} registered as worker {} ({}) with {:?}, labels {:?}",
+            self.address, worker_id, hostname, resources, labels
         );
 
         let control = pry!(params.get_control());
@@ -121,7 +128,7 @@ impl server_bootstrap::Server for ServerBootstrapImpl {
             let worker = pry!(
                 state
                     .get_mut()
-                    .add_worker(worker_id, Some(control), resources,)
+                    .add_worker(worker_id, Some(control), resources, labels, hostname)
             );
             let upstream = ::worker_capnp::worker_upstream::ToClient::new(
                 WorkerUpstreamImpl::new(&state, &worker),
@@ -20,10 +20,10 @@ pub struct ClientServiceImpl {
 }
 
 impl ClientServiceImpl {
-    pub fn new(state: &StateRef, address: &SocketAddr) -> Result<Self> {
+    pub fn new(state: &StateRef, address: &SocketAddr, weight: f32) -> Result<Self> {
         Ok(Self {
             state: state.clone(),
-            client: state.get_mut().add_client(address.clone())?,
+            client: state.get_mut().add_client(address.clone(), weight)?,
         })
     }
 }
@@ -81,11 +81,16 @@ impl client_service::Server for ClientServiceImpl {
 
     fn new_session(
         &mut self,
-        _: client_service::NewSessionParams,
+        params: client_service::NewSessionParams,
         mut results: client_service::NewSessionResults,
     ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let cpu_quota = match params.get_cpu_quota() {
+            0 => None,
+            quota => Some(quota),
+        };
         let mut s = self.state.get_mut();
-        let session = pry!(s.add_session(&self.client));
+        let session = pry!(s.add_session(&self.client, params.get_weight(), cpu_quota));
         results.get().set_session_id(session.get_id());
         debug!("Client asked for a new session, got {:?}", session.get_id());
         Promise::ok(())
@@ -181,7 +186,7 @@ impl client_service::Server for ClientServiceImpl {
                     .collect(),
                 created_objects
                     .iter()
-                    .map(|o| ObjectDescriptor::from(&o.get()))
+                    .map(|o| ObjectDescriptor::from(&o.get(), s.log_client_data()))
                     .collect(),
             );
             // verify submit integrity
@@ -1,8 +1,13 @@
+use std::collections::HashMap;
+
 use common::convert::FromCapnp;
 use common::Attributes;
-use common::id::{DataObjectId, TaskId};
+use common::resources::Resources;
+use common::id::{DataObjectId, Id, SId, TaskId};
+use common::events::{ObjectDescriptor, TaskDescriptor};
 use server::state::StateRef;
-use server::graph::{Worker, WorkerRef};
+use server::graph::{DataObjectRef, TaskInput, TaskRef, TaskState, Worker, WorkerRef};
+use errors::Result;
 use worker_capnp::worker_upstream;
 use capnp::capability::Promise;
 use server::rpc::WorkerDataStoreImpl;
@@ -118,6 +123,166 @@ impl worker_upstream::Server for WorkerUpstreamImpl {
         }
         Promise::ok(())
     }
+
+    fn submit_children(
+        &mut self,
+        params: worker_upstream::SubmitChildrenParams,
+        _: worker_upstream::SubmitChildrenResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let mut s = self.state.get_mut();
+        let parent = pry!(s.task_by_id_check_session(TaskId::from_capnp(&pry!(
+            params.get_parent()
+        ))));
+        {
+            let p = parent.get();
+            if p.assigned.as_ref() != Some(&self.worker)
+                || (p.state != TaskState::Assigned && p.state != TaskState::Running)
+            {
+                return Promise::err(::capnp::Error::failed(format!(
+                    "Task {} is not running on worker {}",
+                    p.id,
+                    self.worker.get_id()
+                )));
+            }
+        }
+        let tasks = pry!(params.get_tasks());
+        let objects = pry!(params.get_objects());
+        let delegations = pry!(params.get_delegations());
+        info!(
+            "Task {} submitted {} tasks and {} data objects",
+            parent.get_id(),
+            tasks.len(),
+            objects.len()
+        );
+        let session = parent.get().session.clone();
+        let session_id = session.get_id();
+        let mut created_tasks = Vec::<TaskRef>::new();
+        let mut created_objects = Vec::<DataObjectRef>::new();
+        let mut delegated = Vec::<DataObjectRef>::new();
+        // catch any insertion error and clean up later
+        let res: Result<()> = (|| {
+            // Submitted objects by their ids in the request
+            let mut submitted = HashMap::<Id, DataObjectRef>::new();
+            // first detach the delegated outputs, they replace their sources
+            for cd in delegations.iter() {
+                let output = s.object_by_id(DataObjectId::from_capnp(&cd.get_output()?))?;
+                parent.detach_output(&output)?;
+                delegated.push(output.clone());
+                let source = DataObjectId::from_capnp(&cd.get_source()?).get_id();
+                if submitted.insert(source, output).is_some() {
+                    bail!("Object {} is delegated more than once", source);
+                }
+            }
+            // second create the objects
+            for co in objects.iter() {
+                let local_id = DataObjectId::from_capnp(&co.get_id()?).get_id();
+                if submitted.contains_key(&local_id) {
+                    continue;
+                }
+                let id = DataObjectId::new(session_id, session.get_mut().new_dynamic_id());
+                let data = if co.get_has_data() {
+                    Some(co.get_data()?.into())
+                } else {
+                    None
+                };
+                let attributes = Attributes::from_capnp(&co.get_attributes()?);
+                let o = s.add_object(
+                    &session,
+                    id,
+                    co.get_keep(),
+                    co.get_label()?.to_string(),
+                    data,
+                    attributes,
+                )?;
+                created_objects.push(o.clone());
+                submitted.insert(local_id, o);
+            }
+            // third create the tasks
+            for ct in tasks.iter() {
+                let id = TaskId::new(session_id, session.get_mut().new_dynamic_id());
+                let attributes = Attributes::from_capnp(&ct.get_attributes()?);
+                let resources: Resources = attributes.get("resources")?;
+                let mut inputs = Vec::<TaskInput>::new();
+                for ci in ct.get_inputs()?.iter() {
+                    let local_id = DataObjectId::from_capnp(&ci.get_id()?).get_id();
+                    let object = if ci.get_existing() {
+                        s.object_by_id(DataObjectId::new(session_id, local_id))?
+                    } else {
+                        match submitted.get(&local_id) {
+                            Some(o) => o.clone(),
+                            None => bail!("Input {} is not a submitted object", local_id),
+                        }
+                    };
+                    inputs.push(TaskInput {
+                        object: object,
+                        label: ci.get_label()?.into(),
+                        path: ci.get_path()?.into(),
+                    });
+                }
+                let mut outputs = Vec::<DataObjectRef>::new();
+                for co in ct.get_outputs()?.iter() {
+                    let local_id = DataObjectId::from_capnp(&co).get_id();
+                    match submitted.get(&local_id) {
+                        Some(o) => outputs.push(o.clone()),
+                        None => bail!("Output {} is not a submitted object", local_id),
+                    }
+                }
+                let t = s.add_task(
+                    &session,
+                    id,
+                    inputs,
+                    outputs,
+                    ct.get_task_type()?.to_string(),
+                    attributes,
+                    resources,
+                )?;
+                created_tasks.push(t);
+            }
+            for o in delegated.iter() {
+                if o.get().producer.is_none() {
+                    bail!(
+                        "Delegated output {} is not produced by a submitted task",
+                        o.get_id()
+                    );
+                }
+            }
+            debug!("New child tasks: {:?}", created_tasks);
+            debug!("New child objects: {:?}", created_objects);
+            s.logger.add_client_submit_event(
+                created_tasks
+                    .iter()
+                    .map(|t| TaskDescriptor::from(&t.get()))
+                    .collect(),
+                created_objects
+                    .iter()
+                    .map(|o| ObjectDescriptor::from(&o.get(), s.log_client_data()))
+                    .collect(),
+            );
+            // verify submit integrity
+            s.verify_submit(&created_tasks, &created_objects)
+        })();
+        if res.is_err() {
+            debug!("Error: {:?}", res);
+            for t in created_tasks {
+                pry!(s.remove_task(&t));
+            }
+            for o in created_objects {
+                pry!(s.remove_object(&o));
+            }
+            for o in delegated {
+                parent.attach_output(&o);
+            }
+            pry!(res);
+        }
+        // Delegated outputs will be produced elsewhere, the parent worker drops them
+        for o in delegated {
+            if o.get().assigned.contains(&self.worker) {
+                s.unassign_object(&o, &self.worker);
+            }
+        }
+        Promise::ok(())
+    }
 }
 
 impl Worker {}
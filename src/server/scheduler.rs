@@ -1,8 +1,12 @@
 use std::collections::hash_map::HashMap;
+use std::collections::BTreeMap;
 use std::clone::Clone;
-use super::graph::{DataObjectRef, Graph, TaskRef, TaskState, WorkerRef};
+use std::cmp::Reverse;
+use super::graph::{ClientRef, DataObjectRef, Graph, Task, TaskRef, TaskState, WorkerRef};
 use common::RcSet;
+use common::id::{SessionId, TaskId};
 use server::graph::SessionRef;
+use server::estimates::DurationEstimates;
 
 #[derive(Default, Clone, Debug)]
 pub struct UpdatedOut {
@@ -60,9 +64,354 @@ impl UpdatedIn {
     fn schedule(&mut self, graph: &mut Graph, updated: &UpdatedIn) -> UpdatedOut;
 }*/
 
+/// Weight of one level of task's bottom level in the score of a task
+const BOTTOM_LEVEL_WEIGHT: i64 = 10000;
+
+/// Tasks consuming outputs of the given task
+fn consumers(tref: &TaskRef) -> Vec<TaskRef> {
+    let t = tref.get();
+    let mut result = Vec::new();
+    for oref in &t.outputs {
+        result.extend(oref.get().consumers.iter().cloned());
+    }
+    result
+}
+
+/// Compute bottom levels of newly submitted tasks and propagate
+/// the change to already existing producers of their inputs. Each task
+/// counts with its weight given by the estimate of its duration.
+/// Returns the old tasks whose bottom level was changed.
+fn update_bottom_levels(
+    new_tasks: &RcSet<TaskRef>,
+    estimates: &DurationEstimates,
+) -> RcSet<TaskRef> {
+    // Postorder DFS over new tasks; consumers are always processed before producers.
+    // Explicit stack is used, since the graph may contain very long chains.
+    let mut done: RcSet<TaskRef> = Default::default();
+    for root in new_tasks {
+        let mut stack = vec![(root.clone(), false)];
+        while let Some((tref, expanded)) = stack.pop() {
+            if done.contains(&tref) {
+                continue;
+            }
+            let next = consumers(&tref);
+            if expanded {
+                let level = estimates.weight(&tref.get())
+                    + next.iter().map(|c| c.get().bottom_level).max().unwrap_or(0);
+                tref.get_mut().bottom_level = level;
+                done.insert(tref);
+            } else {
+                stack.push((tref, true));
+                for c in next {
+                    if new_tasks.contains(&c) && !done.contains(&c) {
+                        stack.push((c, false));
+                    }
+                }
+            }
+        }
+    }
+
+    // Old tasks may get new consumers
+    let mut changed: RcSet<TaskRef> = Default::default();
+    let mut stack: Vec<TaskRef> = new_tasks.iter().cloned().collect();
+    while let Some(tref) = stack.pop() {
+        let t = tref.get();
+        for input in &t.inputs {
+            if let Some(ref pref) = input.object.get().producer {
+                if new_tasks.contains(pref) {
+                    continue;
+                }
+                let mut p = pref.get_mut();
+                let level = t.bottom_level + estimates.weight(&p);
+                if p.bottom_level < level && p.state != TaskState::Finished {
+                    p.bottom_level = level;
+                    changed.insert(pref.clone());
+                    stack.push(pref.clone());
+                }
+            }
+        }
+    }
+    changed
+}
+
+/// Minimal number of bytes of input transfers saved by moving an already
+/// scheduled task to another worker
+const STEAL_TRANSFER_MARGIN: i64 = 1024 * 1024;
+
+/// Size of inputs of the task that are not located on the worker
+fn missing_input_size(t: &Task, wref: &WorkerRef) -> i64 {
+    t.inputs
+        .iter()
+        .map(|input| {
+            let o = input.object.get();
+            if o.located.contains(wref) {
+                0
+            } else {
+                o.size.unwrap_or(0) as i64
+            }
+        })
+        .sum()
+}
+
+/// Work stealing: selects tasks that are scheduled on a worker but not started
+/// there yet (usually they wait for their inputs) and moves them to underloaded
+/// workers with free cpus when it saves at least `STEAL_TRANSFER_MARGIN` bytes of
+/// input transfers. Tasks that save the most go first.
+/// Returns pairs (task, new worker), the caller is responsible for moving the tasks
+/// (and stopping them on the original worker).
+pub fn steal_tasks(graph: &Graph, idle_workers: &RcSet<WorkerRef>) -> Vec<(TaskRef, WorkerRef)> {
+    let mut thieves: Vec<(WorkerRef, u32)> = idle_workers
+        .iter()
+        .filter_map(|wref| {
+            let w = wref.get();
+            if w.scheduled_ready_tasks.is_empty() && w.active_resources < w.resources.cpus() {
+                Some((wref.clone(), w.resources.cpus() - w.active_resources))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if thieves.is_empty() {
+        return Vec::new();
+    }
+
+    let mut candidates: Vec<TaskRef> = Vec::new();
+    for wref in graph.workers.values() {
+        for tref in &wref.get().scheduled_tasks {
+            let t = tref.get();
+            // Running tasks stay; members of gangs and multi-node tasks stay
+            // where they were placed.
+            if (t.state == TaskState::Ready || t.state == TaskState::Assigned)
+                && t.gang.is_none() && t.peers.is_empty()
+            {
+                candidates.push(tref.clone());
+            }
+        }
+    }
+
+    let mut moves = Vec::new();
+    for &mut (ref thief, ref mut free) in &mut thieves {
+        loop {
+            let best = candidates
+                .iter()
+                .enumerate()
+                .filter_map(|(i, tref)| {
+                    let t = tref.get();
+                    let victim = t.scheduled.as_ref().unwrap();
+                    if victim == thief || t.resources.cpus() > *free
+                        || !t.resources.is_subset_of(&thief.get().resources)
+                        || !t.can_run_on(&thief.get())
+                    {
+                        return None;
+                    }
+                    let gain = missing_input_size(&t, victim) - missing_input_size(&t, thief);
+                    if gain >= STEAL_TRANSFER_MARGIN {
+                        Some((gain, i))
+                    } else {
+                        None
+                    }
+                })
+                .max();
+            match best {
+                Some((_, i)) => {
+                    let tref = candidates.swap_remove(i);
+                    *free -= tref.get().resources.cpus();
+                    moves.push((tref, thief.clone()));
+                }
+                None => break,
+            }
+        }
+    }
+    moves
+}
+
+/// Sizes of inputs of the task that are scheduled on workers, summed per worker
+fn local_input_sizes(t: &Task) -> HashMap<WorkerRef, i64> {
+    let mut local = HashMap::new();
+    for input in &t.inputs {
+        let o = input.object.get();
+        for wref in &o.scheduled {
+            *local.entry(wref.clone()).or_insert(0) += o.size.unwrap() as i64;
+        }
+    }
+    local
+}
+
+/// Finds workers for all members of a gang at once. Members requesting more cpus
+/// are placed first. When `free_only` is set, only free cpus of workers are used,
+/// otherwise all cpus are used (for reservations and validation of submits).
+/// Workers in `excluded` are not used.
+pub fn place_gang(
+    graph: &Graph,
+    members: &[TaskRef],
+    free_only: bool,
+    excluded: &RcSet<WorkerRef>,
+) -> Option<Vec<(TaskRef, WorkerRef)>> {
+    let mut available: HashMap<WorkerRef, u32> = graph
+        .workers
+        .values()
+        .filter(|wref| !excluded.contains(*wref))
+        .map(|wref| {
+            let w = wref.get();
+            let cpus = if free_only {
+                w.resources.cpus() - w.active_resources
+            } else {
+                w.resources.cpus()
+            };
+            (wref.clone(), cpus)
+        })
+        .collect();
+    // Anti-affinity groups of members placed on workers
+    let mut groups: HashMap<WorkerRef, Vec<String>> = HashMap::new();
+
+    let mut members = members.to_vec();
+    members.sort_by_key(|tref| Reverse(tref.get().resources.cpus()));
+
+    let mut placement = Vec::new();
+    for tref in members {
+        let wref = {
+            let t = tref.get();
+            let cpus = t.resources.cpus();
+            let local = if free_only {
+                local_input_sizes(&t)
+            } else {
+                HashMap::new()
+            };
+            available
+                .iter()
+                .filter(|&(wref, free)| {
+                    let w = wref.get();
+                    let allowed = if free_only {
+                        t.can_run_on(&w)
+                    } else {
+                        t.constraints.allows_worker(&w)
+                    };
+                    let separated = match t.constraints.anti_affinity {
+                        Some(ref group) => groups
+                            .get(wref)
+                            .map(|g| !g.contains(group))
+                            .unwrap_or(true),
+                        None => true,
+                    };
+                    *free >= cpus && t.resources.is_subset_of(&w.resources) && allowed
+                        && separated
+                })
+                .max_by_key(|&(wref, free)| {
+                    (local.get(wref).cloned().unwrap_or(0), *free, wref.get_id())
+                })
+                .map(|(wref, _)| wref.clone())?
+        };
+        {
+            let t = tref.get();
+            *available.get_mut(&wref).unwrap() -= t.resources.cpus();
+            if let Some(ref group) = t.constraints.anti_affinity {
+                groups
+                    .entry(wref.clone())
+                    .or_insert_with(Vec::new)
+                    .push(group.clone());
+            }
+        }
+        placement.push((tref, wref));
+    }
+    Some(placement)
+}
+
+/// Schedule the ready task on the worker
+fn schedule_task(tref: &TaskRef, wref: &WorkerRef, up_out: &mut UpdatedOut) {
+    {
+        let mut w = wref.get_mut();
+        let mut t = tref.get_mut();
+
+        assert!(t.state == TaskState::Ready);
+        w.active_resources += t.resources.cpus();
+        {
+            let mut s = t.session.get_mut();
+            s.active_cpus += t.resources.cpus();
+            s.served_cpus += t.resources.cpus() as u64;
+        }
+        w.scheduled_tasks.insert(tref.clone());
+
+        // Scheduler "picks" only ready tasks, so we do need to test readiness of task
+        w.scheduled_ready_tasks.insert(tref.clone());
+
+        t.scheduled = Some(wref.clone());
+
+        debug!("Scheduler: {} -> {}", t.id, w.id());
+        for oref in &t.outputs {
+            w.scheduled_objects.insert(oref.clone());
+            oref.get_mut().scheduled.insert(wref.clone());
+
+            up_out
+                .objects
+                .entry(wref.clone())
+                .or_insert(Default::default())
+                .insert(oref.clone());
+        }
+    }
+    up_out.tasks.insert(tref.clone());
+}
+
+/// Ordering of ready tasks within a session (the greatest goes first):
+/// priority, score and the older task first
+type TaskKey = (i32, i64, Reverse<TaskId>);
+
+/// Computes the key of a ready task. The score does not depend on the chosen worker,
+/// it contains the locality bonus of the best worker for the task.
+/// Hence the key changes only when inputs are placed to other workers.
+fn task_key(t: &Task, n_workers: i64) -> TaskKey {
+    let mut total_size = 0;
+    for input in &t.inputs {
+        let o = input.object.get();
+        total_size += o.size.unwrap() * o.scheduled.len();
+    }
+    let neg_avg_size = -(total_size as i64) / n_workers;
+    let best_local = local_input_sizes(t).values().cloned().max().unwrap_or(0);
+    let score = neg_avg_size + t.resources.cpus() as i64 * 5000i64
+        + t.bottom_level as i64 * BOTTOM_LEVEL_WEIGHT + best_local;
+    (t.priority, score, Reverse(t.id))
+}
+
+/// Ready tasks of one session, bucketed by the number of requested cpus (per node)
+/// and nodes, so the best task that fits into free cpus is found without scanning
+/// all tasks
+#[derive(Default, Clone, Debug)]
+struct SessionQueue {
+    buckets: BTreeMap<(u32, u32), BTreeMap<TaskKey, TaskRef>>,
+}
+
+impl SessionQueue {
+    /// The best task that requests at most `max_cpus` cpus on one worker
+    /// and at most `max_total` cpus on all its nodes together
+    fn best(&self, max_cpus: u32, max_total: u32) -> Option<(&TaskKey, &TaskRef)> {
+        self.buckets
+            .range(..(max_cpus + 1, 0))
+            .filter(|&(&(cpus, nodes), _)| cpus * nodes <= max_total)
+            .filter_map(|(_, bucket)| bucket.iter().next_back())
+            .max_by_key(|&(key, _)| key)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+/// Scheduler that assigns ready tasks to workers with free cpus.
+///
+/// Ready tasks are kept in per-session queues ordered by precomputed keys, so
+/// placing one task costs O(sessions + workers + log(ready tasks)) instead of
+/// scanning all pairs of ready tasks and workers. Keys are recomputed only for
+/// tasks whose inputs were placed elsewhere or whose bottom level changed.
 #[derive(Default, Clone, Debug)]
 pub struct ReactiveScheduler {
-    ready_tasks: RcSet<TaskRef>,
+    /// Ready tasks and their current keys
+    ready_tasks: HashMap<TaskRef, TaskKey>,
+    /// Ready tasks ordered within sessions
+    queues: HashMap<SessionRef, SessionQueue>,
+    /// Ready members of gangs, by session and the gang name; a gang is scheduled
+    /// when all its members are ready
+    gangs: HashMap<(SessionRef, String), RcSet<TaskRef>>,
+    /// Durations of finished tasks
+    estimates: DurationEstimates,
 }
 
 impl ReactiveScheduler {
@@ -72,52 +421,247 @@ impl ReactiveScheduler {
     type SessionExtra = ();
     type ClientExtra = ();*/
 
-    fn pick_best(&self, graph: &mut Graph) -> Option<(TaskRef, WorkerRef)> {
-        let mut best_worker = None;
-        let mut best_score = 0;
-        let mut best_task = None;
-
-        let n_workers = graph.workers.len() as i64;
+    fn insert_ready(&mut self, tref: &TaskRef, n_workers: i64) {
+        let t = tref.get();
+        let key = task_key(&t, n_workers);
+        self.queues
+            .entry(t.session.clone())
+            .or_insert_with(Default::default)
+            .buckets
+            .entry((t.resources.cpus(), t.nodes))
+            .or_insert_with(Default::default)
+            .insert(key.clone(), tref.clone());
+        self.ready_tasks.insert(tref.clone(), key);
+    }
 
-        for tref in &self.ready_tasks {
-            let t = tref.get();
-            let mut total_size = 0;
-            for input in &t.inputs {
-                let o = input.object.get();
-                total_size += o.size.unwrap() * o.scheduled.len();
+    fn remove_ready(&mut self, tref: &TaskRef) -> bool {
+        let key = match self.ready_tasks.remove(tref) {
+            Some(key) => key,
+            None => return false,
+        };
+        let t = tref.get();
+        let empty_queue = {
+            let queue = self.queues.get_mut(&t.session).unwrap();
+            let size = (t.resources.cpus(), t.nodes);
+            let empty_bucket = {
+                let bucket = queue.buckets.get_mut(&size).unwrap();
+                bucket.remove(&key);
+                bucket.is_empty()
+            };
+            if empty_bucket {
+                queue.buckets.remove(&size);
             }
-            let neg_avg_size = -(total_size as i64) / n_workers;
-            //debug!("!!! {} AVG SIZE {}", t.id, -neg_avg_size);
+            queue.is_empty()
+        };
+        if empty_queue {
+            self.queues.remove(&t.session);
+        }
+        true
+    }
 
-            for (_, wref) in &graph.workers {
+    /// Returns the best pair (task, worker).
+    ///
+    /// Sessions are compared primarily by fair share: tasks of the client that uses
+    /// the least cpus relative to the client weight go first, then tasks of its session with the least cpus used
+    /// relative to the session weight. Ties are broken by the number of cpus
+    /// already served to the client/session. Within the session, the task with
+    /// the greatest key (priority, then score preferring tasks with long remaining
+    /// path (bottom level), more cpus and data locality) that fits into free cpus
+    /// of some worker is taken. Tasks of sessions over their cpu quota are skipped.
+    /// The worker is `None` when no worker with free cpus satisfies constraints of the task.
+    /// Workers reserved for gangs are not used.
+    fn pick_best(
+        &self,
+        graph: &Graph,
+        reserved: &RcSet<WorkerRef>,
+    ) -> Option<(TaskRef, Option<WorkerRef>)> {
+        let max_free = graph
+            .workers
+            .values()
+            .filter(|wref| !reserved.contains(*wref))
+            .map(|wref| {
                 let w = wref.get();
-                let cpus = t.resources.cpus();
-                if cpus + w.active_resources <= w.resources.cpus()
-                    && t.resources.is_subset_of(&w.resources)
-                {
-                    let mut score = neg_avg_size + cpus as i64 * 5000i64;
-                    for input in &t.inputs {
-                        let o = input.object.get();
-                        if o.scheduled.contains(wref) {
-                            score += o.size.unwrap() as i64;
-                        }
-                    }
-                    if best_score < score || best_worker.is_none() {
-                        best_score = score;
-                        best_worker = Some(wref.clone());
-                        best_task = Some(tref.clone());
-                    }
+                w.resources.cpus() - w.active_resources
+            })
+            .max()
+            .unwrap_or(0);
+        if max_free == 0 {
+            return None;
+        }
+
+        // (active cpus, served cpus) of each client relative to its weight, negated,
+        // so the least served has the highest score
+        let client_usage: HashMap<ClientRef, (f32, f32)> = graph
+            .clients
+            .values()
+            .map(|cref| {
+                let c = cref.get();
+                let mut usage = (0f32, 0f32);
+                for s in &c.sessions {
+                    let s = s.get();
+                    usage.0 -= s.active_cpus as f32 / c.weight;
+                    usage.1 -= s.served_cpus as f32 / c.weight;
+                }
+                (cref.clone(), usage)
+            })
+            .collect();
+
+        let mut best_score = None;
+        let mut best_task = None;
+
+        for (sref, queue) in &self.queues {
+            let s = sref.get();
+            // Multi-node tasks are charged for cpus on all their nodes
+            let total_limit = match s.cpu_quota {
+                Some(quota) => quota.saturating_sub(s.active_cpus),
+                None => u32::max_value(),
+            };
+            if let Some((key, tref)) = queue.best(max_free, total_limit) {
+                let score = (
+                    client_usage.get(&s.client).cloned().unwrap_or((0f32, 0f32)),
+                    (
+                        -(s.active_cpus as f32) / s.weight,
+                        -(s.served_cpus as f32) / s.weight,
+                    ),
+                    key,
+                );
+                if best_score.as_ref().map(|best| *best < score).unwrap_or(true) {
+                    best_score = Some(score);
+                    best_task = Some(tref);
                 }
             }
         }
-        if let Some(wref) = best_worker {
-            Some((best_task.unwrap(), wref))
-        } else {
-            None
+
+        let tref = best_task?.clone();
+        let wref = Self::pick_worker(graph, &tref.get(), reserved);
+        Some((tref, wref))
+    }
+
+    /// The worker with enough free cpus (satisfying constraints of the task) that holds
+    /// the most of input data of the task; ties are broken by the number of free cpus
+    fn pick_worker(graph: &Graph, t: &Task, reserved: &RcSet<WorkerRef>) -> Option<WorkerRef> {
+        let cpus = t.resources.cpus();
+        let local = local_input_sizes(t);
+        graph
+            .workers
+            .values()
+            .filter(|wref| !reserved.contains(*wref))
+            .filter(|wref| {
+                let w = wref.get();
+                cpus + w.active_resources <= w.resources.cpus()
+                    && t.resources.is_subset_of(&w.resources) && t.can_run_on(&w)
+            })
+            .max_by_key(|wref| {
+                let w = wref.get();
+                (
+                    local.get(wref).cloned().unwrap_or(0),
+                    w.resources.cpus() - w.active_resources,
+                )
+            })
+            .cloned()
+    }
+
+    /// Other workers for a multi-node task scheduled on `launcher`; workers with
+    /// the most free cpus are taken. Returns `None` when there is not enough
+    /// workers with free cpus.
+    fn pick_peers(
+        graph: &Graph,
+        t: &Task,
+        launcher: &WorkerRef,
+        reserved: &RcSet<WorkerRef>,
+    ) -> Option<Vec<WorkerRef>> {
+        let cpus = t.resources.cpus();
+        let mut candidates: Vec<(u32, WorkerRef)> = graph
+            .workers
+            .values()
+            .filter(|wref| *wref != launcher && !reserved.contains(*wref))
+            .filter_map(|wref| {
+                let w = wref.get();
+                let free = w.resources.cpus() - w.active_resources;
+                if free >= cpus && t.resources.is_subset_of(&w.resources) && t.can_run_on(&w) {
+                    Some((free, wref.clone()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let n_peers = t.nodes as usize - 1;
+        if candidates.len() < n_peers {
+            return None;
+        }
+        candidates.sort_by_key(|&(free, ref wref)| (Reverse(free), wref.get_id()));
+        Some(
+            candidates
+                .into_iter()
+                .take(n_peers)
+                .map(|(_, wref)| wref)
+                .collect(),
+        )
+    }
+
+    /// Estimates of task durations learned from finished tasks
+    pub fn estimates(&self) -> &DurationEstimates {
+        &self.estimates
+    }
+
+    /// Number of ready tasks waiting for a worker
+    pub fn ready_tasks_count(&self) -> usize {
+        self.ready_tasks.len()
+    }
+
+    /// Inform the scheduler that the task is finished
+    pub fn task_finished(&mut self, task: &Task) {
+        self.estimates.add_finished_task(task);
+    }
+
+    /// Schedules gangs whose members are all ready and fit into free cpus at once.
+    /// Returns workers reserved for gangs that do not fit yet (in the order of
+    /// priority); other tasks are not placed there, so gangs are not starved by
+    /// smaller tasks.
+    fn schedule_gangs(&mut self, graph: &Graph, up_out: &mut UpdatedOut) -> RcSet<WorkerRef> {
+        let mut complete: Vec<(i32, SessionId, String)> = self.gangs
+            .iter()
+            .filter(|&(&(ref sref, ref name), ready)| {
+                sref.get().gangs.get(name).map(|m| m.len()) == Some(ready.len())
+            })
+            .map(|(&(ref sref, ref name), ready)| {
+                let priority = ready.iter().map(|t| t.get().priority).max().unwrap_or(0);
+                (priority, sref.get_id(), name.clone())
+            })
+            .collect();
+        complete.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| (a.1, &a.2).cmp(&(b.1, &b.2))));
+
+        let mut reserved = RcSet::new();
+        for (_, session_id, name) in complete {
+            let sref = graph.sessions[&session_id].clone();
+            let key = (sref, name);
+            let members: Vec<TaskRef> = self.gangs[&key].iter().cloned().collect();
+            let cpus: u32 = members.iter().map(|t| t.get().resources.cpus()).sum();
+            let within_quota = {
+                let s = key.0.get();
+                s.cpu_quota
+                    .map(|quota| s.active_cpus + cpus <= quota)
+                    .unwrap_or(true)
+            };
+            if !within_quota {
+                continue;
+            }
+            if let Some(placement) = place_gang(graph, &members, true, &reserved) {
+                debug!("Scheduler: gang '{}' of session {} placed", key.1, session_id);
+                for (tref, wref) in placement {
+                    schedule_task(&tref, &wref, up_out);
+                }
+                self.gangs.remove(&key);
+            } else if let Some(placement) = place_gang(graph, &members, false, &reserved) {
+                reserved.extend(placement.into_iter().map(|(_, wref)| wref));
+            }
         }
+        reserved
     }
 
     pub fn clear_session(&mut self, session: &SessionRef) {
+        self.queues.remove(session);
+        self.gangs.retain(|&(ref sref, _), _| sref != session);
         let s = session.get();
         for tref in &s.tasks {
             self.ready_tasks.remove(&tref);
@@ -126,59 +670,72 @@ impl ReactiveScheduler {
 
     pub fn schedule(&mut self, graph: &mut Graph, updated: &UpdatedIn) -> UpdatedOut {
         let mut up_out: UpdatedOut = Default::default();
+        let n_workers = ::std::cmp::max(graph.workers.len(), 1) as i64;
 
-        if graph.workers.is_empty() {
-            return up_out;
+        // Keys of ready tasks depend on bottom levels and placement of inputs
+        let mut dirty = update_bottom_levels(&updated.new_tasks, &self.estimates);
+        for oref in updated.objects.keys() {
+            dirty.extend(oref.get().consumers.iter().cloned());
         }
-
-        for tref in &updated.new_tasks {
-            let mut t = tref.get_mut();
-            if t.state == TaskState::Ready {
-                debug!("Scheduler: New ready task {}", t.id);
-                let r = self.ready_tasks.insert(tref.clone());
-                assert!(r);
+        for tref in &dirty {
+            if self.remove_ready(tref) {
+                self.insert_ready(tref, n_workers);
             }
         }
 
-        for tref in &updated.tasks {
-            let mut t = tref.get_mut();
-            if t.state == TaskState::Ready {
+        for tref in updated.new_tasks.iter().chain(updated.tasks.iter()) {
+            let t = tref.get();
+            if t.state != TaskState::Ready || t.scheduled.is_some() {
+                continue;
+            }
+            if let Some(ref gang) = t.gang {
+                self.gangs
+                    .entry((t.session.clone(), gang.clone()))
+                    .or_insert_with(Default::default)
+                    .insert(tref.clone());
+            } else if !self.ready_tasks.contains_key(tref) {
                 debug!("Scheduler: New ready task {}", t.id);
-                let r = self.ready_tasks.insert(tref.clone());
-                assert!(r);
+                self.insert_ready(tref, n_workers);
             }
         }
 
-        debug!("Scheduler started");
-
-        while let Some((tref, wref)) = self.pick_best(graph) {
-            {
-                let mut w = wref.get_mut();
-                let mut t = tref.get_mut();
-
-                assert!(t.state == TaskState::Ready);
-                w.active_resources += t.resources.cpus();
-                w.scheduled_tasks.insert(tref.clone());
+        if graph.workers.is_empty() {
+            return up_out;
+        }
 
-                // Scheduler "picks" only ready tasks, so we do need to test readiness of task
-                w.scheduled_ready_tasks.insert(tref.clone());
+        debug!("Scheduler started");
 
-                t.scheduled = Some(wref.clone());
+        let reserved = self.schedule_gangs(graph, &mut up_out);
 
-                debug!("Scheduler: {} -> {}", t.id, w.id());
-                for oref in &t.outputs {
-                    w.scheduled_objects.insert(oref.clone());
-                    oref.get_mut().scheduled.insert(wref.clone());
+        // Tasks that cannot be placed now because of their constraints;
+        // they are put aside, so they do not block other tasks
+        let mut blocked = Vec::new();
 
-                    up_out
-                        .objects
-                        .entry(wref.clone())
-                        .or_insert(Default::default())
-                        .insert(oref.clone());
+        while let Some((tref, wref)) = self.pick_best(graph, &reserved) {
+            let placement = wref.and_then(|wref| {
+                if tref.get().nodes > 1 {
+                    let peers = Self::pick_peers(graph, &tref.get(), &wref, &reserved)?;
+                    Some((wref, peers))
+                } else {
+                    Some((wref, Vec::new()))
+                }
+            });
+            let (wref, peers) = match placement {
+                Some(placement) => placement,
+                None => {
+                    self.remove_ready(&tref);
+                    blocked.push(tref);
+                    continue;
                 }
+            };
+            schedule_task(&tref, &wref, &mut up_out);
+            if !peers.is_empty() {
+                tref.reserve_peers(peers);
             }
-            self.ready_tasks.remove(&tref);
-            up_out.tasks.insert(tref);
+            self.remove_ready(&tref);
+        }
+        for tref in blocked {
+            self.insert_ready(&tref, n_workers);
         }
         up_out
 
@@ -228,3 +785,373 @@ fn random_worker(g: &mut Graph, seed: usize) -> WorkerRef {
     ws[seed % ws.len()].clone()
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use common::attributes::{AttributeInfo, Attributes};
+    use common::id::{DataObjectId, SId, TaskId};
+    use common::resources::Resources;
+    use server::graph::{ClientRef, DataObjectRef, SessionRef, TaskInput, TaskRef, WorkerRef};
+
+    /// Synthetic graph with `n_tasks` independent ready tasks in each session;
+    /// each task has one finished input placed on a pseudo-random worker
+    fn create_graph(
+        n_workers: usize,
+        n_cpus: u32,
+        n_sessions: usize,
+        n_tasks: usize,
+    ) -> (Graph, UpdatedIn) {
+        let mut graph = Graph::new();
+        let mut updated = UpdatedIn::default();
+        let workers: Vec<WorkerRef> = (0..n_workers)
+            .map(|i| {
+                let address = format!("10.0.{}.{}:1", i / 250, i % 250 + 1);
+                let w = WorkerRef::new(
+                    address.parse().unwrap(),
+                    None,
+                    Resources { cpus: n_cpus },
+                    Default::default(),
+                    Default::default(),
+                );
+                graph.workers.insert(w.get_id(), w.clone());
+                w
+            })
+            .collect();
+        let client = ClientRef::new("10.1.0.1:1".parse().unwrap());
+        graph.clients.insert(client.get_id(), client.clone());
+        for si in 0..n_sessions {
+            let s = SessionRef::new(graph.new_session_id(), &client);
+            graph.sessions.insert(s.get_id(), s.clone());
+            for ti in 0..n_tasks {
+                let o = DataObjectRef::new(
+                    &s,
+                    DataObjectId::new(s.get_id(), 2 * ti as i32),
+                    false,
+                    "input".to_string(),
+                    Some(vec![0; 1 + ti % 100]),
+                    Attributes::new(),
+                );
+                let w = &workers[(ti * 7919 + si) % n_workers];
+                o.get_mut().scheduled.insert(w.clone());
+                w.get_mut().scheduled_objects.insert(o.clone());
+                let input = TaskInput {
+                    object: o,
+                    label: Default::default(),
+                    path: Default::default(),
+                };
+                let t = TaskRef::new(
+                    &s,
+                    TaskId::new(s.get_id(), 2 * ti as i32 + 1),
+                    vec![input],
+                    Vec::new(),
+                    "bench".to_string(),
+                    Attributes::new(),
+                    Resources {
+                        cpus: 1 + (ti % 3) as u32,
+                    },
+                ).unwrap();
+                graph.tasks.insert(t.get_id(), t.clone());
+                updated.new_tasks.insert(t);
+            }
+        }
+        (graph, updated)
+    }
+
+    /// Pretend that all scheduled tasks finished and free the cpus
+    fn free_all_cpus(graph: &mut Graph) {
+        for wref in graph.workers.values() {
+            let mut w = wref.get_mut();
+            w.active_resources = 0;
+            w.scheduled_tasks.clear();
+            w.scheduled_ready_tasks.clear();
+        }
+        for sref in graph.sessions.values() {
+            sref.get_mut().active_cpus = 0;
+        }
+    }
+
+    #[test]
+    fn test_schedule_fills_workers() {
+        let (mut graph, updated) = create_graph(4, 4, 2, 100);
+        let mut scheduler = ReactiveScheduler::default();
+        let out = scheduler.schedule(&mut graph, &updated);
+        assert!(!out.tasks.is_empty());
+        for wref in graph.workers.values() {
+            let w = wref.get();
+            assert_eq!(w.active_resources, w.resources.cpus());
+        }
+        for sref in graph.sessions.values() {
+            assert!(sref.get().served_cpus > 0);
+        }
+        assert_eq!(scheduler.ready_tasks.len() + out.tasks.len(), 200);
+    }
+
+    #[test]
+    fn test_schedule_prefers_local_worker() {
+        let (mut graph, updated) = create_graph(3, 8, 1, 3);
+        let mut scheduler = ReactiveScheduler::default();
+        scheduler.schedule(&mut graph, &updated);
+        for tref in graph.tasks.values() {
+            let t = tref.get();
+            let input = t.inputs[0].object.get();
+            assert!(input.scheduled.contains(t.scheduled.as_ref().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_bottom_levels_weighted_by_estimates() {
+        let (graph, _) = create_graph(1, 1, 1, 0);
+        let s = graph.sessions.values().next().unwrap().clone();
+        let new_task = |id: i32, task_type: &str, duration: Option<i64>| {
+            let mut attributes = Attributes::new();
+            if let Some(duration) = duration {
+                let info = AttributeInfo {
+                    worker: String::new(),
+                    start: String::new(),
+                    duration,
+                };
+                attributes.set("info", info).unwrap();
+            }
+            TaskRef::new(
+                &s,
+                TaskId::new(s.get_id(), id),
+                Vec::new(),
+                Vec::new(),
+                task_type.to_string(),
+                attributes,
+                Resources { cpus: 1 },
+            ).unwrap()
+        };
+        let mut estimates = DurationEstimates::default();
+        estimates.add_finished_task(&new_task(0, "slow", Some(5000)).get());
+
+        let slow = new_task(1, "slow", None);
+        let fast = new_task(2, "fast", None);
+        let new_tasks: RcSet<TaskRef> = vec![slow.clone(), fast.clone()].into_iter().collect();
+        update_bottom_levels(&new_tasks, &estimates);
+        assert_eq!(slow.get().bottom_level, 6);
+        assert_eq!(fast.get().bottom_level, 1);
+    }
+
+    #[test]
+    fn test_queue_multi_node_cost() {
+        let (graph, _) = create_graph(3, 4, 1, 0);
+        let s = graph.sessions.values().next().unwrap().clone();
+        let t = TaskRef::new(
+            &s,
+            TaskId::new(s.get_id(), 1),
+            Vec::new(),
+            Vec::new(),
+            "test".to_string(),
+            Attributes::new(),
+            Resources { cpus: 2 },
+        ).unwrap();
+        t.get_mut().nodes = 2;
+        let mut scheduler = ReactiveScheduler::default();
+        scheduler.insert_ready(&t, 3);
+        let queue = &scheduler.queues[&s];
+        assert!(queue.best(4, 4).is_some());
+        // Two cpus fit into one worker, but four cpus are charged to the quota
+        assert!(queue.best(4, 3).is_none());
+        assert!(queue.best(1, 4).is_none());
+    }
+
+    #[test]
+    fn test_schedule_constraints() {
+        let (mut graph, mut updated) = create_graph(3, 8, 1, 0);
+        let gpu_worker = graph.workers.values().next().unwrap().clone();
+        gpu_worker.get_mut().labels.insert("gpu".to_string());
+        let s = graph.sessions.values().next().unwrap().clone();
+        let (gpu_tasks, group) = {
+            let mut add_task = |id: i32, constraints: &str| {
+                let mut attributes = Attributes::new();
+                let constraints: ::serde_json::Value = ::serde_json::from_str(constraints).unwrap();
+                attributes.set("constraints", constraints).unwrap();
+                let t = TaskRef::new(
+                    &s,
+                    TaskId::new(s.get_id(), id),
+                    Vec::new(),
+                    Vec::new(),
+                    "test".to_string(),
+                    attributes,
+                    Resources { cpus: 1 },
+                ).unwrap();
+                graph.tasks.insert(t.get_id(), t.clone());
+                updated.new_tasks.insert(t.clone());
+                t
+            };
+            let gpu_tasks: Vec<_> = (0..2).map(|i| add_task(i, r#"{"labels": ["gpu"]}"#)).collect();
+            let group: Vec<_> = (10..14)
+                .map(|i| add_task(i, r#"{"anti_affinity": "g"}"#))
+                .collect();
+            (gpu_tasks, group)
+        };
+
+        let mut scheduler = ReactiveScheduler::default();
+        scheduler.schedule(&mut graph, &updated);
+        for tref in &gpu_tasks {
+            assert_eq!(tref.get().scheduled.as_ref(), Some(&gpu_worker));
+        }
+        let mut used: Vec<WorkerRef> = group
+            .iter()
+            .filter_map(|tref| tref.get().scheduled.clone())
+            .collect();
+        assert_eq!(used.len(), 3);
+        used.sort_by_key(|w| w.get_id());
+        used.dedup();
+        assert_eq!(used.len(), 3);
+        // The fourth task of the group waits for a free worker
+        assert_eq!(scheduler.ready_tasks.len(), 1);
+    }
+
+    #[test]
+    fn test_schedule_client_weights() {
+        let (mut graph, mut updated) = create_graph(1, 4, 0, 0);
+        let heavy = graph.clients.values().next().unwrap().clone();
+        heavy.get_mut().weight = 3f32;
+        let light = ClientRef::new("10.1.0.2:1".parse().unwrap());
+        graph.clients.insert(light.get_id(), light.clone());
+        let (heavy_session, light_session) = {
+            let mut add_tasks = |graph: &mut Graph, client: &ClientRef| {
+                let s = SessionRef::new(graph.new_session_id(), client);
+                graph.sessions.insert(s.get_id(), s.clone());
+                for i in 0..4 {
+                    let t = TaskRef::new(
+                        &s,
+                        TaskId::new(s.get_id(), i),
+                        Vec::new(),
+                        Vec::new(),
+                        "test".to_string(),
+                        Attributes::new(),
+                        Resources { cpus: 1 },
+                    ).unwrap();
+                    graph.tasks.insert(t.get_id(), t.clone());
+                    updated.new_tasks.insert(t);
+                }
+                s
+            };
+            (add_tasks(&mut graph, &heavy), add_tasks(&mut graph, &light))
+        };
+
+        let mut scheduler = ReactiveScheduler::default();
+        let out = scheduler.schedule(&mut graph, &updated);
+        assert_eq!(out.tasks.len(), 4);
+        // Four cpus are shared 3:1 by the client weights
+        assert_eq!(heavy_session.get().active_cpus, 3);
+        assert_eq!(light_session.get().active_cpus, 1);
+    }
+
+    #[test]
+    fn test_schedule_gang() {
+        let (mut graph, _) = create_graph(2, 2, 1, 0);
+        let s = graph.sessions.values().next().unwrap().clone();
+        let add_task = |graph: &mut Graph, id: i32, cpus: u32, gang: Option<&str>| {
+            let mut attributes = Attributes::new();
+            if let Some(gang) = gang {
+                attributes.set("gang", gang).unwrap();
+            }
+            let t = TaskRef::new(
+                &s,
+                TaskId::new(s.get_id(), id),
+                Vec::new(),
+                Vec::new(),
+                "test".to_string(),
+                attributes,
+                Resources { cpus: cpus },
+            ).unwrap();
+            graph.tasks.insert(t.get_id(), t.clone());
+            t
+        };
+        let mut scheduler = ReactiveScheduler::default();
+
+        let mut updated = UpdatedIn::default();
+        updated.new_tasks.insert(add_task(&mut graph, 1, 1, None));
+        scheduler.schedule(&mut graph, &updated);
+
+        // The gang does not fit, the workers are reserved for it
+        let mut updated = UpdatedIn::default();
+        let gang: Vec<_> = (10..12)
+            .map(|i| add_task(&mut graph, i, 2, Some("g")))
+            .collect();
+        updated.new_tasks.extend(gang.iter().cloned());
+        let other = add_task(&mut graph, 2, 1, None);
+        updated.new_tasks.insert(other.clone());
+        let out = scheduler.schedule(&mut graph, &updated);
+        assert!(out.tasks.is_empty());
+
+        free_all_cpus(&mut graph);
+        let out = scheduler.schedule(&mut graph, &UpdatedIn::default());
+        assert_eq!(out.tasks.len(), 2);
+        let w0 = gang[0].get().scheduled.clone().unwrap();
+        let w1 = gang[1].get().scheduled.clone().unwrap();
+        assert!(w0 != w1);
+        assert!(other.get().scheduled.is_none());
+    }
+
+    #[test]
+    fn test_schedule_multinode() {
+        let (mut graph, mut updated) = create_graph(3, 4, 1, 0);
+        let s = graph.sessions.values().next().unwrap().clone();
+        let mut attributes = Attributes::new();
+        attributes.set("nodes", 2).unwrap();
+        let tref = TaskRef::new(
+            &s,
+            TaskId::new(s.get_id(), 1),
+            Vec::new(),
+            Vec::new(),
+            "test".to_string(),
+            attributes,
+            Resources { cpus: 4 },
+        ).unwrap();
+        graph.tasks.insert(tref.get_id(), tref.clone());
+        updated.new_tasks.insert(tref.clone());
+
+        let mut scheduler = ReactiveScheduler::default();
+        scheduler.schedule(&mut graph, &updated);
+        let launcher = tref.get().scheduled.clone().unwrap();
+        let peers = tref.get().peers.clone();
+        assert_eq!(peers.len(), 1);
+        assert!(peers[0] != launcher);
+        assert_eq!(peers[0].get().active_resources, 4);
+        assert_eq!(s.get().active_cpus, 8);
+
+        tref.release_peers();
+        assert_eq!(peers[0].get().active_resources, 0);
+        assert!(peers[0].get().peer_tasks.is_empty());
+    }
+
+    /// Benchmark of scheduling on synthetic graphs, run by
+    /// `cargo test --release bench_schedule -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_schedule() {
+        for &(n_workers, n_sessions, n_tasks) in &[
+            (10, 1, 1000),
+            (100, 4, 10000),
+            (300, 10, 10000),
+            (300, 1, 100000),
+        ] {
+            let (mut graph, updated) = create_graph(n_workers, 8, n_sessions, n_tasks);
+            let mut scheduler = ReactiveScheduler::default();
+            let start = Instant::now();
+            let out = scheduler.schedule(&mut graph, &updated);
+            let first = start.elapsed();
+            free_all_cpus(&mut graph);
+            let start = Instant::now();
+            let out2 = scheduler.schedule(&mut graph, &UpdatedIn::default());
+            let second = start.elapsed();
+            println!(
+                "workers={} sessions={} ready={}: {} tasks in {:?}, next {} tasks in {:?}",
+                n_workers,
+                n_sessions,
+                n_sessions * n_tasks,
+                out.tasks.len(),
+                first,
+                out2.tasks.len(),
+                second
+            );
+        }
+    }
+}
@@ -0,0 +1,709 @@
+//! Simulation of a cluster for evaluation of schedulers.
+//!
+//! The real server state and scheduler run against virtual workers that do not
+//! execute anything; tasks take the time given by the simulated graph (or their
+//! attributes) and data transfers take the time given by bandwidth of workers.
+//! Time is virtual, so the simulation runs as fast as the server is able to
+//! process the events.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+
+use capnp::capability::Promise;
+use chrono::TimeZone;
+use tokio_core::reactor::Core;
+
+use common::{Attributes, ConsistencyCheck};
+use common::attributes::AttributeInfo;
+use common::convert::FromCapnp;
+use common::id::{DataObjectId, TaskId, WorkerId};
+use common::logging::null_logger::NullLogger;
+use common::resources::Resources;
+use common::wrapped::WrappedRcRefCell;
+use errors::Result;
+use server::graph::{DataObjectState, TaskInput, TaskState};
+use server::state::StateRef;
+use worker_capnp::worker_control;
+
+/// Virtual workers of a simulated cluster
+#[derive(Deserialize, Debug)]
+pub struct SimWorkerSpec {
+    /// Number of workers with this specification
+    #[serde(default = "default_count")]
+    pub count: usize,
+    pub cpus: u32,
+    /// Incoming bandwidth in bytes per second
+    pub bandwidth: f64,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+fn default_duration() -> f64 {
+    1000f64
+}
+
+/// Simulated cluster
+#[derive(Deserialize, Debug)]
+pub struct SimClusterSpec {
+    pub workers: Vec<SimWorkerSpec>,
+    /// Duration of tasks (in milliseconds) without known duration
+    #[serde(default = "default_duration")]
+    pub default_duration: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SimObjectSpec {
+    pub id: i32,
+    /// Size in bytes
+    pub size: usize,
+    /// Object is uploaded by client (otherwise it is produced by a task)
+    #[serde(default)]
+    pub data: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SimTaskSpec {
+    pub id: i32,
+    #[serde(default = "default_task_type")]
+    pub task_type: String,
+    #[serde(default)]
+    pub inputs: Vec<i32>,
+    #[serde(default)]
+    pub outputs: Vec<i32>,
+    #[serde(default = "default_cpus")]
+    pub cpus: u32,
+    /// Duration in milliseconds; if not given, "info" attribute (e.g. from a trace)
+    /// or default duration of the cluster is used
+    pub duration: Option<f64>,
+    #[serde(default)]
+    pub attributes: HashMap<String, ::serde_json::Value>,
+}
+
+fn default_task_type() -> String {
+    "sim".to_string()
+}
+
+fn default_cpus() -> u32 {
+    1
+}
+
+/// Simulated task graph
+#[derive(Deserialize, Debug)]
+pub struct SimGraphSpec {
+    pub objects: Vec<SimObjectSpec>,
+    pub tasks: Vec<SimTaskSpec>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SimWorkerReport {
+    pub id: String,
+    pub cpus: u32,
+    /// Sum of durations of tasks multiplied by their cpus (ms)
+    pub busy: f64,
+    /// busy / (cpus * makespan)
+    pub utilization: f64,
+    /// Bytes transferred to the worker
+    pub transferred: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SimReport {
+    /// Time when the last task finished (ms)
+    pub makespan: f64,
+    /// Total number of bytes transferred between workers (and from the server)
+    pub transferred: u64,
+    pub tasks: usize,
+    pub workers: Vec<SimWorkerReport>,
+}
+
+enum SimEvent {
+    /// Inputs of the task are fetched and the task starts
+    TaskStarted {
+        worker: WorkerId,
+        task: TaskId,
+        generation: u64,
+    },
+    TaskFinished {
+        worker: WorkerId,
+        task: TaskId,
+        generation: u64,
+        start: u64,
+        cpus: u32,
+        outputs: Vec<(DataObjectId, usize)>,
+    },
+    ObjectFetched {
+        worker: WorkerId,
+        object: DataObjectId,
+        size: usize,
+    },
+}
+
+struct SimWorkerState {
+    cpus: u32,
+    bandwidth: f64,
+    /// Time (us) when the incoming link is free
+    link_free: u64,
+    /// Objects already present on the worker
+    objects: HashSet<DataObjectId>,
+    busy: f64,
+    transferred: u64,
+}
+
+pub struct Simulator {
+    /// Virtual time in microseconds
+    now: u64,
+    /// Events ordered by (time, sequence number)
+    events: BinaryHeap<Reverse<(u64, u64)>>,
+    event_data: HashMap<u64, SimEvent>,
+    event_counter: u64,
+    /// Current generation of assignment of running tasks; stopped tasks are removed
+    running: HashMap<TaskId, u64>,
+    workers: HashMap<WorkerId, SimWorkerState>,
+    durations: HashMap<TaskId, f64>,
+    sizes: HashMap<DataObjectId, usize>,
+    default_duration: f64,
+    /// Number of processed calls from the server; used to detect quiescence
+    calls: u64,
+}
+
+pub type SimulatorRef = WrappedRcRefCell<Simulator>;
+
+impl Simulator {
+    fn push_event(&mut self, time: u64, event: SimEvent) {
+        self.event_counter += 1;
+        self.events.push(Reverse((time, self.event_counter)));
+        self.event_data.insert(self.event_counter, event);
+    }
+
+    fn pop_event(&mut self) -> Option<SimEvent> {
+        self.events.pop().map(|Reverse((time, id))| {
+            self.now = time;
+            self.event_data.remove(&id).unwrap()
+        })
+    }
+
+    /// Transfer of `size` bytes to the worker; returns the time of arrival
+    fn transfer(&mut self, worker_id: &WorkerId, size: usize) -> u64 {
+        let now = self.now;
+        let w = self.workers.get_mut(worker_id).unwrap();
+        if size == 0 {
+            return now;
+        }
+        let duration = (size as f64 / w.bandwidth * 1_000_000f64) as u64;
+        w.link_free = w.link_free.max(now) + duration;
+        w.transferred += size as u64;
+        w.link_free
+    }
+
+    fn task_duration(&self, task_id: &TaskId, attributes: &Attributes) -> f64 {
+        if let Some(duration) = self.durations.get(task_id) {
+            return *duration;
+        }
+        match attributes.find::<AttributeInfo>("info") {
+            Ok(Some(info)) => info.duration as f64,
+            _ => self.default_duration,
+        }
+    }
+}
+
+/// Virtual worker; implements the control interface that the server uses for workers
+struct SimWorkerControl {
+    simulator: SimulatorRef,
+    worker_id: WorkerId,
+}
+
+impl worker_control::Server for SimWorkerControl {
+    fn get_worker_resources(
+        &mut self,
+        _params: worker_control::GetWorkerResourcesParams,
+        mut results: worker_control::GetWorkerResourcesResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let simulator = self.simulator.get();
+        results
+            .get()
+            .set_n_cpus(simulator.workers[&self.worker_id].cpus);
+        Promise::ok(())
+    }
+
+    fn unassign_objects(
+        &mut self,
+        params: worker_control::UnassignObjectsParams,
+        _results: worker_control::UnassignObjectsResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let mut simulator = self.simulator.get_mut();
+        simulator.calls += 1;
+        let w = simulator.workers.get_mut(&self.worker_id).unwrap();
+        for cid in pry!(params.get_objects()).iter() {
+            w.objects.remove(&DataObjectId::from_capnp(&cid));
+        }
+        Promise::ok(())
+    }
+
+    fn stop_tasks(
+        &mut self,
+        params: worker_control::StopTasksParams,
+        _results: worker_control::StopTasksResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let mut simulator = self.simulator.get_mut();
+        simulator.calls += 1;
+        for tid in pry!(params.get_tasks()).iter() {
+            simulator.running.remove(&TaskId::from_capnp(&tid));
+        }
+        Promise::ok(())
+    }
+
+    fn add_nodes(
+        &mut self,
+        params: worker_control::AddNodesParams,
+        _results: worker_control::AddNodesResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let mut simulator = self.simulator.get_mut();
+        simulator.calls += 1;
+
+        // Objects that has to be transferred to the worker
+        let mut remote = HashMap::new();
+        for co in pry!(params.get_new_objects()).iter() {
+            let id = DataObjectId::from_capnp(&pry!(co.get_id()));
+            let placement = WorkerId::from_capnp(&pry!(co.get_placement()));
+            if placement == self.worker_id
+                || simulator.workers[&self.worker_id].objects.contains(&id)
+            {
+                continue;
+            }
+            let size = if co.get_size() < 0 {
+                0
+            } else {
+                co.get_size() as usize
+            };
+            if co.get_assigned() && pry!(co.get_state()) == ::common_capnp::DataObjectState::Finished {
+                // Replica of a finished object
+                let time = simulator.transfer(&self.worker_id, size);
+                simulator.push_event(
+                    time,
+                    SimEvent::ObjectFetched {
+                        worker: self.worker_id,
+                        object: id,
+                        size,
+                    },
+                );
+            } else {
+                remote.insert(id, size);
+            }
+        }
+
+        for ct in pry!(params.get_new_tasks()).iter() {
+            let id = TaskId::from_capnp(&pry!(ct.get_id()));
+            let attributes = Attributes::from_capnp(&pry!(ct.get_attributes()));
+            let resources: Resources = pry!(attributes.get("resources"));
+
+            // Inputs are fetched before the task starts
+            let mut start = simulator.now;
+            for ci in pry!(ct.get_inputs()).iter() {
+                let input_id = DataObjectId::from_capnp(&pry!(ci.get_id()));
+                if let Some(size) = remote.remove(&input_id) {
+                    start = start.max(simulator.transfer(&self.worker_id, size));
+                    simulator
+                        .workers
+                        .get_mut(&self.worker_id)
+                        .unwrap()
+                        .objects
+                        .insert(input_id);
+                }
+            }
+
+            let duration = simulator.task_duration(&id, &attributes);
+
+            let outputs = pry!(ct.get_outputs())
+                .iter()
+                .map(|co| {
+                    let oid = DataObjectId::from_capnp(&co);
+                    (oid, simulator.sizes.get(&oid).cloned().unwrap_or(0))
+                })
+                .collect();
+
+            simulator.event_counter += 1;
+            let generation = simulator.event_counter;
+            simulator.running.insert(id, generation);
+            simulator.push_event(
+                start,
+                SimEvent::TaskStarted {
+                    worker: self.worker_id,
+                    task: id,
+                    generation,
+                },
+            );
+            let finish = start + (duration * 1000f64) as u64;
+            simulator.push_event(
+                finish,
+                SimEvent::TaskFinished {
+                    worker: self.worker_id,
+                    task: id,
+                    generation,
+                    start,
+                    cpus: resources.cpus(),
+                    outputs,
+                },
+            );
+        }
+        Promise::ok(())
+    }
+
+    fn get_info(
+        &mut self,
+        _params: worker_control::GetInfoParams,
+        _results: worker_control::GetInfoResults,
+    ) -> Promise<(), ::capnp::Error> {
+        Promise::err(::capnp::Error::unimplemented(
+            "get_info: not supported by simulated worker".to_string(),
+        ))
+    }
+
+    fn get_data_store(
+        &mut self,
+        _params: worker_control::GetDataStoreParams,
+        _results: worker_control::GetDataStoreResults,
+    ) -> Promise<(), ::capnp::Error> {
+        Promise::err(::capnp::Error::unimplemented(
+            "get_data_store: not supported by simulated worker".to_string(),
+        ))
+    }
+}
+
+/// Process messages between the server and virtual workers until nothing changes
+fn settle(core: &mut Core, state: &StateRef, simulator: &SimulatorRef) {
+    let mut idle_rounds = 0;
+    while idle_rounds < 3 {
+        let calls = simulator.get().calls;
+        core.turn(Some(Duration::from_millis(0)));
+        state.turn();
+        if simulator.get().calls == calls && state.get().updates.is_empty() {
+            idle_rounds += 1;
+        } else {
+            idle_rounds = 0;
+        }
+    }
+}
+
+/// Virtual time (us) as a timestamp
+fn virtual_timestamp(time: u64) -> String {
+    (::chrono::Utc.timestamp(0, 0) + ::chrono::Duration::microseconds(time as i64)).to_rfc3339()
+}
+
+fn apply_event(state: &StateRef, simulator: &SimulatorRef, event: SimEvent) -> Result<()> {
+    let (worker_id, obj_updates, task_updates) = match event {
+        SimEvent::TaskStarted {
+            worker,
+            task,
+            generation,
+        } => {
+            if simulator.get().running.get(&task) != Some(&generation) {
+                // The task was stopped in the meantime
+                return Ok(());
+            }
+            let tref = state.get().task_by_id(task)?;
+            let attributes = tref.get().attributes.clone();
+            (
+                worker,
+                Vec::new(),
+                vec![(tref, TaskState::Running, attributes)],
+            )
+        }
+        SimEvent::TaskFinished {
+            worker,
+            task,
+            generation,
+            start,
+            cpus,
+            outputs,
+        } => {
+            {
+                let mut sim = simulator.get_mut();
+                if sim.running.get(&task) != Some(&generation) {
+                    // The task was stopped in the meantime
+                    return Ok(());
+                }
+                sim.running.remove(&task);
+                let busy = (sim.now - start) as f64 / 1000f64 * cpus as f64;
+                let w = sim.workers.get_mut(&worker).unwrap();
+                w.busy += busy;
+                for &(id, _) in &outputs {
+                    w.objects.insert(id);
+                }
+            }
+            let s = state.get();
+            let mut attributes = Attributes::new();
+            attributes.set(
+                "info",
+                AttributeInfo {
+                    worker: worker.to_string(),
+                    start: virtual_timestamp(start),
+                    duration: ((simulator.get().now - start) / 1000) as i64,
+                },
+            )?;
+            let task_updates = vec![(s.task_by_id(task)?, TaskState::Finished, attributes)];
+            let mut obj_updates = Vec::new();
+            for (id, size) in outputs {
+                obj_updates.push((
+                    s.object_by_id(id)?,
+                    DataObjectState::Finished,
+                    size,
+                    Attributes::new(),
+                ));
+            }
+            (worker, obj_updates, task_updates)
+        }
+        SimEvent::ObjectFetched {
+            worker,
+            object,
+            size,
+        } => {
+            simulator
+                .get_mut()
+                .workers
+                .get_mut(&worker)
+                .unwrap()
+                .objects
+                .insert(object);
+            let oref = match state.get().object_by_id(object) {
+                Ok(oref) => oref,
+                // Object was already removed
+                Err(_) => return Ok(()),
+            };
+            (
+                worker,
+                vec![(oref, DataObjectState::Finished, size, Attributes::new())],
+                Vec::new(),
+            )
+        }
+    };
+    let wref = state.get().graph.workers[&worker_id].clone();
+    state
+        .get_mut()
+        .updates_from_worker(&wref, obj_updates, task_updates);
+    Ok(())
+}
+
+/// Run the simulation of the graph on the cluster and report makespan and transfers
+pub fn run_simulation(cluster: &SimClusterSpec, graph: &SimGraphSpec) -> Result<SimReport> {
+    let mut core = Core::new()?;
+    let unused_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 0);
+    let state = StateRef::new(
+        core.handle(),
+        unused_address,
+        unused_address,
+        Box::new(NullLogger::new()),
+        false,
+        false,
+    );
+
+    let simulator = SimulatorRef::wrap(Simulator {
+        now: 0,
+        events: BinaryHeap::new(),
+        event_data: HashMap::new(),
+        event_counter: 0,
+        running: HashMap::new(),
+        workers: HashMap::new(),
+        durations: HashMap::new(),
+        sizes: HashMap::new(),
+        default_duration: cluster.default_duration,
+        calls: 0,
+    });
+
+    let mut worker_ids = Vec::new();
+    for spec in &cluster.workers {
+        for _ in 0..spec.count {
+            let i = worker_ids.len();
+            let worker_id = SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::new(10, (i / 65536) as u8, (i / 256 % 256) as u8, (i % 256) as u8)),
+                1,
+            );
+            simulator.get_mut().workers.insert(
+                worker_id,
+                SimWorkerState {
+                    cpus: spec.cpus,
+                    bandwidth: spec.bandwidth,
+                    link_free: 0,
+                    objects: HashSet::new(),
+                    busy: 0f64,
+                    transferred: 0,
+                },
+            );
+            let control = worker_control::ToClient::new(SimWorkerControl {
+                simulator: simulator.clone(),
+                worker_id,
+            }).from_server::<::capnp_rpc::Server>();
+            state.get_mut().add_worker(
+                worker_id,
+                Some(control),
+                Resources { cpus: spec.cpus },
+                spec.labels.iter().cloned().collect(),
+                worker_id.ip().to_string(),
+            )?;
+            worker_ids.push(worker_id);
+        }
+    }
+
+    let session = {
+        let mut s = state.get_mut();
+        let client = s.add_client(unused_address, 1f32)?;
+        s.add_session(&client, 1f32, None)?
+    };
+    let session_id = session.get_id();
+
+    {
+        let mut s = state.get_mut();
+        let mut objects = Vec::new();
+        for spec in &graph.objects {
+            let id = DataObjectId::new(session_id, spec.id);
+            let data = if spec.data { Some(Vec::new()) } else { None };
+            let oref = s.add_object(&session, id, false, String::new(), data, Attributes::new())?;
+            // Data are not really uploaded, only the size is faked
+            oref.get_mut().size = Some(spec.size);
+            simulator.get_mut().sizes.insert(id, spec.size);
+            objects.push(oref);
+        }
+        let mut tasks = Vec::new();
+        for spec in &graph.tasks {
+            let id = TaskId::new(session_id, spec.id);
+            let mut attributes = Attributes::new();
+            for (key, value) in &spec.attributes {
+                attributes.set(key, value)?;
+            }
+            let resources = Resources { cpus: spec.cpus };
+            attributes.set("resources", &resources)?;
+            let inputs = spec.inputs
+                .iter()
+                .map(|i| {
+                    Ok(TaskInput {
+                        object: s.object_by_id(DataObjectId::new(session_id, *i))?,
+                        label: String::new(),
+                        path: String::new(),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let outputs = spec.outputs
+                .iter()
+                .map(|i| s.object_by_id(DataObjectId::new(session_id, *i)))
+                .collect::<Result<Vec<_>>>()?;
+            if let Some(duration) = spec.duration {
+                simulator.get_mut().durations.insert(id, duration);
+            }
+            tasks.push(s.add_task(
+                &session,
+                id,
+                inputs,
+                outputs,
+                spec.task_type.clone(),
+                attributes,
+                resources,
+            )?);
+        }
+        s.verify_submit(&tasks, &objects)?;
+    }
+
+    loop {
+        settle(&mut core, &state, &simulator);
+        if session.get().unfinished_tasks == 0 {
+            break;
+        }
+        if let Some(error) = session.get().get_error().as_ref() {
+            bail!("Simulated session failed: {}", error);
+        }
+        let event = simulator.get_mut().pop_event();
+        match event {
+            Some(event) => apply_event(&state, &simulator, event)?,
+            None => bail!("Simulation stalled: no running tasks, but session is not finished"),
+        }
+    }
+    state.get().check_consistency_opt().unwrap(); // unrecoverable
+
+    let sim = simulator.get();
+    let makespan = sim.now as f64 / 1000f64;
+    let workers: Vec<_> = worker_ids
+        .iter()
+        .map(|id| {
+            let w = &sim.workers[id];
+            SimWorkerReport {
+                id: id.to_string(),
+                cpus: w.cpus,
+                busy: w.busy,
+                utilization: if makespan > 0f64 {
+                    w.busy / (w.cpus as f64 * makespan)
+                } else {
+                    0f64
+                },
+                transferred: w.transferred,
+            }
+        })
+        .collect();
+    Ok(SimReport {
+        makespan,
+        transferred: workers.iter().map(|w| w.transferred).sum(),
+        tasks: graph.tasks.len(),
+        workers,
+    })
+}
+
+/// Load JSON specification from a file
+pub fn load_spec<T>(path: &Path) -> Result<T>
+where
+    T: ::serde::de::DeserializeOwned,
+{
+    let file = ::std::fs::File::open(path)?;
+    Ok(::serde_json::from_reader(file)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_simulate_chain() {
+        let cluster: SimClusterSpec = ::serde_json::from_str(
+            r#"{"workers": [{"cpus": 1, "bandwidth": 1000, "count": 2}]}"#,
+        ).unwrap();
+        let graph: SimGraphSpec = ::serde_json::from_str(
+            r#"{"objects": [{"id": 1, "size": 500, "data": true},
+                            {"id": 2, "size": 2000}, {"id": 3, "size": 10}],
+                "tasks": [{"id": 10, "inputs": [1], "outputs": [2], "duration": 100},
+                          {"id": 11, "inputs": [2], "outputs": [3], "duration": 200}]}"#,
+        ).unwrap();
+        let report = run_simulation(&cluster, &graph).unwrap();
+        assert_eq!(report.tasks, 2);
+        // Input is uploaded (0.5s), the second task runs where its input is
+        assert!((report.makespan - 800f64).abs() < 1f64);
+        assert_eq!(report.transferred, 500);
+    }
+
+    #[test]
+    fn test_simulate_steal() {
+        let cluster: SimClusterSpec = ::serde_json::from_str(
+            r#"{"workers": [{"cpus": 1, "bandwidth": 1000000, "count": 2}]}"#,
+        ).unwrap();
+        // When 10 produces its outputs, 12 takes its worker (priority) and 13 is
+        // sent to the other worker once 11 finishes. It waits there for a 10 s
+        // transfer of its input until the worker of 10 is idle again and takes it.
+        let graph: SimGraphSpec = ::serde_json::from_str(
+            r#"{"objects": [{"id": 1, "size": 10000000}, {"id": 2, "size": 10},
+                            {"id": 3, "size": 10}],
+                "tasks": [{"id": 10, "outputs": [1, 2], "duration": 100},
+                          {"id": 11, "duration": 1000},
+                          {"id": 12, "inputs": [2], "duration": 3000,
+                           "attributes": {"priority": 5}},
+                          {"id": 13, "inputs": [1], "outputs": [3], "duration": 100}]}"#,
+        ).unwrap();
+        let report = run_simulation(&cluster, &graph).unwrap();
+        assert_eq!(report.tasks, 4);
+        assert!((report.makespan - 3200f64).abs() < 1f64);
+    }
+}
@@ -1,7 +1,6 @@
 use std::net::SocketAddr;
-use std::path::PathBuf;
 use std::time::Duration;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use futures::{Future, Stream};
 use tokio_core::reactor::Handle;
@@ -15,7 +14,8 @@ use common::rpc::new_rpc_system;
 use server::graph::{ClientRef, DataObjectRef, DataObjectState, Graph, SessionError, SessionRef,
                     TaskInput, TaskRef, TaskState, WorkerRef};
 use server::rpc::ServerBootstrapImpl;
-use server::scheduler::{ReactiveScheduler, UpdatedIn};
+use server::scheduler::{place_gang, steal_tasks, ReactiveScheduler, UpdatedIn};
+use server::estimates::DurationEstimates;
 use common::convert::ToCapnp;
 use common::wrapped::WrappedRcRefCell;
 use common::resources::Resources;
@@ -26,7 +26,6 @@ use server::http::RequestHandler;
 use server::testmode;
 
 use common::logging::logger::Logger;
-use common::logging::sqlite_logger::SQLiteLogger;
 
 const LOGGING_INTERVAL: u64 = 1; // Logging interval in seconds
 
@@ -56,6 +55,9 @@ pub struct State {
     // If testing_mode is true, then __test attributes are interpreted
     test_mode: bool,
 
+    /// Data of client objects are stored in logged `ClientSubmit` events
+    log_client_data: bool,
+
     self_ref: Option<StateRef>,
 
     pub logger: Box<Logger>,
@@ -67,6 +69,29 @@ pub struct State {
 
     /// Listening port for HTTP interface
     http_listen_address: SocketAddr,
+
+    /// Counters exported by the `/metrics` HTTP endpoint
+    pub(super) stats: ServerStats,
+}
+
+/// Cumulative counters of the server
+#[derive(Default, Debug)]
+pub struct ServerStats {
+    /// Number of scheduler runs
+    pub scheduler_runs: u64,
+    /// Total time spent in the scheduler in seconds
+    pub scheduler_seconds: f64,
+    /// Objects sent to workers, either uploaded to the server or fetched from other workers
+    pub transferred_objects: u64,
+    /// Total size of transferred objects in bytes (objects of unknown size are not counted)
+    pub transferred_bytes: u64,
+}
+
+impl ServerStats {
+    fn object_transferred(&mut self, size: Option<usize>) {
+        self.transferred_objects += 1;
+        self.transferred_bytes += size.unwrap_or(0) as u64;
+    }
 }
 
 impl State {
@@ -76,15 +101,17 @@ impl State {
         address: SocketAddr,
         control: Option<::worker_capnp::worker_control::Client>,
         resources: Resources,
+        labels: HashSet<String>,
+        hostname: String,
     ) -> Result<WorkerRef> {
         debug!("New worker {}", address);
         if self.graph.workers.contains_key(&address) {
             bail!("State already contains worker {}", address);
         }
-        let w = WorkerRef::new(address, control, resources);
+        self.logger.add_new_worker_event(address, resources.clone());
+        let w = WorkerRef::new(address, control, resources, labels, hostname);
         self.graph.workers.insert(w.get_id(), w.clone());
         self.underload_workers.insert(w.clone());
-        self.logger.add_new_worker_event(w.get_id());
         Ok(w)
     }
 
@@ -128,12 +155,16 @@ impl State {
     }
 
     /// Add new client, register it in the graph
-    pub fn add_client(&mut self, address: SocketAddr) -> Result<ClientRef> {
+    pub fn add_client(&mut self, address: SocketAddr, weight: f32) -> Result<ClientRef> {
         debug!("New client {}", address);
         if self.graph.clients.contains_key(&address) {
             bail!("State already contains client {}", address);
         }
+        if !(weight > 0f32) {
+            bail!("Client weight has to be positive, got {}", weight);
+        }
         let c = ClientRef::new(address);
+        c.get_mut().weight = weight;
         self.graph.clients.insert(c.get().id, c.clone());
         self.logger.add_new_client_event(c.get().id);
         Ok(c)
@@ -160,8 +191,21 @@ impl State {
     }
 
     /// Create a new session fr a client, register it in the graph.
-    pub fn add_session(&mut self, client: &ClientRef) -> Result<SessionRef> {
+    pub fn add_session(
+        &mut self,
+        client: &ClientRef,
+        weight: f32,
+        cpu_quota: Option<u32>,
+    ) -> Result<SessionRef> {
+        if !(weight > 0f32) {
+            bail!("Session weight has to be positive, got {}", weight);
+        }
         let s = SessionRef::new(self.graph.new_session_id(), client);
+        {
+            let mut session = s.get_mut();
+            session.weight = weight;
+            session.cpu_quota = cpu_quota;
+        }
         self.graph.sessions.insert(s.get_id(), s.clone());
         self.logger
             .add_new_session_event(s.get_id(), client.get().id);
@@ -221,6 +265,7 @@ impl State {
         self.graph.sessions.remove(&session.get_id()).unwrap();
         // unlink
         session.unlink();
+        self.logger.add_session_closed_event(session.get_id());
         Ok(())
     }
 
@@ -424,7 +469,8 @@ impl State {
         }
     }
 
-    /// Verify submit integrity: all objects have either data or producers, acyclicity.
+    /// Verify submit integrity: all objects have either data or producers, acyclicity,
+    /// constraints of tasks can be satisfied by some worker.
     pub fn verify_submit(&mut self, tasks: &[TaskRef], objects: &[DataObjectRef]) -> Result<()> {
         // TODO: Check acyclicity
         // Every object must have data or a single producer
@@ -449,6 +495,116 @@ impl State {
         // Verify every submitted task
         for tref in tasks.iter() {
             tref.check_consistency()?;
+            let t = tref.get();
+            if let Some(quota) = t.session.get().cpu_quota {
+                if t.resources.cpus() * t.nodes() > quota {
+                    bail!(
+                        "Task {} needs {} cpus, but cpu quota of its session is {}",
+                        t.id,
+                        t.resources.cpus() * t.nodes(),
+                        quota
+                    );
+                }
+            }
+            if t.nodes() > 1 {
+                if t.gang().is_some() {
+                    bail!("Multi-node task {} cannot be a member of a gang", t.id);
+                }
+                let n_workers = self.graph
+                    .workers
+                    .values()
+                    .filter(|wref| {
+                        let w = wref.get();
+                        t.constraints().allows_worker(&w) && t.resources.is_subset_of(&w.resources)
+                    })
+                    .count();
+                if n_workers < t.nodes() as usize {
+                    bail!(
+                        "Task {} needs {} workers with {} cpus, but only {} are available",
+                        t.id,
+                        t.nodes(),
+                        t.resources.cpus(),
+                        n_workers
+                    );
+                }
+            }
+            let constraints = t.constraints();
+            if constraints.anti_affinity.as_ref().map(|g| g.is_empty()).unwrap_or(false) {
+                bail!("Task {} has an empty anti-affinity group", t.id);
+            }
+            if constraints.labels.iter().any(|l| l.is_empty())
+                || constraints.hosts.iter().any(|h| h.is_empty())
+            {
+                bail!("Task {} has an empty label or host in its constraints", t.id);
+            }
+            // Unless the task waits for a worker that connects later
+            if !constraints.wait
+                && (!constraints.labels.is_empty() || !constraints.hosts.is_empty())
+                && !self.graph.workers.values().any(|wref| {
+                    let w = wref.get();
+                    constraints.allows_worker(&w) && t.resources.is_subset_of(&w.resources)
+                }) {
+                bail!(
+                    "Task {} needs a worker with {} cpus matching labels {:?} and hosts {:?}, \
+                     but no such worker is connected",
+                    t.id,
+                    t.resources.cpus(),
+                    constraints.labels,
+                    constraints.hosts
+                );
+            }
+        }
+        // Gangs are submitted at once and have to fit into the workers together
+        let mut gangs: HashMap<(SessionRef, String), Vec<TaskRef>> = HashMap::new();
+        for tref in tasks.iter() {
+            let t = tref.get();
+            if let Some(gang) = t.gang() {
+                gangs
+                    .entry((t.session.clone(), gang.clone()))
+                    .or_insert_with(Vec::new)
+                    .push(tref.clone());
+            }
+        }
+        for ((session, name), members) in gangs {
+            let s = session.get();
+            if s.gangs[&name].len() != members.len() {
+                bail!(
+                    "All tasks of gang '{}' have to be submitted at once",
+                    name
+                );
+            }
+            // Members start at once, so none of them may wait for an output of another
+            for tref in &members {
+                for input in &tref.get().inputs {
+                    let producer = input.object.get().producer.clone();
+                    if producer.map(|p| members.contains(&p)).unwrap_or(false) {
+                        bail!(
+                            "Task {} of gang '{}' depends on another task of the gang",
+                            tref.get().id,
+                            name
+                        );
+                    }
+                }
+            }
+            let cpus: u32 = members.iter().map(|t| t.get().resources.cpus()).sum();
+            if let Some(quota) = s.cpu_quota {
+                if cpus > quota {
+                    bail!(
+                        "Gang '{}' needs {} cpus, but cpu quota of its session is {}",
+                        name,
+                        cpus,
+                        quota
+                    );
+                }
+            }
+            if place_gang(&self.graph, &members, false, &Default::default()).is_none() {
+                bail!(
+                    "Gang '{}' ({} tasks, {} cpus) cannot be placed on workers at once",
+                    name,
+                    members.len(),
+                    cpus
+                );
+            }
         }
 
         self.check_consistency_opt().unwrap(); // non-recoverable
@@ -482,6 +638,7 @@ impl State {
                 });
             placement.to_capnp(&mut co.borrow().get_placement().unwrap());
             co.set_assigned(true);
+            self.stats.object_transferred(o.size);
         }
 
         self.handle.spawn(
@@ -586,6 +743,7 @@ impl State {
                             assert!(o.data.is_some());
                             empty_worker_id.clone()
                         });
+                    self.stats.object_transferred(o.size);
                     objects.push((input.object.clone(), placement));
                 }
             }
@@ -713,7 +871,9 @@ impl State {
             self.updates.tasks.insert(tref.clone());
             if let Some(ref wref) = tref.get().scheduled {
                 let mut w = wref.get_mut();
-                w.active_resources += tref.get().resources.cpus();
+                let t = tref.get();
+                w.active_resources += t.resources.cpus();
+                t.session.get_mut().active_cpus += t.resources.cpus();
             }
         }
 
@@ -824,6 +984,16 @@ impl State {
             if ignore_check_again && self.is_task_ignored(&tref.get().id()) {
                 continue;
             }
+            if tref.get().assigned.as_ref() != Some(worker) {
+                // The task was moved to another worker (work stealing) while the update
+                // was on the way; the worker stops the task
+                debug!(
+                    "Ignoring update of task {} from worker {}, it is not assigned there",
+                    tref.get().id,
+                    worker.get_id()
+                );
+                continue;
+            }
             // inform the scheduler
             self.updates.tasks.insert(tref.clone());
             // set the state and possibly propagate
@@ -834,14 +1004,17 @@ impl State {
                         t.session.get_mut().task_finished();
                         t.state = state;
                         t.attributes.update(attributes);
+                        self.scheduler.task_finished(&t);
                         t.scheduled = None;
                         t.assigned = None;
                         let mut w = worker.get_mut();
                         w.scheduled_tasks.remove(&tref);
                         w.assigned_tasks.remove(&tref);
                         w.active_resources -= t.resources.cpus();
+                        t.session.get_mut().active_cpus -= t.resources.cpus();
                         self.logger.add_task_finished_event(t.id);
                     }
+                    tref.release_peers();
                     tref.get_mut().trigger_finish_hooks();
                     self.update_task_assignment(&tref);
 
@@ -887,7 +1060,16 @@ impl State {
                     tref.get_mut().state = state;
                     tref.get_mut().attributes = attributes;
                     let session = tref.get().session.clone();
-                    let error_message = format!("Task {} failed: {}", tref.get().id, error_message);
+                    let error_message = match tref.get().gang() {
+                        // The whole session fails, so other members of the gang are stopped
+                        Some(gang) => format!(
+                            "Task {} (member of gang '{}') failed: {}",
+                            tref.get().id,
+                            gang,
+                            error_message
+                        ),
+                        None => format!("Task {} failed: {}", tref.get().id, error_message),
+                    };
                     self.fail_session(&session, error_message.clone(), debug_message)
                         .unwrap();
                     self.logger.add_task_failed_event(
@@ -998,6 +1180,50 @@ impl State {
         }
     }
 
+    /// Move scheduled tasks that were not started yet to underloaded workers
+    /// (work stealing). Tasks already sent to a worker are stopped there.
+    pub fn rebalance_tasks(&mut self) {
+        if self.underload_workers.is_empty() {
+            return;
+        }
+        for (tref, to) in steal_tasks(&self.graph, &self.underload_workers) {
+            let from = tref.get().scheduled.clone().unwrap();
+            debug!(
+                "Moving task {} from worker {} to {}",
+                tref.get().id,
+                from.get_id(),
+                to.get_id()
+            );
+            {
+                let mut t = tref.get_mut();
+                let cpus = t.resources.cpus();
+                {
+                    let mut f = from.get_mut();
+                    f.scheduled_tasks.remove(&tref);
+                    f.scheduled_ready_tasks.remove(&tref);
+                    f.active_resources -= cpus;
+                }
+                {
+                    let mut w = to.get_mut();
+                    w.scheduled_tasks.insert(tref.clone());
+                    w.active_resources += cpus;
+                }
+                for oref in &t.outputs {
+                    let mut o = oref.get_mut();
+                    o.scheduled.remove(&from);
+                    from.get_mut().scheduled_objects.remove(oref);
+                    o.scheduled.insert(to.clone());
+                    to.get_mut().scheduled_objects.insert(oref.clone());
+                }
+                t.scheduled = Some(to.clone());
+            }
+            // Unassigns the task from the original worker (if it was sent there)
+            // and enqueues it as ready on the new worker
+            self.update_task_assignment(&tref);
+            self.underload_workers.insert(from);
+        }
+    }
+
     /// Run the scheduler and do any immediate updates the assignments.
     pub fn run_scheduler(&mut self) {
         debug!("Running scheduler");
@@ -1007,7 +1233,12 @@ impl State {
         }
 
         // Run scheduler and reset updated objects.
+        let start = ::std::time::Instant::now();
         let changed = self.scheduler.schedule(&mut self.graph, &self.updates);
+        let elapsed = start.elapsed();
+        self.stats.scheduler_runs += 1;
+        self.stats.scheduler_seconds +=
+            elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
         self.updates.clear();
 
         // Update assignments of (possibly) changed objects.
@@ -1026,6 +1257,40 @@ impl State {
     pub fn handle(&self) -> &Handle {
         &self.handle
     }
+
+    #[inline]
+    pub fn log_client_data(&self) -> bool {
+        self.log_client_data
+    }
+
+    /// Estimates of task durations learned from finished tasks
+    pub fn scheduler_estimates(&self) -> &DurationEstimates {
+        self.scheduler.estimates()
+    }
+
+    /// Number of ready tasks waiting in the scheduler
+    pub fn ready_tasks_count(&self) -> usize {
+        self.scheduler.ready_tasks_count()
+    }
+
+    /// Estimated remaining work of the session in cpu-milliseconds and
+    /// the number of unfinished tasks without any estimate
+    pub fn session_remaining_work(&self, session: &SessionRef) -> (f64, usize) {
+        let estimates = self.scheduler.estimates();
+        let mut work = 0f64;
+        let mut unknown = 0;
+        for tref in &session.get().tasks {
+            let t = tref.get();
+            if t.is_finished() {
+                continue;
+            }
+            match estimates.estimate(&t) {
+                Some(duration) => work += duration * t.resources.cpus() as f64,
+                None => unknown += 1,
+            }
+        }
+        (work, unknown)
+    }
 }
 
 impl ConsistencyCheck for State {
@@ -1059,21 +1324,24 @@ impl StateRef {
         handle: Handle,
         listen_address: SocketAddr,
         http_listen_address: SocketAddr,
-        log_dir: PathBuf,
+        logger: Box<Logger>,
         test_mode: bool,
+        log_client_data: bool,
     ) -> Self {
         let s = Self::wrap(State {
             graph: Default::default(),
             test_mode: test_mode,
+            log_client_data: log_client_data,
             listen_address: listen_address,
             http_listen_address: http_listen_address,
+            stats: Default::default(),
             handle: handle,
             scheduler: Default::default(),
             underload_workers: Default::default(),
             updates: Default::default(),
             stop_server: false,
             self_ref: None,
-            logger: Box::new(SQLiteLogger::new(&log_dir).unwrap()),
+            logger: logger,
             timer: tokio_timer::wheel()
                 .tick_duration(Duration::from_millis(100))
                 .num_slots(512)
@@ -1157,6 +1425,9 @@ impl StateRef {
             self.get().check_consistency_opt().unwrap(); // unrecoverable
         }
 
+        // Move waiting tasks to idle workers
+        self.get_mut().rebalance_tasks();
+
         // Assign ready tasks to workers (up to overbook limit)
         self.get_mut().distribute_tasks();
         !self.get().stop_server
@@ -12,7 +12,9 @@ impl DataBuilder {
     }
 
     pub fn write_blob(&mut self, data: &Data) -> Result<()> {
-        // TODO: Assert that data is blob
+        if !data.is_blob() {
+            bail!("Only blobs can be written into builder");
+        }
         match data.storage() {
             &Storage::Memory(ref bytes) => self.write(&bytes[..]),
             &Storage::Path(ref path) => {
@@ -39,3 +41,14 @@ impl DataBuilder {
         )))
     }
 }
+
+impl ::std::io::Write for DataBuilder {
+    fn write(&mut self, data: &[u8]) -> ::std::io::Result<usize> {
+        DataBuilder::write(self, data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> ::std::io::Result<()> {
+        Ok(())
+    }
+}
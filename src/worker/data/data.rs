@@ -16,29 +16,82 @@ pub enum Storage {
     Path(DataOnFs),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Blob,
+    Directory,
+}
+
 #[derive(Debug)]
 pub struct Data {
     storage: Storage,
+    data_type: DataType,
+}
+
+/// Sum of sizes of all files in directory (recursively)
+fn dir_size(path: &Path) -> ::std::result::Result<usize, ::std::io::Error> {
+    let mut size = 0;
+    for entry in ::std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len() as usize;
+        }
+    }
+    Ok(size)
+}
+
+/// Recursively copy directory
+fn copy_dir(source: &Path, target: &Path) -> ::std::result::Result<(), ::std::io::Error> {
+    ::std::fs::create_dir(target)?;
+    for entry in ::std::fs::read_dir(source)? {
+        let entry = entry?;
+        let target_path = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target_path)?;
+        } else {
+            ::std::fs::copy(entry.path(), &target_path)?;
+        }
+    }
+    Ok(())
 }
 
 impl Data {
     /// Create Data from vector
     pub fn new(storage: Storage) -> Data {
-        Data { storage }
+        Data {
+            storage,
+            data_type: DataType::Blob,
+        }
     }
 
     pub fn new_from_path(path: PathBuf, size: usize) -> Data {
         Data {
             storage: Storage::Path(DataOnFs { path, size }),
+            data_type: DataType::Blob,
         }
     }
 
+    /// Create directory data object from an existing directory
+    pub fn new_directory(path: PathBuf) -> ::std::result::Result<Self, ::std::io::Error> {
+        let size = dir_size(&path)?;
+        Ok(Data {
+            storage: Storage::Path(DataOnFs { path, size }),
+            data_type: DataType::Directory,
+        })
+    }
+
     pub fn new_by_fs_move(
         source_path: &Path,
         target_path: PathBuf,
     ) -> ::std::result::Result<Self, ::std::io::Error> {
         ::std::fs::rename(source_path, &target_path)?;
         let metadata = ::std::fs::metadata(&target_path)?;
+        if metadata.is_dir() {
+            return Data::new_directory(target_path);
+        }
         metadata.permissions().set_mode(0o400);
         let size = metadata.len() as usize;
         Ok(Data::new_from_path(target_path, size))
@@ -59,6 +112,11 @@ impl Data {
         &self.storage
     }
 
+    #[inline]
+    pub fn data_type(&self) -> DataType {
+        self.data_type
+    }
+
     /// Return size of data in bytes
     /// If data is directory than size is sum of sizes of all blobs in directory
     pub fn size(&self) -> usize {
@@ -68,6 +126,28 @@ impl Data {
         }
     }
 
+    /// Call a function on the content of data
+    /// Data on filesystem are memory mapped
+    pub fn map_bytes<F, R>(&self, f: F) -> Result<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        if self.is_directory() {
+            bail!("Directory cannot be mapped into memory");
+        }
+        match self.storage {
+            Storage::Memory(ref data) => Ok(f(data)),
+            Storage::Path(ref data) => {
+                // Empty file cannot be mapped
+                if data.size == 0 {
+                    return Ok(f(&[]));
+                }
+                let mem = unsafe { ::memmap::Mmap::map(&::std::fs::File::open(&data.path)?) }?;
+                Ok(f(&mem))
+            }
+        }
+    }
+
     /// Map data object on a given path
     /// Caller is responsible for deletion of the path
     /// It creates a symlink to real data or new file if data only in memory
@@ -97,7 +177,11 @@ impl Data {
                 file.write_all(data)?;
             }
             Storage::Path(ref data) => {
-                ::std::fs::copy(&data.path, path)?;
+                if self.is_directory() {
+                    copy_dir(&data.path, path)?;
+                } else {
+                    ::std::fs::copy(&data.path, path)?;
+                }
             }
         };
         Ok(())
@@ -105,8 +189,12 @@ impl Data {
 
     #[inline]
     pub fn is_blob(&self) -> bool {
-        // TODO: Directories
-        true
+        self.data_type == DataType::Blob
+    }
+
+    #[inline]
+    pub fn is_directory(&self) -> bool {
+        self.data_type == DataType::Directory
     }
 
     pub fn to_subworker_capnp(&self, builder: &mut ::subworker_capnp::local_data::Builder) {
@@ -123,7 +211,10 @@ impl Data {
 impl Drop for Data {
     fn drop(&mut self) {
         match self.storage {
-            Storage::Path(ref data) => ::std::fs::remove_file(&data.path).unwrap(),
+            Storage::Path(ref data) => match self.data_type {
+                DataType::Blob => ::std::fs::remove_file(&data.path).unwrap(),
+                DataType::Directory => ::std::fs::remove_dir_all(&data.path).unwrap(),
+            },
             Storage::Memory(_) => { /* Do nothing */ }
         }
     }
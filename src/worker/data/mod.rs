@@ -2,6 +2,6 @@ pub mod data;
 pub mod pack;
 pub mod builder;
 
-pub use self::data::{Data, Storage};
+pub use self::data::{Data, DataType, Storage};
 pub use self::builder::DataBuilder;
-pub use self::pack::{new_pack_stream, PackStream};
+pub use self::pack::{new_pack_stream, pack_directory, unpack_directory, PackStream};
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use errors::Result;
 use super::{Data, Storage};
 
@@ -9,15 +10,44 @@ pub trait PackStream {
     fn read(&mut self, size: usize) -> (&[u8], bool);
 }
 
+/// Pack a directory into an uncompressed tar archive, paths in the archive
+/// are relative to the directory
+pub fn pack_directory(path: &Path) -> Result<Vec<u8>> {
+    let mut builder = ::tar::Builder::new(Vec::new());
+    builder.follow_symlinks(false);
+    builder.append_dir_all(".", path)?;
+    Ok(builder.into_inner()?)
+}
+
+/// Unpack a directory packed by `pack_directory` into `target_path`
+pub fn unpack_directory(archive: &[u8], target_path: PathBuf) -> Result<Data> {
+    ::std::fs::create_dir(&target_path)?;
+    if let Err(e) = ::tar::Archive::new(archive).unpack(&target_path) {
+        ::std::fs::remove_dir_all(&target_path)?;
+        return Err(e.into());
+    }
+    Ok(Data::new_directory(target_path)?)
+}
+
 // Create a new pack stream for given dataobject
+// Directories are transferred as tar archives (see `pack_directory`)
 pub fn new_pack_stream(data: Arc<Data>) -> Result<Box<PackStream>> {
+    if data.is_directory() {
+        let archive = match data.storage() {
+            &Storage::Path(ref p) => pack_directory(&p.path)?,
+            &Storage::Memory(_) => unreachable!(),
+        };
+        return Ok(Box::new(BufferPackStream {
+            buffer: archive,
+            position: 0,
+        }));
+    }
     let data_ref = data.clone();
     Ok(match data.storage() {
         &Storage::Memory(_) => Box::new(MemoryPackStream {
             data: data_ref,
             position: 0,
         }),
-        // TODO: Directory
         &Storage::Path(ref p) => Box::new(MmapPackStream {
             data: data_ref,
             position: 0,
@@ -50,6 +80,24 @@ impl PackStream for MemoryPackStream {
     }
 }
 
+struct BufferPackStream {
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl PackStream for BufferPackStream {
+    fn read(&mut self, read_size: usize) -> (&[u8], bool) {
+        let start = self.position;
+        let (end, eof) = if start + read_size < self.buffer.len() {
+            (start + read_size, false)
+        } else {
+            (self.buffer.len(), true)
+        };
+        self.position = end;
+        (&self.buffer[start..end], eof)
+    }
+}
+
 struct MmapPackStream {
     data: Arc<Data>,
     mmap: ::memmap::Mmap,
@@ -46,6 +46,18 @@ impl WorkDir {
         value
     }
 
+    /// Bytes available to the worker on the filesystem of the working directory
+    pub fn free_space(&self) -> Result<u64> {
+        let stat = ::nix::sys::statvfs::statvfs(&self.path)
+            .map_err(|e| format!("Cannot read filesystem statistics: {}", e))?;
+        Ok(stat.blocks_available() as u64 * stat.fragment_size() as u64)
+    }
+
+    /// Number of temporary directories of running tasks
+    pub fn task_dir_count(&self) -> Result<usize> {
+        Ok(::std::fs::read_dir(self.path.join("tasks"))?.count())
+    }
+
     pub fn new_path_for_dataobject(&self) -> PathBuf {
         self.path
             .join(Path::new(&format!("data/{}", self.new_id())))
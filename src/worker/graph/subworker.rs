@@ -16,6 +16,7 @@ pub struct Subworker {
     control: ::subworker_capnp::subworker_control::Client,
     work_dir: ::tempdir::TempDir,
     kill_sender: Option<::futures::unsync::oneshot::Sender<()>>,
+    pid: u32,
 }
 
 pub type SubworkerRef = WrappedRcRefCell<Subworker>;
@@ -36,6 +37,11 @@ impl Subworker {
         self.work_dir.path()
     }
 
+    #[inline]
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
     #[inline]
     pub fn control(&self) -> &::subworker_capnp::subworker_control::Client {
         &self.control
@@ -65,6 +71,7 @@ impl SubworkerRef {
         control: ::subworker_capnp::subworker_control::Client,
         work_dir: ::tempdir::TempDir,
         kill_sender: ::futures::unsync::oneshot::Sender<()>,
+        pid: u32,
     ) -> Self {
         Self::wrap(Subworker {
             subworker_id,
@@ -72,6 +79,7 @@ impl SubworkerRef {
             control,
             work_dir,
             kill_sender: Some(kill_sender),
+            pid,
         })
     }
 }
@@ -6,6 +6,7 @@ use common::{Attributes, RcSet};
 use worker::data::Data;
 use common::wrapped::WrappedRcRefCell;
 use common::resources::Resources;
+use common::monitor::ProcessUsage;
 use std::fmt;
 
 use errors::Result;
@@ -53,6 +54,15 @@ pub struct Task {
     pub(in super::super) attributes: Attributes,
 
     pub(in super::super) new_attributes: Attributes,
+
+    /// Outputs delegated to tasks submitted by this task, they are produced elsewhere.
+    pub(in super::super) delegated: RcSet<DataObjectRef>,
+
+    /// Process of a running `!run` task, it is sampled for peak RSS
+    pub(in super::super) pid: Option<u32>,
+
+    /// Resources used by the process of the task (`!run` program or subworker)
+    pub(in super::super) usage: Option<ProcessUsage>,
 }
 
 impl Task {
@@ -95,6 +105,12 @@ impl Task {
         Ok(())
     }
 
+    /// Returns true if the output is produced by a task submitted by this task
+    #[inline]
+    pub fn is_delegated(&self, output: &DataObjectRef) -> bool {
+        self.delegated.contains(output)
+    }
+
     pub fn output(&self, index: usize) -> DataObjectRef {
         self.outputs.get(index).unwrap().clone()
     }
@@ -137,6 +153,9 @@ impl TaskRef {
             resources: resources,
             attributes: attributes,
             new_attributes: Attributes::new(),
+            delegated: Default::default(),
+            pid: None,
+            usage: None,
         });
 
         for input in &task.get().inputs {
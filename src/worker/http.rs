@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use futures;
+use hyper::{Error, StatusCode};
+use hyper::header::ContentLength;
+use hyper::server::{Request, Response, Service};
+
+use common::metrics::Metrics;
+use worker::state::StateRef;
+
+/// HTTP service of the worker, exports metrics of the worker
+pub struct RequestHandler {
+    state: StateRef,
+}
+
+impl RequestHandler {
+    pub fn new(state: StateRef) -> Self {
+        Self { state: state }
+    }
+}
+
+fn get_metrics(state: &StateRef) -> Response {
+    let state = state.get();
+    let mut m = Metrics::new();
+
+    m.gauge("rain_worker_local_cpus", "Cpus of the worker", state.get_resources().cpus());
+    m.gauge(
+        "rain_worker_free_cpus",
+        "Cpus not used by running tasks",
+        state.get_free_resources().cpus(),
+    );
+    m.gauge(
+        "rain_worker_running_tasks",
+        "Running tasks",
+        state.graph.running_tasks.len(),
+    );
+    m.gauge(
+        "rain_worker_ready_tasks",
+        "Ready tasks waiting for resources",
+        state.graph.ready_tasks.len(),
+    );
+    m.gauge("rain_worker_tasks", "Tasks assigned to the worker", state.graph.tasks.len());
+    m.gauge("rain_worker_objects", "Objects held by the worker", state.graph.objects.len());
+
+    let mut pools: HashMap<String, (usize, usize)> = HashMap::new();
+    for sw in state.graph.subworkers.values() {
+        pools
+            .entry(sw.get().subworker_type().to_string())
+            .or_insert((0, 0))
+            .0 += 1;
+    }
+    for sw in state.graph.idle_subworkers.iter() {
+        pools
+            .entry(sw.get().subworker_type().to_string())
+            .or_insert((0, 0))
+            .1 += 1;
+    }
+    m.family("rain_worker_subworkers", "gauge", "Subworkers by type");
+    for (subworker_type, &(total, _)) in pools.iter() {
+        m.value("rain_worker_subworkers", &[("type", subworker_type.as_str())], total);
+    }
+    m.family("rain_worker_idle_subworkers", "gauge", "Idle subworkers by type");
+    for (subworker_type, &(_, idle)) in pools.iter() {
+        m.value("rain_worker_idle_subworkers", &[("type", subworker_type.as_str())], idle);
+    }
+
+    // Monitoring is measured periodically, nothing is exported before the first measurement
+    if let Some(monitoring) = state.last_monitoring() {
+        m.family("rain_worker_cpu_usage", "gauge", "Usage of the cpu in percent");
+        for (i, usage) in monitoring.cpu_usage.iter().enumerate() {
+            m.value("rain_worker_cpu_usage", &[("cpu", i.to_string().as_str())], usage);
+        }
+        m.gauge(
+            "rain_worker_memory_usage",
+            "Usage of the memory of the node in percent",
+            monitoring.mem_usage,
+        );
+        m.family(
+            "rain_worker_network_received_bytes_total",
+            "counter",
+            "Bytes received by the network device",
+        );
+        for (device, stat) in monitoring.net_stat.iter() {
+            m.value(
+                "rain_worker_network_received_bytes_total",
+                &[("device", device.trim())],
+                stat[0],
+            );
+        }
+        m.family(
+            "rain_worker_network_transmitted_bytes_total",
+            "counter",
+            "Bytes transmitted by the network device",
+        );
+        for (device, stat) in monitoring.net_stat.iter() {
+            m.value(
+                "rain_worker_network_transmitted_bytes_total",
+                &[("device", device.trim())],
+                stat[1],
+            );
+        }
+        let work_dir = &monitoring.work_dir;
+        m.gauge(
+            "rain_worker_work_dir_free_bytes",
+            "Bytes available on the filesystem of the working directory",
+            work_dir.free_space,
+        );
+        m.family("rain_worker_data_bytes", "gauge", "Bytes of finished objects by storage");
+        m.value("rain_worker_data_bytes", &[("storage", "memory")], work_dir.data_in_memory);
+        m.value("rain_worker_data_bytes", &[("storage", "file")], work_dir.data_in_files);
+        m.gauge(
+            "rain_worker_task_dirs",
+            "Temporary directories of running tasks",
+            work_dir.task_dirs,
+        );
+    }
+
+    let data = m.into_string();
+    Response::new()
+        .with_header(ContentLength(data.len() as u64))
+        .with_body(data)
+}
+
+impl Service for RequestHandler {
+    type Request = Request;
+    type Response = Response;
+    type Error = Error;
+
+    type Future = Box<futures::Future<Item = Self::Response, Error = Self::Error>>;
+
+    fn call(&self, req: Request) -> Self::Future {
+        debug!("HTTP request: {}", req.path());
+        let response = match req.path() {
+            "/metrics" => get_metrics(&self.state),
+            _ => Response::new().with_status(StatusCode::NotFound),
+        };
+        Box::new(futures::future::ok(response))
+    }
+}
@@ -4,5 +4,6 @@ pub mod graph;
 pub mod data;
 pub mod rpc;
 pub mod tasks;
+pub mod http;
 
 pub use self::state::{State, StateRef};
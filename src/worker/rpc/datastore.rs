@@ -3,7 +3,7 @@ use common::convert::FromCapnp;
 use common::id::DataObjectId;
 use worker::data::{new_pack_stream, PackStream};
 
-use datastore_capnp::{data_store, read_reply, reader};
+use datastore_capnp::{data_store, read_reply, reader, DataType};
 use worker::state::StateRef;
 
 pub struct DataStoreImpl {
@@ -35,19 +35,39 @@ impl data_store::Server for DataStoreImpl {
                 return Promise::ok(());
             }
         };
-        let size = object.get().size.map(|s| s as i64).unwrap_or(-1i64);
+        let data = object.get().data().clone();
+        // Size of a packed directory is not known in advance
+        let size = if data.is_directory() {
+            -1i64
+        } else {
+            object.get().size.map(|s| s as i64).unwrap_or(-1i64)
+        };
 
         let offset = params.get_offset();
 
         assert!(offset == 0); // TODO: implement for different offset
 
-        let pack_stream = new_pack_stream(object.get().data().clone()).unwrap();
+        let data_type = if data.is_directory() {
+            DataType::Directory
+        } else {
+            DataType::Blob
+        };
+        let pack_stream = match new_pack_stream(data) {
+            Ok(s) => s,
+            Err(e) => {
+                error!("Cannot create reader for id={}: {}", id, e);
+                let mut error = results.get().init_error();
+                error.set_message(&e.to_string());
+                return Promise::ok(());
+            }
+        };
         let reader = reader::ToClient::new(ReaderImpl::new(pack_stream))
             .from_server::<::capnp_rpc::Server>();
 
         let mut results = results.get();
         results.set_reader(reader);
         results.set_size(size);
+        results.set_data_type(data_type);
         results.set_ok(());
         Promise::ok(())
     }
@@ -4,13 +4,14 @@ use std::sync::Arc;
 use std::rc::Rc;
 use std::cell::Cell;
 
-use common::id::{DataObjectId, SubworkerId};
+use common::id::{DataObjectId, SubworkerId, TaskId};
 use common::convert::FromCapnp;
 use worker::{State, StateRef};
 use worker::data::{Data, Storage};
 use subworker_capnp::subworker_upstream;
 use capnp;
 use capnp::capability::Promise;
+use futures::Future;
 
 use errors::Result;
 
@@ -68,6 +69,46 @@ impl subworker_upstream::Server for SubworkerUpstreamImpl {
         );
         Promise::ok(())
     }
+
+    fn submit_children(
+        &mut self,
+        params: subworker_upstream::SubmitChildrenParams,
+        _: subworker_upstream::SubmitChildrenResults,
+    ) -> Promise<(), ::capnp::Error> {
+        let params = pry!(params.get());
+        let state = self.state.get();
+        let task_ref = pry!(state.running_task_by_id(TaskId::from_capnp(&pry!(
+            params.get_parent()
+        ))));
+        debug!("Subworker submits children of task id={}", task_ref.get().id);
+
+        let mut delegated = Vec::new();
+        for cd in pry!(params.get_delegations()).iter() {
+            let output = pry!(state.object_by_id(DataObjectId::from_capnp(&pry!(cd.get_output()))));
+            if !task_ref.get().outputs.contains(&output) {
+                return Promise::err(capnp::Error::failed(format!(
+                    "Object {} is not an output of task {}",
+                    output.get().id,
+                    task_ref.get().id
+                )));
+            }
+            delegated.push(output);
+        }
+
+        let mut req = state.submit_children_request();
+        {
+            let mut r = req.get();
+            pry!(r.set_parent(pry!(params.get_parent())));
+            pry!(r.set_tasks(pry!(params.get_tasks())));
+            pry!(r.set_objects(pry!(params.get_objects())));
+            pry!(r.set_delegations(pry!(params.get_delegations())));
+        }
+        Promise::from_future(
+            state
+                .send_children_submit(task_ref, req, delegated)
+                .map_err(|e| e.into()),
+        )
+    }
 }
 
 pub fn data_from_capnp(
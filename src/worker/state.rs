@@ -17,10 +17,11 @@ use common::events;
 
 use worker::graph::{subworker_command, DataObject, DataObjectRef, DataObjectState, Graph,
                     SubworkerRef, TaskInput, TaskRef, TaskState};
-use worker::data::Data;
+use worker::data::{unpack_directory, Data, Storage};
 use worker::tasks::TaskInstance;
 use worker::rpc::{SubworkerUpstreamImpl, WorkerControlImpl};
 use worker::fs::workdir::WorkDir;
+use worker::http::RequestHandler;
 
 use futures::Future;
 use futures::Stream;
@@ -31,12 +32,14 @@ use tokio_core::net::TcpStream;
 use tokio_timer;
 use tokio_uds::{UnixListener, UnixStream};
 use capnp_rpc::rpc_twoparty_capnp;
+use hyper::server::Http;
 use capnp::capability::Promise;
 use errors::{Error, ErrorKind, Result};
 
 use WORKER_PROTOCOL_VERSION;
 
 const MONITORING_INTERVAL: u64 = 5; // Monitoring interval in seconds
+const USAGE_SAMPLING_INTERVAL: u64 = 200; // Sampling of task processes in milliseconds
 const DELETE_WAIT_LIST_INTERVAL: u64 = 2; // How often is delete_wait_list checked in seconds
 
 pub struct State {
@@ -71,6 +74,9 @@ pub struct State {
 
     free_resources: Resources,
 
+    /// Labels announced to the server, used for placement constraints of tasks
+    labels: Vec<String>,
+
     /// Path to working directory
     work_dir: WorkDir,
 
@@ -78,6 +84,9 @@ pub struct State {
 
     monitor: Monitor,
 
+    /// The most recent monitoring measurement, exported by the `/metrics` HTTP endpoint
+    last_monitoring: Option<events::MonitoringEvent>,
+
     /// Listing of subworkers that were started as process, but not registered
     /// The second member of triplet is subworker_type
     /// Third member (oneshot) is fired when registration is completed
@@ -87,8 +96,9 @@ pub struct State {
             String,                                           // type (e.g. "py")
             ::tempdir::TempDir,                               // working dir
             ::futures::unsync::oneshot::Sender<SubworkerRef>, // when finished
-            ::futures::unsync::oneshot::Sender<()>,
-        ), // kill switch of worker
+            ::futures::unsync::oneshot::Sender<()>,           // kill switch of worker
+            u32,                                              // pid of the process
+        ),
     >,
 
     // Map from name of subworkers to its arguments
@@ -129,6 +139,14 @@ impl State {
         &self.resources
     }
 
+    pub fn get_free_resources(&self) -> &Resources {
+        &self.free_resources
+    }
+
+    pub fn last_monitoring(&self) -> Option<&events::MonitoringEvent> {
+        self.last_monitoring.as_ref()
+    }
+
     /// Start scheduler in next loop
     pub fn need_scheduling(&mut self) {
         self.need_scheduling = true;
@@ -293,16 +311,18 @@ impl State {
                         &args[1..],
                     )?;
 
+                    let child = command.spawn_async(&self.handle)?;
+
                     self.initializing_subworkers.push((
                         subworker_id,
                         subworker_type.to_string(),
                         subworker_dir,
                         ready_sender,
                         kill_sender,
+                        child.id(),
                     ));
 
-                    let command_future = command
-                        .status_async2(&self.handle)?
+                    let command_future = child
                         .map_err(|e| e.into())
                         .and_then(move |status| {
                             error!(
@@ -347,12 +367,12 @@ impl State {
     ) -> Result<()> {
         let index = self.initializing_subworkers
             .iter()
-            .position(|&(id, _, _, _, _)| id == subworker_id)
+            .position(|&(id, _, _, _, _, _)| id == subworker_id)
             .ok_or("Subworker registered under unexpected id")?;
 
         info!("Subworker registered (subworker_id={})", subworker_id);
 
-        let (_, sw_type, work_dir, ready_sender, kill_sender) =
+        let (_, sw_type, work_dir, ready_sender, kill_sender, pid) =
             self.initializing_subworkers.remove(index);
 
         if sw_type != subworker_type {
@@ -360,7 +380,7 @@ impl State {
         }
 
         let subworker =
-            SubworkerRef::new(subworker_id, subworker_type, control, work_dir, kill_sender);
+            SubworkerRef::new(subworker_id, subworker_type, control, work_dir, kill_sender, pid);
 
         let r = self.graph
             .subworkers
@@ -442,14 +462,28 @@ impl State {
                         ::datastore_capnp::reader_response::Which::Ok(()) => {
                             let size = response.get_size();
                             let reader = response.get_reader().unwrap();
-                            ::worker::rpc::fetch::fetch_from_reader(
+                            let fetch = ::worker::rpc::fetch::fetch_from_reader(
                                 reader,
                                 if size == -1 {
                                     None
                                 } else {
                                     Some(size as usize)
                                 },
-                            )
+                            );
+                            match response.get_data_type() {
+                                Ok(::datastore_capnp::DataType::Directory) => {
+                                    let state_ref = state_ref.clone();
+                                    Box::new(fetch.and_then(move |archive| {
+                                        let target_path =
+                                            state_ref.get().work_dir().new_path_for_dataobject();
+                                        archive.map_bytes(|bytes| {
+                                            unpack_directory(bytes, target_path)
+                                        })?
+                                    }))
+                                        as Box<Future<Item = Data, Error = Error>>
+                                }
+                                _ => fetch,
+                            }
                         }
                         ::datastore_capnp::reader_response::Which::Redirect(w) => {
                             assert!(is_server);
@@ -472,6 +506,11 @@ impl State {
                             debug!("Datastore ignore occured; id={}", dataobj_id);
                             Box::new(Err(Error::from(ErrorKind::Ignored)).into_future())
                         }
+                        ::datastore_capnp::reader_response::Which::Error(e) => {
+                            let message = e.and_then(|e| e.get_message().map(|m| m.to_string()))
+                                .unwrap_or_else(|_| "Invalid error message".to_string());
+                            Box::new(Err(Error::from(message)).into_future())
+                        }
                         _ => panic!("Invalid reposponse from datastore"),
                     }
                 })
@@ -666,6 +705,52 @@ impl State {
         &mut self.monitor
     }
 
+    pub fn monitor(&self) -> &Monitor {
+        &self.monitor
+    }
+
+    /// Disk usage and content of the working directory reported in monitoring events
+    pub fn work_dir_stats(&self) -> events::WorkDirStats {
+        let mut stats = events::WorkDirStats::default();
+        for object in self.graph.objects.values() {
+            let object = object.get();
+            if !object.is_finished() {
+                continue;
+            }
+            let data = object.data();
+            match *data.storage() {
+                Storage::Memory(_) => stats.data_in_memory += data.size() as u64,
+                Storage::Path(_) => stats.data_in_files += data.size() as u64,
+            }
+        }
+        stats.free_space = self.work_dir
+            .free_space()
+            .unwrap_or_else(|e| {
+                warn!("Cannot get free space of work dir: {}", e);
+                0
+            });
+        stats.task_dirs = self.work_dir.task_dir_count().unwrap_or(0);
+        stats.subworkers = self.graph.subworkers.len();
+        stats.idle_subworkers = self.graph.idle_subworkers.len();
+        stats
+    }
+
+    /// Sample peak RSS of processes of running `!run` tasks. Other values are
+    /// taken exactly when the program is reaped; the peak RSS of the program
+    /// itself is combined with the peak RSS of the reaped process tree.
+    pub fn sample_running_tasks(&self) {
+        for instance in self.graph.running_tasks.values() {
+            let mut task = instance.task_ref().get_mut();
+            let sample = match task.pid.and_then(|pid| self.monitor.process_usage(pid)) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            task.usage
+                .get_or_insert_with(Default::default)
+                .update_peak_rss(sample.peak_rss);
+        }
+    }
+
     /// Send event to server
     pub fn send_event(&mut self, event: events::Event) {
         debug!("Sending event to server");
@@ -682,6 +767,49 @@ impl State {
         self.spawn_panic_on_error(req.send().promise.map(|_| ()).map_err(|e| e.into()));
     }
 
+    /// Get a running task that submits child tasks and objects
+    pub fn running_task_by_id(&self, id: TaskId) -> Result<TaskRef> {
+        let task_ref = self.task_by_id(id)?;
+        if task_ref.get().state != TaskState::Running {
+            bail!("Task {} is not running", id);
+        }
+        Ok(task_ref)
+    }
+
+    /// Create a request for submitting child tasks and objects of a running task
+    pub fn submit_children_request(
+        &self,
+    ) -> ::capnp::capability::Request<
+        ::worker_capnp::worker_upstream::submit_children_params::Owned,
+        ::worker_capnp::worker_upstream::submit_children_results::Owned,
+    > {
+        self.upstream.as_ref().unwrap().submit_children_request()
+    }
+
+    /// Send child tasks and objects to the server. When the server accepts them,
+    /// `delegated` outputs of the task are no longer produced by the task itself.
+    pub fn send_children_submit(
+        &self,
+        task_ref: TaskRef,
+        req: ::capnp::capability::Request<
+            ::worker_capnp::worker_upstream::submit_children_params::Owned,
+            ::worker_capnp::worker_upstream::submit_children_results::Owned,
+        >,
+        delegated: Vec<DataObjectRef>,
+    ) -> Box<Future<Item = (), Error = Error>> {
+        Box::new(req.send().promise.map_err(|e| e.into()).map(move |_| {
+            let mut task = task_ref.get_mut();
+            debug!(
+                "Children of task id={} accepted, {} outputs delegated",
+                task.id,
+                delegated.len()
+            );
+            for output in delegated {
+                task.delegated.insert(output);
+            }
+        }))
+    }
+
     #[inline]
     pub fn self_ref(&self) -> StateRef {
         self.self_ref.as_ref().unwrap().clone()
@@ -694,6 +822,7 @@ impl StateRef {
         work_dir: PathBuf,
         log_dir: PathBuf,
         n_cpus: u32,
+        labels: Vec<String>,
         subworkers: HashMap<String, Vec<String>>,
     ) -> Self {
         let resources = Resources { cpus: n_cpus };
@@ -703,6 +832,7 @@ impl StateRef {
             free_slots: 4 * n_cpus,
             resources: resources.clone(),
             free_resources: resources,
+            labels,
             upstream: None,
             datastores: HashMap::new(),
             updated_objects: Default::default(),
@@ -717,6 +847,7 @@ impl StateRef {
             graph: Graph::new(),
             need_scheduling: false,
             monitor: Monitor::new(),
+            last_monitoring: None,
             initializing_subworkers: Vec::new(),
             subworker_args: subworkers,
             self_ref: None,
@@ -765,6 +896,14 @@ impl StateRef {
         self.get()
             .resources
             .to_capnp(&mut req.get().get_resources().unwrap());
+        {
+            let inner = self.get();
+            let mut labels = req.get().init_labels(inner.labels.len() as u32);
+            for (i, label) in inner.labels.iter().enumerate() {
+                labels.set(i as u32, label);
+            }
+        }
+        req.get().set_hostname(&::common::sys::get_hostname());
 
         let state = self.clone();
         let future = req.send()
@@ -832,6 +971,7 @@ impl StateRef {
         &self,
         server_address: SocketAddr,
         mut listen_address: SocketAddr,
+        http_listen_address: Option<SocketAddr>,
         ready_file: Option<&str>,
     ) {
         let handle = self.get().handle.clone();
@@ -881,6 +1021,32 @@ impl StateRef {
             });
         handle.spawn(future);
 
+        // --- Start HTTP server ---
+        if let Some(http_listen_address) = http_listen_address {
+            let handle1 = handle.clone();
+            let state = self.clone();
+            let http_server = Http::new()
+                .serve_addr_handle(&http_listen_address, &handle, move || {
+                    Ok(RequestHandler::new(state.clone()))
+                })
+                .unwrap();
+            handle.spawn(
+                http_server
+                    .for_each(move |conn| {
+                        handle1.spawn(conn.map(|_| ()).map_err(|e| {
+                            error!("Http connection error: {:?}", e);
+                        }));
+                        Ok(())
+                    })
+                    .map_err(|_| ()),
+            );
+            info!(
+                "Metrics are exported at http://{}:{}/metrics",
+                ::common::sys::get_hostname(),
+                http_listen_address.port()
+            );
+        }
+
         // --- Start monitoring ---
         let state = self.clone();
 
@@ -900,13 +1066,31 @@ impl StateRef {
                     return Ok(());
                 }
 
-                let event = s.monitor.build_event(&worker_id);
+                let mut event = s.monitor.build_event(&worker_id);
+                if let events::Event::Monitoring(ref mut m) = event {
+                    m.work_dir = s.work_dir_stats();
+                    s.last_monitoring = Some(m.clone());
+                }
                 s.send_event(event);
                 Ok(())
             })
             .map_err(|e| error!("Monitoring error {}", e));
         handle.spawn(monitoring);
 
+        // --- Start sampling of task processes ---
+        let state = self.clone();
+        let interval = state
+            .get()
+            .timer
+            .interval(Duration::from_millis(USAGE_SAMPLING_INTERVAL));
+        let sampling = interval
+            .for_each(move |()| {
+                state.get().sample_running_tasks();
+                Ok(())
+            })
+            .map_err(|e| error!("Sampling of task processes failed {}", e));
+        handle.spawn(sampling);
+
         // --- Start checking wait list ----
         let state = self.clone();
         let interval = state
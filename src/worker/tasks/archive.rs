@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use std::io::Write;
+
+use super::TaskResult;
+use worker::state::State;
+use worker::graph::TaskRef;
+use worker::data::{Data, DataBuilder, Storage};
+use futures::future;
+use errors::Result;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum Compression {
+    Gzip,
+}
+
+#[derive(Deserialize, Default)]
+struct ArchiveConfig {
+    #[serde(default)]
+    compression: Option<Compression>,
+}
+
+fn archive_config(task_ref: &TaskRef) -> Result<ArchiveConfig> {
+    let config: Option<ArchiveConfig> = task_ref.get().attributes.find("config")?;
+    Ok(config.unwrap_or_default())
+}
+
+/// Append data object into archive under the given name;
+/// directory with an empty name is placed into the root of the archive
+fn append_data<W: Write>(builder: &mut ::tar::Builder<W>, name: &str, data: &Data) -> Result<()> {
+    if data.is_directory() {
+        match data.storage() {
+            &Storage::Path(ref p) if name.is_empty() => {
+                for entry in ::std::fs::read_dir(&p.path)? {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        builder.append_dir_all(entry.file_name(), entry.path())?;
+                    } else {
+                        builder.append_path_with_name(entry.path(), entry.file_name())?;
+                    }
+                }
+            }
+            &Storage::Path(ref p) => builder.append_dir_all(name, &p.path)?,
+            &Storage::Memory(_) => unreachable!(),
+        }
+        return Ok(());
+    }
+    if name.is_empty() {
+        bail!("Blob input needs a label to be archived");
+    }
+    let mut header = ::tar::Header::new_gnu();
+    header.set_size(data.size() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    data.map_bytes(|bytes| builder.append_data(&mut header, name, bytes))??;
+    Ok(())
+}
+
+fn build_archive<W: Write>(writer: W, inputs: &[(String, Arc<Data>)]) -> Result<W> {
+    let mut builder = ::tar::Builder::new(writer);
+    for &(ref name, ref data) in inputs {
+        append_data(&mut builder, name, data)?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Task that packs inputs into a tar archive;
+/// labels of inputs are used as names in the archive
+pub fn task_tar(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let config = archive_config(&task_ref)?;
+    let inputs: Vec<(String, Arc<Data>)> = {
+        let task = task_ref.get();
+        if task.outputs.len() != 1 {
+            bail!("Task '!tar' has to have exactly one output");
+        }
+        task.inputs
+            .iter()
+            .map(|input| (input.label.clone(), input.object.get().data().clone()))
+            .collect()
+    };
+
+    Ok(Box::new(future::lazy(move || {
+        let mut builder = match config.compression {
+            None => build_archive(DataBuilder::new(), &inputs)?,
+            Some(Compression::Gzip) => {
+                let encoder = ::flate2::write::GzEncoder::new(
+                    DataBuilder::new(),
+                    ::flate2::Compression::default(),
+                );
+                build_archive(encoder, &inputs)?.finish()?
+            }
+        };
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(builder.build()));
+        Ok(())
+    })))
+}
+
+/// Task that unpacks a tar archive into a directory
+pub fn task_untar(state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let state_ref = state.self_ref();
+    let config = archive_config(&task_ref)?;
+    let input = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        task.input_data(0)
+    };
+
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        let target_path = state_ref.get().work_dir().new_path_for_dataobject();
+        ::std::fs::create_dir(&target_path)?;
+        let result = input.map_bytes(|bytes| match config.compression {
+            None => ::tar::Archive::new(bytes).unpack(&target_path),
+            Some(Compression::Gzip) => {
+                ::tar::Archive::new(::flate2::read::GzDecoder::new(bytes)).unpack(&target_path)
+            }
+        });
+        if let Err(e) = result.and_then(|r| r.map_err(|e| e.into())) {
+            ::std::fs::remove_dir_all(&target_path)?;
+            return Err(e);
+        }
+        let data = Data::new_directory(target_path)?;
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(data));
+        Ok(())
+    })))
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use super::TaskResult;
+use worker::state::State;
+use worker::graph::TaskRef;
+use worker::data::{Data, Storage};
+use futures::future;
+use errors::Result;
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Default for Algorithm {
+    fn default() -> Self {
+        Algorithm::Sha256
+    }
+}
+
+#[derive(Deserialize, Default)]
+struct ChecksumConfig {
+    #[serde(default)]
+    algorithm: Algorithm,
+}
+
+#[derive(Deserialize)]
+struct AssertChecksumConfig {
+    #[serde(default)]
+    algorithm: Algorithm,
+    digest: String,
+}
+
+/// Hex encoded digest of bytes
+fn digest(algorithm: Algorithm, bytes: &[u8]) -> String {
+    match algorithm {
+        Algorithm::Sha256 => {
+            use sha2::Digest;
+            ::sha2::Sha256::digest(bytes)
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        }
+        Algorithm::Blake3 => ::blake3::hash(bytes).to_hex().to_string(),
+    }
+}
+
+fn data_digest(algorithm: Algorithm, data: &Data) -> Result<String> {
+    data.map_bytes(|bytes| digest(algorithm, bytes))
+}
+
+/// Task that computes digest of each input blob;
+/// the i-th output contains hex encoded digest of the i-th input
+pub fn task_checksum(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let (inputs, config) = {
+        let task = task_ref.get();
+        if task.inputs.len() != task.outputs.len() {
+            bail!("Task '!checksum' needs the same number of inputs and outputs");
+        }
+        let config: Option<ChecksumConfig> = task.attributes.find("config")?;
+        (task.inputs_data(), config.unwrap_or_default())
+    };
+
+    for (i, input) in inputs.iter().enumerate() {
+        if !input.is_blob() {
+            bail!("Input {} object is not blob", i);
+        }
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        let digests = inputs
+            .iter()
+            .map(|input| data_digest(config.algorithm, input))
+            .collect::<Result<Vec<_>>>()?;
+        let task = task_ref.get();
+        for (i, digest) in digests.into_iter().enumerate() {
+            let data = Data::new(Storage::Memory(digest.into_bytes()));
+            task.output(i).get_mut().set_data(Arc::new(data));
+        }
+        Ok(())
+    })))
+}
+
+/// Task that forwards its input when its digest matches the expected one,
+/// otherwise the task fails
+pub fn task_assert_checksum(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let (input, config) = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        let config: AssertChecksumConfig = task.attributes.get("config")?;
+        (task.input_data(0), config)
+    };
+
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        let digest = data_digest(config.algorithm, &input)?;
+        if digest != config.digest.to_lowercase() {
+            bail!(
+                "Checksum mismatch ({:?}): expected {}, got {}",
+                config.algorithm,
+                config.digest,
+                digest
+            );
+        }
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(input);
+        Ok(())
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            digest(Algorithm::Sha256, b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(Algorithm::Blake3, b""),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::io::BufReader;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use futures::{future, Future, Stream};
+use futures::unsync::oneshot;
+use tokio_io::io::{read_until, write_all};
+use tokio_uds::{UnixListener, UnixStream};
+
+use common::Attributes;
+use common::convert::ToCapnp;
+use common::id::{DataObjectId, Id, SId, TaskId};
+use common::resources::Resources;
+use worker::graph::{DataObjectRef, TaskRef, TaskState};
+use worker::graph::task::Task;
+use worker::state::{State, StateRef};
+use worker_capnp::worker_upstream::submit_children_params;
+use errors::{Error, Result};
+
+/// Object submitted by a running task
+#[derive(Deserialize, Debug)]
+pub struct ChildObjectSpec {
+    /// Id local to the submit
+    pub id: Id,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default)]
+    pub keep: bool,
+    /// Text content of the object; if not given, the object is produced by a task
+    pub data: Option<String>,
+    #[serde(default)]
+    pub attributes: HashMap<String, ::serde_json::Value>,
+}
+
+/// Input of a task submitted by a running task
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum ChildInputSpec {
+    /// Id of a submitted object
+    Submitted(Id),
+    /// Id of an existing object of the session, written as `{"existing": id}`
+    Existing { existing: Id },
+}
+
+/// Task submitted by a running task
+#[derive(Deserialize, Debug)]
+pub struct ChildTaskSpec {
+    /// Id local to the submit
+    pub id: Id,
+    pub task_type: String,
+    /// Submitted objects or existing objects of the session
+    #[serde(default)]
+    pub inputs: Vec<ChildInputSpec>,
+    /// Submitted objects
+    #[serde(default)]
+    pub outputs: Vec<Id>,
+    #[serde(default = "default_cpus")]
+    pub cpus: u32,
+    #[serde(default)]
+    pub attributes: HashMap<String, ::serde_json::Value>,
+}
+
+fn default_cpus() -> u32 {
+    1
+}
+
+/// Output of the running task that is produced by a submitted object
+#[derive(Deserialize, Debug)]
+pub struct DelegationSpec {
+    /// Index of the output of the running task
+    pub output: usize,
+    /// Id of the submitted object
+    pub source: Id,
+}
+
+/// Tasks and objects submitted by a running task through the task socket
+#[derive(Deserialize, Debug)]
+pub struct ChildGraphSpec {
+    #[serde(default)]
+    pub objects: Vec<ChildObjectSpec>,
+    #[serde(default)]
+    pub tasks: Vec<ChildTaskSpec>,
+    #[serde(default)]
+    pub delegations: Vec<DelegationSpec>,
+}
+
+#[derive(Serialize, Debug)]
+struct SubmitResponse {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+fn make_attributes(items: &HashMap<String, ::serde_json::Value>) -> Result<Attributes> {
+    let mut attributes = Attributes::new();
+    for (key, value) in items {
+        attributes.set(key, value)?;
+    }
+    Ok(attributes)
+}
+
+/// Fill the submit request, returns the delegated outputs of the task
+fn fill_request(
+    task: &Task,
+    spec: &ChildGraphSpec,
+    req: &mut submit_children_params::Builder,
+) -> Result<Vec<DataObjectRef>> {
+    let session_id = task.id.get_session_id();
+    task.id.to_capnp(&mut req.borrow().get_parent()?);
+    {
+        let mut objects = req.borrow().init_objects(spec.objects.len() as u32);
+        for (i, o) in spec.objects.iter().enumerate() {
+            let mut co = objects.borrow().get(i as u32);
+            DataObjectId::new(session_id, o.id).to_capnp(&mut co.borrow().get_id()?);
+            co.set_keep(o.keep);
+            co.set_label(&o.label);
+            if let Some(ref data) = o.data {
+                co.set_has_data(true);
+                co.set_data(data.as_bytes());
+            }
+            make_attributes(&o.attributes)?.to_capnp(&mut co.borrow().get_attributes()?);
+        }
+    }
+    {
+        let mut tasks = req.borrow().init_tasks(spec.tasks.len() as u32);
+        for (i, t) in spec.tasks.iter().enumerate() {
+            let mut ct = tasks.borrow().get(i as u32);
+            TaskId::new(session_id, t.id).to_capnp(&mut ct.borrow().get_id()?);
+            ct.set_task_type(&t.task_type);
+            {
+                let mut inputs = ct.borrow().init_inputs(t.inputs.len() as u32);
+                for (j, input) in t.inputs.iter().enumerate() {
+                    let mut ci = inputs.borrow().get(j as u32);
+                    let (id, existing) = match *input {
+                        ChildInputSpec::Submitted(id) => (id, false),
+                        ChildInputSpec::Existing { existing } => (existing, true),
+                    };
+                    DataObjectId::new(session_id, id).to_capnp(&mut ci.borrow().get_id()?);
+                    ci.set_label("");
+                    ci.set_path("");
+                    ci.set_existing(existing);
+                }
+            }
+            {
+                let mut outputs = ct.borrow().init_outputs(t.outputs.len() as u32);
+                for (j, id) in t.outputs.iter().enumerate() {
+                    let mut co = outputs.borrow().get(j as u32);
+                    DataObjectId::new(session_id, *id).to_capnp(&mut co);
+                }
+            }
+            let mut attributes = make_attributes(&t.attributes)?;
+            if attributes.find::<Resources>("resources")?.is_none() {
+                attributes.set("resources", Resources { cpus: t.cpus })?;
+            }
+            attributes.to_capnp(&mut ct.borrow().get_attributes()?);
+        }
+    }
+    let mut delegated = Vec::new();
+    {
+        let mut delegations = req.borrow().init_delegations(spec.delegations.len() as u32);
+        for (i, d) in spec.delegations.iter().enumerate() {
+            let output = match task.outputs.get(d.output) {
+                Some(o) => o.clone(),
+                None => bail!("Task {} has no output {}", task.id, d.output),
+            };
+            let mut cd = delegations.borrow().get(i as u32);
+            output.get().id.to_capnp(&mut cd.borrow().get_output()?);
+            DataObjectId::new(session_id, d.source).to_capnp(&mut cd.borrow().get_source()?);
+            delegated.push(output);
+        }
+    }
+    Ok(delegated)
+}
+
+fn submit_spec(
+    state_ref: &StateRef,
+    task_ref: &TaskRef,
+    line: &[u8],
+) -> Result<Box<Future<Item = (), Error = Error>>> {
+    let spec: ChildGraphSpec = ::serde_json::from_slice(line)?;
+    let state = state_ref.get();
+    if task_ref.get().state != TaskState::Running {
+        bail!("Task {} is not running", task_ref.get().id);
+    }
+    let mut req = state.submit_children_request();
+    let delegated = fill_request(&task_ref.get(), &spec, &mut req.get())?;
+    Ok(state.send_children_submit(task_ref.clone(), req, delegated))
+}
+
+/// Read one submit (a JSON document on a single line) and answer with a JSON line
+/// `{"ok": true}` or `{"ok": false, "error": "..."}`
+fn handle_connection(
+    state_ref: StateRef,
+    task_ref: TaskRef,
+    stream: UnixStream,
+) -> Box<Future<Item = (), Error = Error>> {
+    Box::new(
+        read_until(BufReader::new(stream), b'\n', Vec::new())
+            .map_err(|e| e.into())
+            .and_then(move |(reader, line)| {
+                let submit: Box<Future<Item = (), Error = Error>> = match submit_spec(&state_ref, &task_ref, &line) {
+                    Ok(f) => f,
+                    Err(e) => Box::new(future::err(e)),
+                };
+                submit.then(move |r| {
+                    let response = match r {
+                        Ok(()) => SubmitResponse {
+                            ok: true,
+                            error: None,
+                        },
+                        Err(e) => SubmitResponse {
+                            ok: false,
+                            error: Some(e.description().to_string()),
+                        },
+                    };
+                    let mut data = ::serde_json::to_vec(&response).unwrap();
+                    data.push(b'\n');
+                    write_all(reader.into_inner(), data)
+                        .map(|_| ())
+                        .map_err(|e| e.into())
+                })
+            }),
+    )
+}
+
+/// Listen on a unix socket `name` in directory `dir` where programs of the task
+/// may submit child tasks and objects. Listening stops when the returned sender
+/// is dropped.
+pub fn start_task_socket(
+    state: &State,
+    task_ref: &TaskRef,
+    dir: &Path,
+    name: &str,
+) -> Result<oneshot::Sender<()>> {
+    let listener = {
+        // Socket paths are limited (108 bytes), the socket is bound through
+        // the descriptor of the directory, so the length of `dir` does not matter
+        let dir_file = ::std::fs::File::open(dir)?;
+        let path = format!("/proc/self/fd/{}/{}", dir_file.as_raw_fd(), name);
+        UnixListener::bind(&path, state.handle())?
+    };
+    let (sender, receiver) = oneshot::channel::<()>();
+    let state_ref = state.self_ref();
+    let task_ref = task_ref.clone();
+    let handle = state.handle().clone();
+    let future = listener
+        .incoming()
+        .map_err(|e| Error::from(e))
+        .for_each(move |(stream, _)| {
+            let task_id = task_ref.get().id;
+            handle.spawn(
+                handle_connection(state_ref.clone(), task_ref.clone(), stream).map_err(
+                    move |e| warn!("Submit from task id={} failed: {}", task_id, e.description()),
+                ),
+            );
+            Ok(())
+        })
+        .select2(receiver)
+        .then(|_| Ok(()));
+    state.handle().spawn(future);
+    Ok(sender)
+}
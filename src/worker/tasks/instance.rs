@@ -6,6 +6,8 @@ use worker::state::State;
 use worker::tasks;
 use worker::rpc::subworker::data_from_capnp;
 use common::Attributes;
+use common::attributes::AttributeInfo;
+use common::events;
 use common::convert::ToCapnp;
 use errors::{Error, Result};
 
@@ -27,13 +29,6 @@ pub struct TaskInstance {
 pub type TaskFuture = Future<Item = (), Error = Error>;
 pub type TaskResult = Result<Box<TaskFuture>>;
 
-#[derive(Serialize)]
-struct AttributeInfo {
-    worker: String,
-    start: String,
-    duration: i64,
-}
-
 fn fail_unknown_type(_state: &mut State, task_ref: TaskRef) -> TaskResult {
     bail!("Unknown task type {}", task_ref.get().task_type)
 }
@@ -64,6 +59,11 @@ impl Drop for KillOnDrop {
 }
 
 impl TaskInstance {
+    #[inline]
+    pub fn task_ref(&self) -> &TaskRef {
+        &self.task_ref
+    }
+
     pub fn start(state: &mut State, task_ref: TaskRef) {
         {
             let mut task = task_ref.get_mut();
@@ -79,10 +79,19 @@ impl TaskInstance {
             match task_type {
                 task_type if !task_type.starts_with("!") => Self::start_task_in_subworker,
                 "!run" => tasks::run::task_run,
+                "!pipe" => tasks::pipe::task_pipe,
                 "!concat" => tasks::basic::task_concat,
                 "!sleep" => tasks::basic::task_sleep,
                 "!open" => tasks::basic::task_open,
                 "!export" => tasks::basic::task_export,
+                "!split" => tasks::shard::task_split,
+                "!slice" => tasks::shard::task_slice,
+                "!sort_lines" => tasks::shard::task_sort_lines,
+                "!merge_sorted" => tasks::shard::task_merge_sorted,
+                "!tar" => tasks::archive::task_tar,
+                "!untar" => tasks::archive::task_untar,
+                "!checksum" => tasks::checksum::task_checksum,
+                "!assert_checksum" => tasks::checksum::task_assert_checksum,
                 _ => fail_unknown_type,
             }
         };
@@ -130,14 +139,29 @@ impl TaskInstance {
                     };
                     task.new_attributes.set("info", info).unwrap();
 
+                    task.pid = None;
+                    if let Some(usage) = task.usage.take() {
+                        task.new_attributes.set("usage", &usage).unwrap();
+                        let event = events::Event::TaskUsage(events::TaskUsageEvent {
+                            task: task.id,
+                            worker: state.worker_id().clone(),
+                            usage,
+                        });
+                        state.send_event(event);
+                    }
+
                     match r {
                         Ok((true, _)) => {
-                            let all_finished = task.outputs.iter().all(|o| o.get().is_finished());
+                            let all_finished = task.outputs
+                                .iter()
+                                .all(|o| task.is_delegated(o) || o.get().is_finished());
                             if !all_finished {
                                 task.set_failed("Some of outputs were not produced".to_string());
                             } else {
                                 for output in &task.outputs {
-                                    state.object_is_finished(output);
+                                    if !task.is_delegated(output) {
+                                        state.object_is_finished(output);
+                                    }
                                 }
                                 debug!("Task was successfully finished");
                                 task.state = TaskState::Finished;
@@ -175,6 +199,15 @@ impl TaskInstance {
             // This is can happen when task is terminated and feature dropped without finishhing
             let mut sw_wrapper = KillOnDrop::new(subworker.clone());
 
+            // The subworker runs one task at a time, the task gets the difference
+            // of usage of the subworker process
+            let pid = subworker.get().pid();
+            let start_usage = {
+                let state = state_ref.get();
+                state.monitor().reset_peak_rss(pid);
+                state.monitor().process_usage(pid)
+            };
+
             let mut req = subworker.get().control().run_task_request();
             {
                 let task = task_ref.get();
@@ -239,6 +272,10 @@ impl TaskInstance {
                     let result = match r {
                         Ok(response) => {
                             let mut task = task_ref.get_mut();
+                            let end_usage = state_ref.get().monitor().process_usage(pid);
+                            if let (Some(start), Some(end)) = (start_usage, end_usage) {
+                                task.usage = Some(end.since(&start));
+                            }
                             let response = response.get()?;
                             task.new_attributes
                                 .update_from_capnp(&response.get_task_attributes()?);
@@ -247,6 +284,9 @@ impl TaskInstance {
                             if response.get_ok() {
                                 debug!("Task id={} finished in subworker", task.id);
                                 for (co, output) in response.get_data()?.iter().zip(&task.outputs) {
+                                    if task.is_delegated(output) {
+                                        continue;
+                                    }
                                     let data = data_from_capnp(&state_ref.get(), work_dir, &co)?;
                                     let attributes =
                                         Attributes::from_capnp(&co.get_attributes().unwrap());
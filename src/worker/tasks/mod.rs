@@ -1,5 +1,10 @@
 pub mod instance;
 pub mod basic;
 pub mod run;
+pub mod pipe;
+pub mod shard;
+pub mod archive;
+pub mod checksum;
+pub mod children;
 
 pub use self::instance::{TaskFuture, TaskInstance, TaskResult};
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::sync::Arc;
+use std::process::{Command, Stdio};
+use tokio_process::CommandExt;
+use futures::Future;
+use std::os::unix::io::{FromRawFd, IntoRawFd};
+
+use super::TaskResult;
+use super::run::read_stderr;
+use worker::graph::TaskRef;
+use worker::state::State;
+use worker::data::Data;
+
+#[derive(Deserialize)]
+struct PipeConfig {
+    /// Arguments of programs; stdout of each stage is connected to stdin of the next one
+    pub stages: Vec<Vec<String>>,
+}
+
+/// Task that runs a pipeline of programs
+/// The optional input is used as stdin of the first stage,
+/// stdout of the last stage is the output of the task.
+/// Stderr of each stage is captured separately.
+pub fn task_pipe(state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let state_ref = state.self_ref();
+    let config: PipeConfig = {
+        let task = task_ref.get();
+        if task.inputs.len() > 1 {
+            bail!("Task '!pipe' accepts at most one input");
+        }
+        if task.outputs.len() != 1 {
+            bail!("Task '!pipe' has to have exactly one output");
+        }
+        task.attributes.get("config")?
+    };
+
+    if config.stages.is_empty() {
+        bail!("Pipeline has no stages");
+    }
+
+    if config.stages.iter().any(|args| args.is_empty()) {
+        bail!("Arguments of a pipeline stage are empty");
+    }
+
+    let (dir, futures) = {
+        let task = task_ref.get();
+        let dir = state.work_dir().make_task_temp_dir(task.id)?;
+
+        let mut in_io = if task.inputs.is_empty() {
+            Stdio::null()
+        } else {
+            let path = dir.path().join("+in");
+            task.input_data(0).map_to_path(&path)?;
+            let in_id = File::open(&path)?.into_raw_fd();
+            unsafe { Stdio::from_raw_fd(in_id) }
+        };
+
+        let last = config.stages.len() - 1;
+        let mut futures = Vec::with_capacity(config.stages.len());
+
+        for (i, args) in config.stages.iter().enumerate() {
+            let stage_in_io = ::std::mem::replace(&mut in_io, Stdio::null());
+            let out_io = if i == last {
+                let out_id = File::create(dir.path().join("+out"))?.into_raw_fd();
+                unsafe { Stdio::from_raw_fd(out_id) }
+            } else {
+                // Pipes are created with CLOEXEC, so they do not leak into other stages
+                let (read_id, write_id) =
+                    ::nix::unistd::pipe2(::nix::fcntl::OFlag::O_CLOEXEC)
+                        .map_err(|e| format!("Cannot create pipe: {}", e))?;
+                in_io = unsafe { Stdio::from_raw_fd(read_id) };
+                unsafe { Stdio::from_raw_fd(write_id) }
+            };
+            let err_id = File::create(dir.path().join(format!("+err{}", i)))?.into_raw_fd();
+            let err_io = unsafe { Stdio::from_raw_fd(err_id) };
+
+            debug!("Starting pipeline stage {}: {}", i, args[0]);
+
+            // Command is dropped right after spawning, hence
+            // the parent closes its copies of pipe ends
+            let future = Command::new(&args[0])
+                .args(&args[1..])
+                .stdin(stage_in_io)
+                .stdout(out_io)
+                .stderr(err_io)
+                .current_dir(dir.path())
+                .status_async2(state.handle())
+                .map_err(|e| format!("Stage {} ({}) cannot be started: {}", i, args[0], e))?;
+            futures.push(future);
+        }
+        (dir, futures)
+    };
+
+    Ok(Box::new(
+        ::futures::future::join_all(futures)
+            .map_err(|e| e.into())
+            .and_then(move |statuses| {
+                let exit_codes: Vec<Option<i32>> = statuses.iter().map(|s| s.code()).collect();
+                task_ref
+                    .get_mut()
+                    .new_attributes
+                    .set("exit_codes", &exit_codes)?;
+
+                // The same as "pipefail" in bash; the last failed stage is reported,
+                // since earlier stages may only be killed by SIGPIPE
+                if let Some(i) = statuses.iter().rposition(|s| !s.success()) {
+                    let stderr_path = dir.path().join(format!("+err{}", i));
+                    let stderr = match read_stderr(&stderr_path) {
+                        Ok(s) => format!("Stderr: {}\n", s),
+                        Err(e) => format!(
+                            "Stderr could not be obtained: {}",
+                            ::std::error::Error::description(&e)
+                        ),
+                    };
+                    let name = &config.stages[i][0];
+                    match statuses[i].code() {
+                        Some(code) => bail!(
+                            "Pipeline stage {} ({}) exited with code {}\n{}",
+                            i,
+                            name,
+                            code,
+                            stderr
+                        ),
+                        None => bail!(
+                            "Pipeline stage {} ({}) terminated by signal\n{}",
+                            i,
+                            name,
+                            stderr
+                        ),
+                    }
+                }
+
+                let state = state_ref.get();
+                let task = task_ref.get();
+                let target_path = state.work_dir().new_path_for_dataobject();
+                let data = Data::new_by_fs_move(&dir.path().join("+out"), target_path)?;
+                task.output(0).get_mut().set_data(Arc::new(data));
+                Ok(())
+            }),
+    ))
+}
@@ -1,19 +1,20 @@
 use std::fs::File;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::process::{Command, Stdio};
-use tokio_process::CommandExt;
 use futures::Future;
 use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::path::Path;
-use std::io::Read;
+use std::io::{Read, Write};
 
 use super::TaskResult;
+use super::children::start_task_socket;
 use worker::graph::TaskRef;
 use worker::state::State;
-use worker::data::Data;
-use errors::Result;
+use worker::data::{Data, Storage};
+use common::monitor::wait_process;
+use errors::{Error, Result};
 
-fn read_stderr(path: &Path) -> Result<String> {
+pub fn read_stderr(path: &Path) -> Result<String> {
     // TODO: If the file is too big, truncate the beginning
     let mut file = File::open(path)?;
     let mut s = String::new();
@@ -21,18 +22,58 @@ fn read_stderr(path: &Path) -> Result<String> {
     Ok(s)
 }
 
+fn default_success_codes() -> Vec<i32> {
+    vec![0]
+}
+
 #[derive(Serialize, Deserialize)]
 struct RunConfig {
     pub args: Vec<String>,
     pub in_paths: Vec<String>,
     pub out_paths: Vec<String>,
+    /// Exit codes that are considered as a successful run
+    #[serde(default = "default_success_codes")]
+    pub success_codes: Vec<i32>,
+    /// If true, outputs that were not created by the program are
+    /// produced as empty blobs instead of failing the task
+    #[serde(default)]
+    pub optional_outputs: bool,
+    /// If true, the program may submit child tasks and objects through a unix socket
+    #[serde(default)]
+    pub task_socket: bool,
+}
+
+/// Writes a hostfile of a multi-node task (in format of mpirun) into the task directory
+fn write_hostfile(dir: &Path, hosts: &[String], cpus: u32) -> Result<()> {
+    let mut file = File::create(dir.join("+hostfile"))?;
+    for host in hosts {
+        writeln!(file, "{} slots={}", host, cpus)?;
+    }
+    Ok(())
+}
+
+/// Kills the program when the task is dropped before the program exits
+struct KillOnDrop {
+    pid: u32,
+    /// Set when the program has exited, its pid may be reused after that
+    exited: Arc<Mutex<bool>>,
+}
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let exited = self.exited.lock().unwrap();
+        if !*exited {
+            debug!("Killing program (pid={})", self.pid);
+            unsafe { ::nix::libc::kill(self.pid as ::nix::libc::pid_t, ::nix::libc::SIGKILL) };
+        }
+    }
 }
 
 pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
     let state_ref = state.self_ref();
     let config: RunConfig = task_ref.get().attributes.get("config")?;
 
-    let (dir, future, stderr_path) = {
+    let (dir, future, kill_guard, stderr_path, task_socket) = {
         // Parse arguments
         let name = config.args.get(0).ok_or_else(|| "Arguments are empty")?;
         let task = task_ref.get();
@@ -65,20 +106,77 @@ pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
 
         debug!("Starting command: {}", name);
 
-        let future = Command::new(&name)
+        let mut command = Command::new(&name);
+        command
             .args(&config.args[1..])
             .stdin(in_io)
             .stdout(out_io)
             .stderr(err_io)
-            .current_dir(dir.path())
-            .status_async2(state.handle())?;
+            .current_dir(dir.path());
+
+        // Multi-node task: the server sends hosts of all nodes, this worker is the first one
+        let node_hosts: Option<Vec<String>> = task.attributes.find("node_hosts")?;
+        if let Some(hosts) = node_hosts {
+            write_hostfile(dir.path(), &hosts, task.resources.cpus())?;
+            command
+                .env("RAIN_NODES", hosts.len().to_string())
+                .env("RAIN_NODE_HOSTS", hosts.join(","))
+                .env("RAIN_HOSTFILE", dir.path().join("+hostfile"));
+        }
+
+        // Programs may submit child tasks and objects through the task socket,
+        // its path is relative to the working directory of the program
+        let task_socket = if config.task_socket {
+            let sender = start_task_socket(state, &task_ref, dir.path(), "+rain.sock")?;
+            command.env("RAIN_TASK_SOCKET", "+rain.sock");
+            Some(sender)
+        } else {
+            None
+        };
+
+        let child = command.spawn()?;
+        let pid = child.id();
+
+        // The program is reaped by a thread that gets its exact resource usage
+        let exited = Arc::new(Mutex::new(false));
+        let (sender, receiver) = ::futures::sync::oneshot::channel();
+        {
+            let exited = exited.clone();
+            ::std::thread::spawn(move || {
+                let result = wait_process(pid, || *exited.lock().unwrap() = true);
+                let _ = sender.send(result);
+            });
+        }
+        let future = receiver
+            .map_err(|_| Error::from("Waiting for the program failed"))
+            .and_then(|result| result);
 
-        (dir, future, stderr_path)
+        (dir, future, KillOnDrop { pid, exited }, stderr_path, task_socket)
     };
+    // Peak RSS of the program is sampled while it runs
+    task_ref.get_mut().pid = Some(kill_guard.pid);
 
-    Ok(Box::new(future.map_err(|e| e.into()).and_then(
-        move |status| {
-            if !status.success() {
+    Ok(Box::new(future.and_then(
+        move |(status, mut usage)| {
+            // The program has finished, stop listening
+            drop(task_socket);
+            drop(kill_guard);
+            {
+                let mut task = task_ref.get_mut();
+                task.pid = None;
+                if let Some(ref sampled) = task.usage {
+                    usage.update_peak_rss(sampled.peak_rss);
+                }
+                task.usage = Some(usage);
+            }
+            if let Some(code) = status.code() {
+                task_ref.get_mut().new_attributes.set("exit_code", code)?;
+            }
+            let success = status
+                .code()
+                .map(|code| config.success_codes.contains(&code))
+                .unwrap_or(false);
+            if !success {
                 let stderr = match read_stderr(&stderr_path) {
                     Ok(s) => format!("Stderr: {}\n", s),
                     Err(e) => format!(
@@ -87,7 +185,7 @@ pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
                     ),
                 };
                 match status.code() {
-                    Some(code) => bail!("Program exit with exit code {}\n{}", code, stderr),
+                    Some(code) => bail!("Program exited with code {}\n{}", code, stderr),
                     None => bail!("Program terminated by signal\n{}", stderr),
                 }
             }
@@ -96,9 +194,18 @@ pub fn task_run(state: &mut State, task_ref: TaskRef) -> TaskResult {
                 let task = task_ref.get();
 
                 for (path, dataobj) in config.out_paths.iter().zip(&task.outputs) {
+                    if task.is_delegated(dataobj) {
+                        continue;
+                    }
                     let path = dir.path().join(path);
-                    if !path.is_file() {
-                        bail!("Output '{}' not found");
+                    if !path.exists() {
+                        if config.optional_outputs {
+                            debug!("Optional output {:?} not found, using empty blob", path);
+                            let mut obj = dataobj.get_mut();
+                            obj.set_data(Arc::new(Data::new(Storage::Memory(Vec::new()))));
+                            continue;
+                        }
+                        bail!("Output '{}' not found", path.display());
                     }
                     let target_path = state.work_dir().new_path_for_dataobject();
                     let data = Data::new_by_fs_move(&path, target_path)?;
@@ -0,0 +1,315 @@
+use std::sync::Arc;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use super::TaskResult;
+use worker::state::State;
+use worker::graph::TaskRef;
+use worker::data::{Data, DataBuilder, Storage};
+use futures::future;
+
+#[derive(Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+enum SplitConfig {
+    Lines,
+    Bytes,
+    Delimiter { delimiter: String },
+}
+
+#[derive(Deserialize)]
+struct SliceConfig {
+    start: usize,
+    end: Option<usize>,
+    #[serde(default)]
+    lines: bool,
+}
+
+/// Returns offsets where records end; each record contains its delimiter
+/// (except the last record when data does not end by delimiter)
+fn record_ends(data: &[u8], delimiter: &[u8]) -> Vec<usize> {
+    assert!(!delimiter.is_empty());
+    let mut ends = Vec::new();
+    let mut i = 0;
+    while i + delimiter.len() <= data.len() {
+        if &data[i..i + delimiter.len()] == delimiter {
+            i += delimiter.len();
+            ends.push(i);
+        } else {
+            i += 1;
+        }
+    }
+    if ends.last().cloned().unwrap_or(0) < data.len() {
+        ends.push(data.len());
+    }
+    ends
+}
+
+/// Sizes of n_parts parts that are as equal as possible
+fn balanced_parts(n_units: usize, n_parts: usize) -> Vec<usize> {
+    (0..n_parts)
+        .map(|i| n_units / n_parts + if i < n_units % n_parts { 1 } else { 0 })
+        .collect()
+}
+
+/// Returns n_parts + 1 offsets dividing data into parts,
+/// records (if delimiter is given) are never divided
+fn split_offsets(data: &[u8], delimiter: Option<&[u8]>, n_parts: usize) -> Vec<usize> {
+    let mut offsets = vec![0];
+    match delimiter {
+        None => {
+            let mut position = 0;
+            for size in balanced_parts(data.len(), n_parts) {
+                position += size;
+                offsets.push(position);
+            }
+        }
+        Some(delimiter) => {
+            let ends = record_ends(data, delimiter);
+            let mut count = 0;
+            for size in balanced_parts(ends.len(), n_parts) {
+                count += size;
+                offsets.push(if count == 0 { 0 } else { ends[count - 1] });
+            }
+        }
+    }
+    offsets
+}
+
+/// Returns byte range for slice; start and end are in lines or bytes
+fn slice_range(data: &[u8], lines: bool, start: usize, end: Option<usize>) -> (usize, usize) {
+    if lines {
+        let ends = record_ends(data, b"\n");
+        let offset = |i: usize| {
+            let i = i.min(ends.len());
+            if i == 0 {
+                0
+            } else {
+                ends[i - 1]
+            }
+        };
+        let end = end.unwrap_or(ends.len());
+        let start = offset(start);
+        (start, offset(end).max(start))
+    } else {
+        let end = end.unwrap_or(data.len()).min(data.len());
+        let start = start.min(data.len());
+        (start, end.max(start))
+    }
+}
+
+/// Lines of data without terminating newlines
+fn lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut start = 0;
+    record_ends(data, b"\n")
+        .into_iter()
+        .map(|end| {
+            let line = &data[start..end];
+            start = end;
+            if line.last() == Some(&b'\n') {
+                &line[..line.len() - 1]
+            } else {
+                line
+            }
+        })
+        .collect()
+}
+
+fn sort_lines(data: &[u8]) -> Vec<u8> {
+    let mut lines = lines(data);
+    lines.sort();
+    let mut result = Vec::with_capacity(data.len() + 1);
+    for line in lines {
+        result.extend_from_slice(line);
+        result.push(b'\n');
+    }
+    result
+}
+
+/// K-way merge of sorted lines
+fn merge_sorted_lines(inputs: &[Vec<&[u8]>]) -> Vec<u8> {
+    let mut result = Vec::new();
+    let mut heap = BinaryHeap::new();
+    for (i, lines) in inputs.iter().enumerate() {
+        if let Some(line) = lines.get(0) {
+            heap.push(Reverse((*line, i, 0)));
+        }
+    }
+    while let Some(Reverse((line, i, j))) = heap.pop() {
+        result.extend_from_slice(line);
+        result.push(b'\n');
+        if let Some(next) = inputs[i].get(j + 1) {
+            heap.push(Reverse((*next, i, j + 1)));
+        }
+    }
+    result
+}
+
+fn data_from_bytes(bytes: &[u8]) -> Data {
+    let mut builder = DataBuilder::new();
+    builder.write(bytes);
+    builder.build()
+}
+
+/// Task that splits input blob into parts, one part for each output
+pub fn task_split(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let (input, config, n_outputs) = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        let config: SplitConfig = task.attributes.get("config")?;
+        (task.input_data(0), config, task.outputs.len())
+    };
+
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+    if n_outputs == 0 {
+        bail!("Task '!split' needs at least one output");
+    }
+    if let SplitConfig::Delimiter { ref delimiter } = config {
+        if delimiter.is_empty() {
+            bail!("Delimiter is empty");
+        }
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        let parts = input.map_bytes(|bytes| {
+            let offsets = match config {
+                SplitConfig::Bytes => split_offsets(bytes, None, n_outputs),
+                SplitConfig::Lines => split_offsets(bytes, Some(&b"\n"[..]), n_outputs),
+                SplitConfig::Delimiter { ref delimiter } => {
+                    split_offsets(bytes, Some(delimiter.as_bytes()), n_outputs)
+                }
+            };
+            offsets
+                .windows(2)
+                .map(|w| data_from_bytes(&bytes[w[0]..w[1]]))
+                .collect::<Vec<_>>()
+        })?;
+        let task = task_ref.get();
+        for (i, data) in parts.into_iter().enumerate() {
+            task.output(i).get_mut().set_data(Arc::new(data));
+        }
+        Ok(())
+    })))
+}
+
+/// Task that returns a range of bytes or lines of the input blob
+pub fn task_slice(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let (input, config) = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        if task.outputs.len() != 1 {
+            bail!("Task '!slice' has to have exactly one output");
+        }
+        let config: SliceConfig = task.attributes.get("config")?;
+        (task.input_data(0), config)
+    };
+
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        let result = input.map_bytes(|bytes| {
+            let (start, end) = slice_range(bytes, config.lines, config.start, config.end);
+            data_from_bytes(&bytes[start..end])
+        })?;
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(result));
+        Ok(())
+    })))
+}
+
+/// Task that sorts lines of the input blob
+pub fn task_sort_lines(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let input = {
+        let task = task_ref.get();
+        task.check_number_of_args(1)?;
+        if task.outputs.len() != 1 {
+            bail!("Task '!sort_lines' has to have exactly one output");
+        }
+        task.input_data(0)
+    };
+
+    if !input.is_blob() {
+        bail!("Input object is not blob");
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        let result = input.map_bytes(|bytes| Data::new(Storage::Memory(sort_lines(bytes))))?;
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(result));
+        Ok(())
+    })))
+}
+
+/// Task that merges lines of sorted input blobs into one sorted blob
+pub fn task_merge_sorted(_state: &mut State, task_ref: TaskRef) -> TaskResult {
+    let inputs = {
+        let task = task_ref.get();
+        if task.outputs.len() != 1 {
+            bail!("Task '!merge_sorted' has to have exactly one output");
+        }
+        task.inputs_data()
+    };
+
+    for (i, input) in inputs.iter().enumerate() {
+        if !input.is_blob() {
+            bail!("Input {} object is not blob", i);
+        }
+    }
+
+    Ok(Box::new(future::lazy(move || {
+        // Inputs are copied to be able to hold all of them at once
+        let contents = inputs
+            .iter()
+            .map(|input| input.map_bytes(|bytes| bytes.to_vec()))
+            .collect::<::errors::Result<Vec<_>>>()?;
+        let result = {
+            let all_lines: Vec<_> = contents.iter().map(|c| lines(c)).collect();
+            merge_sorted_lines(&all_lines)
+        };
+        let output = task_ref.get().output(0);
+        output.get_mut().set_data(Arc::new(Data::new(Storage::Memory(result))));
+        Ok(())
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_ends() {
+        assert_eq!(record_ends(b"", b"\n"), Vec::<usize>::new());
+        assert_eq!(record_ends(b"a\nbc\n", b"\n"), vec![2, 5]);
+        assert_eq!(record_ends(b"a\nbc", b"\n"), vec![2, 4]);
+        assert_eq!(record_ends(b"a;;b;;;;c", b";;"), vec![3, 6, 8, 9]);
+    }
+
+    #[test]
+    fn test_split_offsets() {
+        assert_eq!(split_offsets(b"abcdefg", None, 3), vec![0, 3, 5, 7]);
+        assert_eq!(split_offsets(b"a\nb\nc\n", Some(&b"\n"[..]), 2), vec![0, 4, 6]);
+        assert_eq!(split_offsets(b"a\n", Some(&b"\n"[..]), 3), vec![0, 2, 2, 2]);
+        assert_eq!(split_offsets(b"", None, 2), vec![0, 0, 0]);
+    }
+
+    #[test]
+    fn test_slice_range() {
+        let data = b"one\ntwo\nthree\n";
+        assert_eq!(slice_range(data, false, 2, Some(5)), (2, 5));
+        assert_eq!(slice_range(data, false, 10, Some(100)), (10, 14));
+        assert_eq!(slice_range(data, true, 1, Some(2)), (4, 8));
+        assert_eq!(slice_range(data, true, 1, None), (4, 14));
+        assert_eq!(slice_range(data, true, 5, None), (14, 14));
+    }
+
+    #[test]
+    fn test_sort_and_merge() {
+        assert_eq!(sort_lines(b"c\na\nb"), b"a\nb\nc\n".to_vec());
+        let a = lines(b"a\nc\ne\n");
+        let b = lines(b"b\nd\n");
+        assert_eq!(merge_sorted_lines(&[a, b]), b"a\nb\nc\nd\ne\n".to_vec());
+    }
+}
@@ -77,9 +77,14 @@ class TestEnv(Env):
               n_cpus=1,
               listen_addr=None,
               listen_port=None,
-              worker_defs=None):
+              worker_defs=None,
+              worker_http_port=None,
+              server_args=()):
         """
         Start infrastructure: server & n workers
+        If worker_http_port is set, i-th worker serves HTTP on
+        worker_http_port + i
+        server_args are appended to the arguments of the server
         """
         env = os.environ.copy()
         env["RUST_LOG"] = "trace"
@@ -117,7 +122,7 @@ class TestEnv(Env):
         args = (RAIN_BIN, "server",
                 "--ready-file", server_ready_file,
                 "--logdir", os.path.join(WORK_DIR, "server"),
-                "--listen", str(addr))
+                "--listen", str(addr)) + tuple(server_args)
         self.server = self.start_process("server", args, env=env)
         assert self.server is not None
 
@@ -145,6 +150,9 @@ class TestEnv(Env):
                     "--cpus", str(cpus),
                     "--logdir", os.path.join(wdir, "logs"),
                     "--workdir", os.path.join(wdir, "work"))
+            if worker_http_port is not None:
+                args += ("--http-listen",
+                         "127.0.0.1:{}".format(worker_http_port + i))
             self.workers.append(self.start_process(name, args, env=env))
 
         it = 0
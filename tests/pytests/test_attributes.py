@@ -1,4 +1,4 @@
-from rain.client import remote
+from rain.client import remote, tasks
 import time
 
 
@@ -27,3 +27,33 @@ def test_update_attributes(test_env):
         # for now, lets just trim padding
         start = start[:start.index(".") + 6]
         time.strptime(start, '%Y-%m-%dT%H:%M:%S.%f')
+
+
+def test_usage_attribute(test_env):
+
+    @remote()
+    def allocate(ctx):
+        data = b"x" * (20 * 1024 * 1024)
+        return data[:1]
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("sleep 0.5")
+        t2 = allocate()
+        t3 = tasks.execute("head -c 100000 /dev/zero", stdout=True)
+        s.submit()
+        s.wait_all()
+        t1.update()
+        t2.update()
+        t3.update()
+        usage = t1.attributes["usage"]
+        assert usage["peak_rss"] > 0
+        assert usage["cpu_time"] >= 0
+        # Usage of short programs is taken when they exit
+        usage = t3.attributes["usage"]
+        assert usage["peak_rss"] > 0
+        assert usage["read_bytes"] >= 100000
+        assert usage["written_bytes"] >= 100000
+        usage = t2.attributes["usage"]
+        assert usage["peak_rss"] >= 20 * 1024 * 1024
+        assert usage["cpu_time"] >= 0
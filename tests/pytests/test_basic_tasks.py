@@ -1,6 +1,10 @@
-from rain.client import tasks, RainException, blob
+from rain.client import tasks, RainException, blob, Input
 import pytest
 
+import io
+import os
+import tarfile
+
 
 def test_sleep1(test_env):
     """Sleep followed by wait"""
@@ -129,3 +133,108 @@ def test_task_export(test_env):
             assert f.read() == "Hello World!"
         with open(test2) as f:
             assert "bin" in f.read()
+
+
+def test_split_lines(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.split(blob("a\nb\nc\nd\ne\n"), 2)
+        t2 = tasks.split(blob("a;b;c"), 3, delimiter=";")
+        t3 = tasks.split(blob("abcdefg"), 3, mode="bytes")
+        for t in (t1, t2, t3):
+            t.keep_outputs()
+        s.submit()
+        assert [o.get_bytes() for o in t1.fetch_outputs()] == \
+            [b"a\nb\nc\n", b"d\ne\n"]
+        assert [o.get_bytes() for o in t2.fetch_outputs()] == \
+            [b"a;", b"b;", b"c"]
+        assert [o.get_bytes() for o in t3.fetch_outputs()] == \
+            [b"abc", b"de", b"fg"]
+
+
+def test_slice(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        data = blob("one\ntwo\nthree\n")
+        t1 = tasks.slice(data, 2, 6)
+        t2 = tasks.slice(data, 1, lines=True)
+        t1.output.keep()
+        t2.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"e\ntw"
+        assert t2.output.fetch().get_bytes() == b"two\nthree\n"
+
+
+def test_sort_and_merge(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.sort_lines(blob("d\nb\nf"))
+        t2 = tasks.sort_lines(blob("e\na\nc\n"))
+        t3 = tasks.merge_sorted((t1, t2))
+        t3.output.keep()
+        s.submit()
+        assert t3.output.fetch().get_bytes() == b"a\nb\nc\nd\ne\nf\n"
+
+
+def test_tar_untar(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        results = []
+        for compression in (None, "gzip"):
+            t1 = tasks.tar({"a.txt": blob("Hello "), "b.txt": blob("world")},
+                           compression=compression)
+            t2 = tasks.untar(t1, compression=compression)
+            t3 = tasks.execute(("cat", "d/a.txt", "d/b.txt"),
+                               input_files=(Input("d", dataobj=t2.output),),
+                               stdout=True)
+            t3.output.keep()
+            results.append(t3)
+        s.submit()
+        for t in results:
+            assert t.output.fetch().get_bytes() == b"Hello world"
+
+
+def test_directory_transfer(test_env):
+    """Directory objects are moved between workers and fetched as tar archives"""
+    test_env.start(2)
+    with test_env.client.new_session() as s:
+        t1 = tasks.tar({"a.txt": blob("Hello "), "b.txt": blob("world")})
+        t2 = tasks.untar(t1)
+        t2.output.keep()
+        consumers = []
+        for i in range(2):
+            t = tasks.execute("sleep 0.5 && cat d/a.txt d/b.txt",
+                              input_files=(Input("d", dataobj=t2.output),),
+                              stdout=True, shell=True)
+            t.output.keep()
+            consumers.append(t)
+        s.submit()
+        for t in consumers:
+            assert t.output.fetch().get_bytes() == b"Hello world"
+
+        archive = tarfile.open(fileobj=io.BytesIO(t2.output.fetch().get_bytes()))
+        names = sorted(os.path.normpath(n) for n in archive.getnames())
+        assert names == [".", "a.txt", "b.txt"]
+
+
+def test_checksum(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.checksum((blob("abc"), blob("")))
+        t1.keep_outputs()
+        t2 = tasks.assert_checksum(
+            blob("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        t2.output.keep()
+        s.submit()
+        assert [o.get_bytes() for o in t1.fetch_outputs()] == [
+            b"ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            b"e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"]
+        assert t2.output.fetch().get_bytes() == b"abc"
+
+    with test_env.client.new_session() as s:
+        t1 = tasks.assert_checksum(blob("abc"), "00" * 32)
+        t1.output.keep()
+        s.submit()
+        with pytest.raises(RainException):
+            t1.wait()
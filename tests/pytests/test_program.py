@@ -186,6 +186,48 @@ def test_program_fail(test_env):
         pytest.raises(RainException, lambda: t1.wait())
 
 
+def test_execute_success_codes(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute(("grep", "xyz", Input("data", dataobj=blob("abc"))),
+                           stdout="output", success_codes=(0, 1))
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b""
+        t1.update()
+        assert t1.attributes["exit_code"] == 1
+
+
+def test_execute_optional_outputs(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.execute("true", output_files=["not_created"],
+                           optional_outputs=True)
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b""
+
+
+def test_pipe(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.pipe(("cat", ("grep", "a"), "sort -r"),
+                        stdin=blob("abc\nxyz\naaa\n"))
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"abc\naaa\n"
+
+
+def test_pipe_fail(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = tasks.pipe(("echo abc", "ls /non-existing-dir", "cat"))
+        s.submit()
+        with pytest.raises(RainException) as e:
+            t1.wait()
+        assert "stage 1 (ls)" in str(e.value)
+
+
 def test_execute_shell(test_env):
     test_env.start(1)
     p1 = Program(("echo", "$HOME"), stdout=True)
@@ -304,3 +346,26 @@ def test_execute_cpus(test_env):
         tasks.execute("sleep 1", cpus=2)
         s.submit()
         test_env.assert_duration(1.9, 2.3, lambda: s.wait_all())
+
+
+def test_execute_submit_children(test_env):
+    """Program submits a child task producing its output"""
+    script = """
+import json, os, socket
+sock = socket.socket(socket.AF_UNIX)
+sock.connect(os.environ["RAIN_TASK_SOCKET"])
+graph = {"objects": [{"id": 1, "data": "Hello "}, {"id": 2, "data": "world"},
+                     {"id": 3}],
+         "tasks": [{"id": 4, "task_type": "!concat",
+                    "inputs": [1, 2], "outputs": [3]}],
+         "delegations": [{"output": 0, "source": 3}]}
+sock.sendall(json.dumps(graph).encode() + b"\\n")
+assert json.loads(sock.makefile().readline())["ok"]
+"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t0 = tasks.execute(("python3", "-c", script), stdout=True,
+                           task_socket=True)
+        t0.output.keep()
+        s.submit()
+        assert t0.output.fetch().get_bytes() == b"Hello world"
@@ -1,4 +1,4 @@
-from rain.client import remote, Program, Input, Output, blob, pickled
+from rain.client import remote, Program, Input, Output, blob, pickled, tasks
 from rain.client import RainException, RainWarning
 from rain.common import DataInstance
 import pytest
@@ -593,3 +593,26 @@ def test_debug_message(test_env):
         t.update()
         assert t.attributes["debug"] == \
             "This is first message\nThis is second message and variable a = 11"
+
+
+def test_remote_child_tasks(test_env):
+    """Pytask submitting child tasks, the output is delegated"""
+
+    @remote()
+    def child(ctx, data):
+        return data.get_bytes().upper()
+
+    @remote()
+    def parent(ctx, data):
+        with ctx.child_session() as s:
+            parts = [child(blob(part)) for part in data.get_bytes().split()]
+            t = tasks.concat([p.output for p in parts] +
+                             [s.input_object(data)])
+        return t.output
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t1 = parent(blob("a b"))
+        t1.output.keep()
+        s.submit()
+        assert t1.output.fetch().get_bytes() == b"ABa b"
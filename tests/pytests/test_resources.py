@@ -1,4 +1,5 @@
-from rain.client import tasks, blob
+from rain.client import rpc, tasks, blob, RainException
+import pytest
 
 import time
 
@@ -81,3 +82,110 @@ def test_number_of_tasks_and_objects(test_env):
         assert len(workers) == 1
         assert workers[0]["tasks"] == []
         assert workers[0]["objects"] == []
+
+
+def test_session_cpu_quota(test_env):
+    """2x 1cpu tasks on 2 cpu worker in session with cpu quota 1"""
+    test_env.start(1, n_cpus=2)
+    with test_env.client.new_session(cpu_quota=1) as s:
+        tasks.sleep(1.0, blob("first"))
+        tasks.sleep(1.0, blob("second"))
+        s.submit()
+        test_env.assert_duration(1.9, 2.1, lambda: s.wait_all())
+
+    with test_env.client.new_session(cpu_quota=1) as s:
+        tasks.sleep(1.0, blob("first"), cpus=2)
+        with pytest.raises(RainException):
+            s.submit()
+
+
+def test_fair_share_sessions(test_env):
+    """Session submitted later is not starved by the first one"""
+    test_env.start(1)
+    with test_env.client.new_session() as s1:
+        for i in range(5):
+            tasks.sleep(0.5, blob("first"))
+        s1.submit()
+        with test_env.client.new_session() as s2:
+            tasks.sleep(0.5, blob("second"))
+            s2.submit()
+            test_env.assert_duration(0.4, 1.1, lambda: s2.wait_all())
+
+
+def test_placement_constraints(test_env):
+    """Tasks without a matching worker wait for it"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t = tasks.sleep(0.1, blob("data"))
+        t.attributes["constraints"] = {"hosts": ["127.0.0.1"],
+                                       "anti_affinity": "group"}
+        s.submit()
+        s.wait_all()
+
+    with test_env.client.new_session() as s:
+        t = tasks.sleep(0.1, blob("data"))
+        t.attributes["constraints"] = {"labels": ["gpu-node"]}
+        with pytest.raises(RainException):
+            s.submit()
+
+    with test_env.client.new_session() as s:
+        t = tasks.sleep(0.1, blob("data"))
+        t.attributes["constraints"] = {"labels": ["gpu-node"], "wait": True}
+        s.submit()
+        time.sleep(0.5)
+        t.update()
+        assert t.state == rpc.common.TaskState.ready
+
+    with test_env.client.new_session() as s:
+        t = tasks.sleep(0.1, blob("data"))
+        t.attributes["constraints"] = {"anti_affinity": ""}
+        with pytest.raises(RainException):
+            s.submit()
+
+
+def test_gang_dependency(test_env):
+    """Members of a gang cannot depend on each other"""
+    test_env.start(2)
+    with test_env.client.new_session() as s:
+        t1 = tasks.sleep(0.1, blob("data"))
+        t1.attributes["gang"] = "peers"
+        t2 = tasks.sleep(0.1, t1)
+        t2.attributes["gang"] = "peers"
+        with pytest.raises(RainException):
+            s.submit()
+
+
+def test_gang(test_env):
+    """Gang of two tasks runs at once on two workers"""
+    test_env.start(2)
+    with test_env.client.new_session() as s:
+        for i in range(2):
+            t = tasks.sleep(0.5, blob("data"))
+            t.attributes["gang"] = "peers"
+        s.submit()
+        test_env.assert_duration(0.4, 0.8, lambda: s.wait_all())
+
+    with test_env.client.new_session() as s:
+        for i in range(3):
+            t = tasks.sleep(0.1, blob("data"))
+            t.attributes["gang"] = "too-big"
+        with pytest.raises(RainException):
+            s.submit()
+
+
+def test_multinode_task(test_env):
+    """Task on two workers gets hosts of both of them"""
+    test_env.start(2)
+    with test_env.client.new_session() as s:
+        t = tasks.execute("echo $RAIN_NODES; wc -l < $RAIN_HOSTFILE",
+                          stdout=True, shell=True)
+        t.attributes["nodes"] = 2
+        t.output.keep()
+        s.submit()
+        assert t.output.fetch().get_bytes().split() == [b"2", b"2"]
+
+    with test_env.client.new_session() as s:
+        t = tasks.execute("true")
+        t.attributes["nodes"] = 3
+        with pytest.raises(RainException):
+            s.submit()
@@ -1,5 +1,6 @@
 
 from schedtester import Scenario, Worker
+from rain.client import tasks, blob
 import random
 
 # Size of object considered as big
@@ -97,3 +98,34 @@ def test_scheduler_big_vs_dynamic_small(test_env):
     t0 = s.new_task([o1], expect_worker=w0)
     s.new_task([t0.output, o2], expect_worker=w1)
     s.run()
+
+
+def test_scheduler_priority(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        ts = []
+        for p in (1, 3, 2):
+            t = tasks.sleep(0.2, blob("data"))
+            t.attributes["priority"] = p
+            ts.append(t)
+        s.submit()
+        for t in ts:
+            t.wait()
+            t.update()
+        starts = [t.attributes["info"]["start"] for t in ts]
+        assert starts[1] < starts[2] < starts[0]
+
+
+# t0 -- t1 -- t2    t3
+def test_scheduler_bottom_level(test_env):
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t3 = tasks.sleep(0.2, blob("data"))
+        t0 = tasks.sleep(0.2, blob("data"))
+        t1 = tasks.sleep(0.2, t0)
+        tasks.sleep(0.2, t1)
+        s.submit()
+        s.wait_all()
+        t0.update()
+        t3.update()
+        assert t0.attributes["info"]["start"] < t3.attributes["info"]["start"]
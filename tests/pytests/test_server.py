@@ -1,3 +1,13 @@
+from rain.client import tasks, blob
+from conftest import RAIN_BIN
+
+import json
+import os
+import subprocess
+import time
+import urllib.request
+
+
 
 def test_listen_argument1(test_env):
     test_env.start(1, listen_addr="127.0.0.1", listen_port="33112")
@@ -5,3 +15,248 @@ def test_listen_argument1(test_env):
 
 def test_listen_argument2(test_env):
     test_env.start(1, listen_addr="0.0.0.0", listen_port="33112")
+
+
+def test_http_api(test_env):
+    """JSON API of the HTTP server (default port 8080)"""
+
+    def get(path):
+        with urllib.request.urlopen("http://localhost:8080" + path) as f:
+            return json.loads(f.read().decode())
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t = tasks.concat((blob("a"), blob("b")))
+        t.output.keep()
+        s.submit()
+        t.wait()
+
+        workers = get("/api/workers")
+        assert len(workers) == 1
+
+        sessions = get("/api/sessions")
+        assert s.session_id in [session["id"] for session in sessions]
+
+        task_infos = get("/api/sessions/{}/tasks".format(s.session_id))
+        assert [info["state"] for info in task_infos] == ["Finished"]
+
+        obj = get("/api/objects/{}/{}".format(s.session_id, t.output.id.id))
+        assert obj["size"] == 2
+        assert obj["located"] == [workers[0]["id"]]
+
+
+def test_metrics(test_env):
+    """Metrics of the server and workers in Prometheus text format"""
+
+    def get_metrics(port):
+        url = "http://localhost:{}/metrics".format(port)
+        with urllib.request.urlopen(url) as f:
+            metrics = {}
+            for line in f.read().decode().splitlines():
+                if not line.startswith("#"):
+                    name, value = line.rsplit(" ", 1)
+                    metrics[name] = float(value)
+            return metrics
+
+    test_env.start(1, worker_http_port=18081)
+    with test_env.client.new_session() as s:
+        t = tasks.concat((blob("a"), blob("b")))
+        t.output.keep()
+        s.submit()
+        t.wait()
+
+        metrics = get_metrics(8080)
+        assert metrics["rain_workers"] == 1
+        assert metrics["rain_sessions"] >= 1
+        assert metrics['rain_tasks{state="finished"}'] == 1
+        assert metrics["rain_ready_tasks"] == 0
+        assert metrics["rain_scheduler_runs_total"] > 0
+        assert metrics["rain_transferred_objects_total"] == 2
+        assert metrics["rain_transferred_bytes_total"] == 2
+
+        metrics = get_metrics(18081)
+        assert metrics["rain_worker_local_cpus"] == 1
+        assert metrics["rain_worker_running_tasks"] == 0
+        assert metrics["rain_worker_free_cpus"] == 1
+
+
+def test_live_events(test_env):
+    """Events streamed by /events/live as server-sent events"""
+    test_env.start(1)
+    url = "http://localhost:8080/events/live?event_type=TaskFinished"
+    with urllib.request.urlopen(url, timeout=10) as feed:
+        with test_env.client.new_session() as s:
+            t = tasks.concat((blob("a"), blob("b")))
+            s.submit()
+            t.wait()
+
+            line = feed.readline().decode()
+            assert line.startswith("data: ")
+            event = json.loads(line[len("data: "):])["event"]
+            assert event["type"] == "TaskFinished"
+            assert event["task"]["session_id"] == s.session_id
+
+
+def test_monitoring_work_dir(test_env):
+    """Monitoring events contain statistics of the working directory"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t = tasks.concat((blob("a"), blob("b")))
+        t.output.keep()
+        s.submit()
+        t.wait()
+
+        url = "http://localhost:8080/events/live?event_type=Monitoring"
+        with urllib.request.urlopen(url, timeout=15) as feed:
+            line = feed.readline().decode()
+            assert line.startswith("data: ")
+            event = json.loads(line[len("data: "):])["event"]
+            work_dir = event["work_dir"]
+            assert work_dir["free_space"] > 0
+            assert work_dir["data_in_memory"] >= 2
+            assert work_dir["task_dirs"] == 0
+
+
+def test_search_events(test_env):
+    """Search in logged events with pagination"""
+
+    def search(criteria):
+        data = json.dumps(criteria).encode()
+        url = "http://localhost:8080/events/search"
+        with urllib.request.urlopen(url, data=data) as f:
+            return json.loads(f.read().decode())
+
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        ts = [tasks.concat((blob("a"), blob("b"))) for i in range(3)]
+        s.submit()
+        s.wait_all()
+        time.sleep(1.5)  # Events are stored once per second
+
+        criteria = {"session": {"value": s.session_id, "mode": "="},
+                    "event_type": {"value": "TaskFinished", "mode": "="},
+                    "limit": 2,
+                    "count": True}
+        result = search(criteria)
+        assert result["count"] == 3
+        assert len(result["events"]) == 2
+        assert result["next_cursor"] == result["events"][1]["id"]
+
+        criteria["cursor"] = result["next_cursor"]
+        result = search(criteria)
+        assert len(result["events"]) == 1
+        assert result["next_cursor"] is None
+
+        task_id = ts[0].id.id
+        result = search({"session": {"value": s.session_id, "mode": "="},
+                         "task": {"value": task_id, "mode": "="}})
+        assert result["count"] is None
+        types = [e["event"]["type"] for e in result["events"]]
+        assert types == ["TaskStarted", "TaskFinished"]
+
+
+def test_events_export(test_env):
+    """Export of events from the log directory of the server"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t = tasks.concat((blob("a"), blob("b")))
+        s.submit()
+        t.wait()
+        session_id = s.session_id
+    time.sleep(1.5)  # Events are stored once per second
+
+    log_dir = os.path.join(test_env.work_dir, "server")
+    output = subprocess.check_output(
+        (RAIN_BIN, "events", "export", log_dir, "--session", str(session_id)))
+    events = [json.loads(line) for line in output.decode().splitlines()]
+    types = [e["event"]["type"] for e in events]
+    assert types[0] == "SessionNew"
+    assert "TaskFinished" in types
+    assert types[-1] == "SessionClosed"
+
+    output = subprocess.check_output(
+        (RAIN_BIN, "events", "export", log_dir, "--format", "csv"))
+    lines = output.decode().splitlines()
+    assert lines[0] == \
+        "id,timestamp,event_type,session,worker,task,dataobject,event"
+    assert len(lines) > len(events)
+
+
+def test_trace(test_env):
+    """Chrome trace of a session from the server and from the log directory"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        t = tasks.concat((blob("a"), blob("b")))
+        s.submit()
+        t.wait()
+        session_id = s.session_id
+    time.sleep(1.5)  # Events are stored once per second
+
+    url = "http://localhost:8080/api/sessions/{}/trace".format(session_id)
+    with urllib.request.urlopen(url) as f:
+        trace = json.loads(f.read().decode())
+    slices = [e for e in trace["traceEvents"] if e["ph"] == "X"]
+    assert len(slices) == 1
+    assert slices[0]["name"].startswith("!concat")
+    assert slices[0]["tid"] == 1
+    assert any(e["ph"] == "i" for e in trace["traceEvents"])
+    assert any(e["name"] == "process_name" for e in trace["traceEvents"])
+
+    log_dir = os.path.join(test_env.work_dir, "server")
+    output = subprocess.check_output(
+        (RAIN_BIN, "trace", log_dir, "--session", str(session_id)))
+    assert json.loads(output.decode()) == trace
+
+
+def test_replay(test_env):
+    """Analysis of a session from the event log and its resubmission"""
+    test_env.start(1, server_args=("--log-client-data",))
+    with test_env.client.new_session() as s:
+        a = tasks.concat((blob("a"), blob("b")))
+        b = tasks.concat((a, blob("c")))
+        s.submit()
+        b.wait()
+        session_id = s.session_id
+    time.sleep(1.5)  # Events are stored once per second
+
+    log_dir = os.path.join(test_env.work_dir, "server")
+    output = subprocess.check_output(
+        (RAIN_BIN, "replay", log_dir, "--session", str(session_id)))
+    report = json.loads(output.decode())
+    assert report["session"] == session_id
+    assert report["tasks"] == 2
+    assert report["finished_tasks"] == 2
+    assert report["failed_tasks"] == 0
+    assert len(report["critical_path"]["tasks"]) == 2
+    assert len(report["workers"]) == 1
+    # Only data from the client are transferred (3 blobs of 1 byte)
+    assert report["transferred"] == 3
+
+    output = subprocess.check_output(
+        (RAIN_BIN, "replay", log_dir, "--session", str(session_id),
+         "--submit", "127.0.0.1:" + test_env.running_port))
+    assert json.loads(output.decode())["tasks"] == 2
+    time.sleep(1.5)
+    output = subprocess.check_output(
+        (RAIN_BIN, "replay", log_dir, "--session", str(session_id + 1)))
+    assert json.loads(output.decode())["finished_tasks"] == 2
+
+
+def test_replay_without_data(test_env):
+    """Resubmission fails when data of client objects were not logged"""
+    test_env.start(1)
+    with test_env.client.new_session() as s:
+        a = tasks.concat((blob("a"), blob("b")))
+        s.submit()
+        a.wait()
+        session_id = s.session_id
+    time.sleep(1.5)  # Events are stored once per second
+
+    log_dir = os.path.join(test_env.work_dir, "server")
+    p = subprocess.Popen(
+        (RAIN_BIN, "replay", log_dir, "--session", str(session_id),
+         "--submit", "127.0.0.1:" + test_env.running_port),
+        stdout=subprocess.PIPE, stderr=subprocess.PIPE)
+    _, stderr = p.communicate()
+    assert p.returncode != 0
+    assert "not in the event log" in stderr.decode()